@@ -1,5 +1,6 @@
 use crate::*;
-use cgmath::{ Deg, perspective, Vector3, Matrix4, Point3};
+use cgmath::prelude::*;
+use cgmath::{ Deg, Vector3, Vector4, Matrix4, Point3};
 use std::ffi::{CString, CStr};
 
 /// Macro to get c strings from literals without runtime overhead
@@ -13,11 +14,44 @@ macro_rules! c_str {
 
 pub struct EngineConfig {
     pub window_width: u32,
-    pub window_height: u32
+    pub window_height: u32,
+    pub shadows: ShadowSettings,
+    pub render_path: RenderPath,
+    pub exposure: ExposureSettings,
+    /// Rebuild shaders when their files or includes change, on in debug builds.
+    pub hot_reload: bool,
+    /// Print the `FrameStats` of a frame every second.
+    pub print_stats: bool
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            window_width: 800,
+            window_height: 600,
+            shadows: ShadowSettings::default(),
+            render_path: RenderPath::Forward,
+            exposure: ExposureSettings::default(),
+            hot_reload: cfg!(debug_assertions),
+            print_stats: false
+        }
+    }
 }
 
 pub struct Engine {
-    window: Window
+    window: Window,
+    state: GlState,
+    pub shadows: ShadowMaps,
+    /// Effects run over every frame, enable and reorder them at any time.
+    pub post: PostProcessStack,
+    /// Feeds the `exposure` of the "tonemap" effect. Change its settings to script the exposure.
+    pub exposure: AutoExposure,
+    /// Set when the config asked for the deferred path.
+    pub deferred: Option<DeferredRenderer>,
+    /// Counts of the last finished frame.
+    pub frame_stats: FrameStats,
+    hot_reload: bool,
+    print_stats: bool
 }
 
 impl Engine {
@@ -25,10 +59,25 @@ impl Engine {
         let window = Window::new(config.window_width, config.window_height);
         Engine { 
             window,
+            state: GlState::new(),
+            shadows: ShadowMaps::new(config.shadows),
+            post: PostProcessStack::with_defaults(config.window_width, config.window_height),
+            exposure: AutoExposure::new(config.exposure),
+            deferred: match config.render_path {
+                RenderPath::Forward => None,
+                RenderPath::Deferred => Some(DeferredRenderer::new(config.window_width, config.window_height))
+            },
+            frame_stats: FrameStats::default(),
+            hot_reload: config.hot_reload,
+            print_stats: config.print_stats
         }
     }
     
     pub fn start(&mut self, scene: &mut Scene) -> () {
+        if self.deferred.is_some() && scene.shading == Shading::Pbr {
+            println!("The deferred path shades Phong only, drawing the PBR scene forward");
+            self.deferred = None;
+        }
         self.run_loop(scene);
     }
     
@@ -45,31 +94,119 @@ impl Engine {
         let mut model_pos = Vector3::<f32>::new(0.0, 0.0, 0.0);
 
         let mut last_frame: f32 = 0.0;
+        let mut last_report: f32 = 0.0;
+        let mut last_reload_check: f32 = 0.0;
 
         while !self.window.should_close() {
             let curr_frame = self.window.get_time() as f32;
             let delta_time = curr_frame - last_frame;
-            let last_frame = curr_frame;
+            last_frame = curr_frame;
 
             self.window.process_events();
 
+            if self.hot_reload && curr_frame - last_reload_check >= 0.5 {
+                self.reload_shaders(scene);
+                last_reload_check = curr_frame;
+            }
+
             //render
             unsafe {
                 self.window.clear();
                 
-                scene.shader.useProgram();
+                let camera = CameraView {
+                    view: Matrix4::from_translation(Vector3::<f32>::new(0., 0., -3.)),
+                    fovy: Deg(45.0),
+                    aspect: self.window.width as f32 / self.window.height as f32,
+                    near: 0.1,
+                    far: 100.0
+                };
+                let projection = camera.projection();
+                let view = camera.view;
+                let view_pos = (view.invert().unwrap() * Vector4::new(0.0, 0.0, 0.0, 1.0)).truncate();
+
+                let mut model = Matrix4::<f32>::from_translation(model_pos);
+                scene.root.update_lod(&(view * model), &projection, self.window.height as f32);
+
+                let root = &scene.root;
+                self.shadows.render(&scene.lights, &camera, |shader| root.draw(shader, &model));
+
+                let target = self.post.begin(self.window.width, self.window.height);
 
-                let projection: Matrix4::<f32> = perspective(Deg(45.0), self.window.width as f32 / self.window.height as f32, 0.1, 100.0);
-                let view: Matrix4<f32> = Matrix4::from_translation(Vector3::<f32>::new(0., 0., -3.));
+                // whatever ran since the last frame may have changed GL state behind the cache's back
+                self.state.invalidate();
+
+                if let Some(deferred) = self.deferred.as_mut() {
+                    deferred.begin_geometry(self.window.width, self.window.height);
+                    let deferred: &DeferredRenderer = deferred;
+                    let geometry_shader = &deferred.geometry_shader;
+                    self.state.use_program(geometry_shader.ID);
+                    geometry_shader.setMat4(c_str!("projection"), &projection);
+                    geometry_shader.setMat4(c_str!("view"), &view);
+                    let mut opaque = RenderQueue::new();
+                    opaque.push_model(&scene.root, geometry_shader, &model, &view);
+                    opaque.retain(|item| !item.transparent);
+                    opaque.execute(&mut self.state);
+
+                    let environment = scene.skybox.as_ref().map(|skybox| &skybox.cubemap);
+                    deferred.light(target, &camera, &scene.lights, Some(&self.shadows), environment);
+                    self.state.invalidate();
+                }
+
+                self.state.use_program(scene.shader.ID);
                 scene.shader.setMat4(c_str!("projection"), &projection);
                 scene.shader.setMat4(c_str!("view"), &view);
+                scene.shader.setVector3(c_str!("viewPos"), &view_pos);
+                apply_lights(&scene.shader, &scene.lights, Some(&self.shadows));
+                self.shadows.bind(&scene.shader);
+                bind_environment(&scene.shader, scene.skybox.as_ref().map(|skybox| &skybox.cubemap));
+                bind_ibl(&scene.shader, scene.ibl.as_ref());
 
-                let mut model = Matrix4::<f32>::from_translation(model_pos);
-                scene.shader.setMat4(c_str!("model"), &model);
-                scene.root.draw(&scene.shader);
+                let mut queue = RenderQueue::new();
+                queue.push_model(&scene.root, &scene.shader, &model, &view);
+                let mut transparent = queue.take_transparent();
+                if self.deferred.is_some() {
+                    // opaque objects were shaded by the deferred path
+                    queue.clear();
+                }
+                queue.execute(&mut self.state);
+                if let Some(skybox) = &scene.skybox {
+                    skybox.draw(&camera);
+                    self.state.invalidate();
+                }
+                transparent.execute(&mut self.state);
+                let exposure = self.exposure.update(self.post.scene_texture(), delta_time);
+                if let Some(tonemap) = self.post.effect_mut("tonemap") {
+                    tonemap.set_param("exposure", exposure);
+                }
+                self.post.finish();
+                self.frame_stats = self.state.take_stats();
 
                 self.window.update();
             }
+
+            if self.print_stats && curr_frame - last_report >= 1.0 {
+                println!("{}", self.frame_stats);
+                last_report = curr_frame;
+            }
+        }
+    }
+
+    /// Rebuilds every shader of the scene and the renderer whose files changed on disk.
+    /// A shader that fails to build keeps its previous program. The state cache forgets
+    /// the replaced programs, GL may hand their names out again.
+    fn reload_shaders(&mut self, scene: &mut Scene) {
+        let mut replaced: Vec<u32> = scene.shader.reload_if_changed().into_iter().collect();
+        if let Some(skybox) = scene.skybox.as_mut() {
+            replaced.extend(skybox.reload_shaders());
+        }
+        replaced.extend(self.shadows.reload_shaders());
+        replaced.extend(self.post.reload_shaders());
+        replaced.extend(self.exposure.reload_shaders());
+        if let Some(deferred) = self.deferred.as_mut() {
+            replaced.extend(deferred.reload_shaders());
+        }
+        for program in replaced {
+            self.state.forget_program(program);
         }
     }
 }
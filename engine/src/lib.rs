@@ -7,3 +7,6 @@ pub use self::engine::EngineConfig;
 
 pub mod model;
 pub use self::model::*;
+
+pub mod render;
+pub use self::render::*;
@@ -10,7 +10,9 @@ use tobj;
 fn main() {
     let config = EngineConfig {
         window_width: 800,
-        window_height: 600
+        window_height: 600,
+        print_stats: true,
+        ..EngineConfig::default()
     };
     let mut engine = Engine::new(config);
     let mut scene = Scene::new("src/ico_sphere/b_cube.obj");
@@ -0,0 +1,404 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use gl;
+use image;
+use image::RgbaImage;
+
+use crate::model::mesh::Texture;
+use crate::model::model::{Model, ModelFormat};
+use crate::model::texture::{gpu_memory_size, create_texture, TextureOptions, TextureSampler};
+
+/// How `Model::build_atlas` packs material textures.
+#[derive(Clone, Debug)]
+pub struct AtlasSettings {
+    /// Largest page side in pixels. Materials that do not fit keep their own textures,
+    /// the biggest ones first.
+    pub max_size: u32,
+    /// Images wider or taller than this keep their own texture.
+    pub max_image_size: u32,
+    /// Border around every image, filled with its edge pixels, so filtering does not
+    /// pick up the neighbours. Pages keep the mip levels down to the one where the border
+    /// is a texel wide, log2 of it, the smaller ones would blend neighbours together.
+    pub padding: u32,
+    /// Prefix of the page and description file names.
+    pub name: String
+}
+
+impl Default for AtlasSettings {
+    fn default() -> Self {
+        AtlasSettings {
+            max_size: 4096,
+            max_image_size: 512,
+            padding: 4,
+            name: "atlas".into()
+        }
+    }
+}
+
+/// Where one material's images went, in pixels, padding not included.
+#[derive(Clone, Debug)]
+pub struct AtlasEntry {
+    /// Index into `Model::materials`.
+    pub material: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Type and path of the textures the material had before.
+    pub sources: Vec<(String, String)>
+}
+
+/// Materials with the same texture types, packed the same way into one page per type.
+pub struct AtlasGroup {
+    /// Texture types, sorted, one page each.
+    pub types: Vec<String>,
+    pub width: u32,
+    pub height: u32,
+    pub entries: Vec<AtlasEntry>,
+    /// The page textures, in the order of `types`.
+    pub textures: Vec<Texture>,
+    /// RGBA rows of every page, in the row order the textures were uploaded in.
+    pages: Vec<Vec<u8>>
+}
+
+pub struct Atlas {
+    pub name: String,
+    pub padding: u32,
+    pub groups: Vec<AtlasGroup>,
+    /// The pages are stored bottom row first, like OBJ textures are uploaded.
+    bottom_up: bool
+}
+
+impl Atlas {
+    /// File name of the page of `type_` in group `group`.
+    pub fn page_file_name(name: &str, group: usize, type_: &str) -> String {
+        format!("{}{}_{}.png", name, group, type_)
+    }
+
+    /// Writes every page as PNG and a `<name>.atlas` text file that lists them along with
+    /// the rectangle of each material and the images that went into it. Write into the
+    /// model's directory for the texture paths of the model to point at the pages.
+    pub fn write(&self, directory: &Path) -> io::Result<()> {
+        let mut description = BufWriter::new(File::create(directory.join(format!("{}.atlas", self.name)))?);
+        writeln!(description, "# texture atlas written by engine")?;
+        writeln!(description, "padding {}", self.padding)?;
+        for (g, group) in self.groups.iter().enumerate() {
+            writeln!(description)?;
+            writeln!(description, "group {} {} {}", g, group.width, group.height)?;
+            for (type_, pixels) in group.types.iter().zip(&group.pages) {
+                let file_name = Atlas::page_file_name(&self.name, g, type_);
+                writeln!(description, "page {} {}", type_, file_name)?;
+
+                let row = group.width as usize * 4;
+                let rows: Vec<u8> = if self.bottom_up {
+                    pixels.chunks(row).rev().flat_map(|r| r.iter().cloned()).collect()
+                } else {
+                    pixels.clone()
+                };
+                image::save_buffer(directory.join(&file_name), &rows, group.width, group.height, image::ColorType::RGBA(8))?;
+            }
+            for entry in &group.entries {
+                writeln!(description, "entry {} {} {} {} {}", entry.material, entry.x, entry.y, entry.width, entry.height)?;
+                for (type_, path) in &entry.sources {
+                    writeln!(description, "source {} {}", type_, path)?;
+                }
+            }
+        }
+        description.flush()
+    }
+}
+
+/// Skyline bottom-left packer. Each node is a segment of the top outline: x, y and width.
+struct Skyline {
+    width: u32,
+    height: u32,
+    nodes: Vec<(u32, u32, u32)>
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Skyline {
+        Skyline { width, height, nodes: vec![(0, 0, width)] }
+    }
+
+    /// Places a `width` x `height` rectangle as low as possible, then as far left.
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(u32, usize, u32)> = None;
+        for i in 0..self.nodes.len() {
+            if let Some(y) = self.fit(i, width, height) {
+                if best.map_or(true, |(best_y, _, _)| y + height < best_y) {
+                    best = Some((y + height, i, y));
+                }
+            }
+        }
+        let (_, i, y) = best?;
+        let x = self.nodes[i].0;
+
+        self.nodes.insert(i, (x, y + height, width));
+        // cut the nodes the new one covers
+        while i + 1 < self.nodes.len() {
+            let end = self.nodes[i].0 + self.nodes[i].2;
+            let next = &mut self.nodes[i + 1];
+            if next.0 >= end {
+                break;
+            }
+            let covered = end - next.0;
+            if next.2 <= covered {
+                self.nodes.remove(i + 1);
+            } else {
+                next.0 += covered;
+                next.2 -= covered;
+                break;
+            }
+        }
+        // and merge neighbours at the same height
+        let mut j = 0;
+        while j + 1 < self.nodes.len() {
+            if self.nodes[j].1 == self.nodes[j + 1].1 {
+                self.nodes[j].2 += self.nodes[j + 1].2;
+                self.nodes.remove(j + 1);
+            } else {
+                j += 1;
+            }
+        }
+        Some((x, y))
+    }
+
+    /// Lowest y a rectangle starting at node `i` can sit at.
+    fn fit(&self, i: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.nodes[i].0;
+        if x + width > self.width {
+            return None;
+        }
+        let (mut y, mut remaining, mut j) = (0, width, i);
+        loop {
+            y = y.max(self.nodes[j].1);
+            if y + height > self.height {
+                return None;
+            }
+            if self.nodes[j].2 >= remaining {
+                return Some(y);
+            }
+            remaining -= self.nodes[j].2;
+            j += 1;
+        }
+    }
+}
+
+/// Packs rectangles of `sizes` into the smallest power of two page, no side above
+/// `max_size`, that holds all of them. Returns the page size and the position of each.
+/// The result only depends on the sizes and their order, so the same input always
+/// packs the same way.
+pub fn pack_rects(sizes: &[(u32, u32)], max_size: u32) -> Option<(u32, u32, Vec<(u32, u32)>)> {
+    let area: u64 = sizes.iter().map(|&(w, h)| w as u64 * h as u64).sum();
+    let largest = sizes.iter().map(|&(w, h)| w.max(h)).max().unwrap_or(1).max(1);
+    if largest > max_size {
+        return None;
+    }
+    // tallest first, then widest, then as given
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| (Reverse(sizes[i].1), Reverse(sizes[i].0), i));
+
+    let (mut width, mut height) = (largest.next_power_of_two(), largest.next_power_of_two());
+    while width <= max_size && height <= max_size {
+        if width as u64 * height as u64 >= area {
+            let mut skyline = Skyline::new(width, height);
+            let mut positions = vec![(0, 0); sizes.len()];
+            let packed = order.iter().all(|&i| match skyline.insert(sizes[i].0, sizes[i].1) {
+                Some(position) => { positions[i] = position; true }
+                None => false
+            });
+            if packed {
+                return Some((width, height, positions));
+            }
+        }
+        if width > height { height *= 2 } else { width *= 2 }
+    }
+    None
+}
+
+/// A material whose textures can go into an atlas, with them loaded.
+struct Candidate {
+    material: usize,
+    width: u32,
+    height: u32,
+    images: Vec<RgbaImage>
+}
+
+impl Model {
+    /// Packs the textures of materials that are small enough into shared atlas pages and
+    /// remaps the texture coordinates of their meshes, so they draw without rebinding.
+    /// Materials are grouped by the texture types they have, each group packs into one
+    /// page per type. Meshes with coordinates outside 0 to 1 repeat their textures and
+    /// keep them, as do images that are not plain files `image` reads.
+    /// The pages are named for `Atlas::write`, which has to write them into the model
+    /// directory for the texture paths to be found again, e.g. by `export_obj`.
+    pub fn build_atlas(&mut self, settings: &AtlasSettings) -> Atlas {
+        // OBJ textures are flipped on load, glTF ones are not
+        let bottom_up = self.source_format != ModelFormat::Gltf;
+        let mut groups: BTreeMap<Vec<String>, Vec<Candidate>> = BTreeMap::new();
+        for material in 0..self.materials.len() {
+            if let Some((types, candidate)) = self.atlas_candidate(material, settings, bottom_up) {
+                groups.entry(types).or_insert_with(Vec::new).push(candidate);
+            }
+        }
+
+        let mut atlas = Atlas { name: settings.name.clone(), padding: settings.padding, groups: Vec::new(), bottom_up };
+        for (types, mut candidates) in groups {
+            // leave out the largest until the rest fits
+            let packed = loop {
+                if candidates.len() < 2 {
+                    break None;
+                }
+                let sizes: Vec<(u32, u32)> = candidates.iter()
+                    .map(|c| (c.width + 2 * settings.padding, c.height + 2 * settings.padding))
+                    .collect();
+                match pack_rects(&sizes, settings.max_size) {
+                    Some(packed) => break Some(packed),
+                    None => {
+                        let largest = (0..candidates.len())
+                            .max_by_key(|&i| (candidates[i].width * candidates[i].height, Reverse(i)))
+                            .unwrap();
+                        println!("atlas: {} does not fit, keeps its textures", self.materials[candidates[largest].material].name);
+                        candidates.remove(largest);
+                    }
+                }
+            };
+            if let Some((width, height, positions)) = packed {
+                let group = self.build_atlas_group(atlas.groups.len(), types, candidates, width, height, &positions, settings);
+                atlas.groups.push(group);
+            }
+        }
+        self.release_unused_textures();
+        atlas
+    }
+
+    /// The sorted texture types of `material` and its images, if it can be packed.
+    fn atlas_candidate(&self, material: usize, settings: &AtlasSettings, bottom_up: bool) -> Option<(Vec<String>, Candidate)> {
+        let textures = &self.materials[material].textures;
+        let mut types: Vec<String> = textures.iter().map(|t| t.type_.clone()).collect();
+        types.sort();
+        types.dedup();
+        if textures.is_empty() || types.len() != textures.len() {
+            return None;
+        }
+        let mut meshes = self.meshes.iter().filter(|mesh| mesh.material_id == Some(material)).peekable();
+        meshes.peek()?;
+        let in_range = |c: f32| c >= -1e-4 && c <= 1.0 + 1e-4;
+        if !meshes.all(|mesh| mesh.vertices.iter().all(|v| in_range(v.tex_coords.x) && in_range(v.tex_coords.y))) {
+            return None;
+        }
+
+        let mut images = Vec::with_capacity(types.len());
+        for type_ in &types {
+            let texture = textures.iter().find(|t| &t.type_ == type_).unwrap();
+            let image = image::open(Path::new(&self.directory).join(&texture.path)).ok()?;
+            let image = if bottom_up { image.flipv() } else { image };
+            let image = image.to_rgba();
+            if image.width() > settings.max_image_size || image.height() > settings.max_image_size {
+                return None;
+            }
+            images.push(image);
+        }
+        // images of different sizes are scaled to the largest
+        let width = images.iter().map(|i| i.width()).max().unwrap();
+        let height = images.iter().map(|i| i.height()).max().unwrap();
+        Some((types, Candidate { material, width, height, images }))
+    }
+
+    fn build_atlas_group(&mut self, index: usize, types: Vec<String>, candidates: Vec<Candidate>, width: u32, height: u32,
+                         positions: &[(u32, u32)], settings: &AtlasSettings) -> AtlasGroup {
+        let padding = settings.padding;
+        let mut pages = vec![vec![0u8; width as usize * height as usize * 4]; types.len()];
+        let mut entries = Vec::with_capacity(candidates.len());
+        for (candidate, &(x, y)) in candidates.iter().zip(positions) {
+            let (x, y) = (x + padding, y + padding);
+            for (page, image) in pages.iter_mut().zip(&candidate.images) {
+                let resized;
+                let image = if image.dimensions() != (candidate.width, candidate.height) {
+                    resized = image::imageops::resize(image, candidate.width, candidate.height, image::FilterType::Triangle);
+                    &resized
+                } else {
+                    image
+                };
+                blit_padded(page, width, image, x, y, padding);
+            }
+            let material = &self.materials[candidate.material];
+            entries.push(AtlasEntry {
+                material: candidate.material,
+                x, y,
+                width: candidate.width,
+                height: candidate.height,
+                sources: material.textures.iter().map(|t| (t.type_.clone(), t.path.clone())).collect()
+            });
+        }
+
+        let textures: Vec<Texture> = types.iter().zip(&pages).map(|(type_, pixels)| unsafe {
+            let options = TextureOptions { sampler: TextureSampler::clamped(), ..TextureOptions::for_type(type_) };
+            let id = create_texture(width, height, gl::RGBA, gl::UNSIGNED_BYTE, pixels, &options);
+            gl::BindTexture(gl::TEXTURE_2D, id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, max_mip_level(settings.padding) as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            Texture {
+                id,
+                type_: type_.clone(),
+                path: Atlas::page_file_name(&settings.name, index, type_),
+                sampler: None,
+                memory_size: gpu_memory_size(id)
+            }
+        }).collect();
+        self.textures_loaded.extend(textures.iter().cloned());
+
+        let (page_width, page_height) = (width as f32, height as f32);
+        for entry in &entries {
+            self.materials[entry.material].textures = textures.clone();
+            for mesh in self.meshes.iter_mut().filter(|mesh| mesh.material_id == Some(entry.material)) {
+                for vertex in &mut mesh.vertices {
+                    let (u, v) = (vertex.tex_coords.x.max(0.0).min(1.0), vertex.tex_coords.y.max(0.0).min(1.0));
+                    vertex.tex_coords.x = (entry.x as f32 + u * entry.width as f32) / page_width;
+                    vertex.tex_coords.y = (entry.y as f32 + v * entry.height as f32) / page_height;
+                }
+                mesh.textures = textures.clone();
+                mesh.update_vertices();
+            }
+        }
+        println!("atlas: {} materials into {}x{} pages of {}", entries.len(), width, height, types.join(", "));
+
+        AtlasGroup { types, width, height, entries, textures, pages }
+    }
+
+    /// Deletes the loaded textures no material uses anymore.
+    fn release_unused_textures(&mut self) {
+        let used: Vec<u32> = self.materials.iter().flat_map(|m| m.textures.iter().map(|t| t.id)).collect();
+        let (kept, unused): (Vec<Texture>, Vec<Texture>) = self.textures_loaded.drain(..)
+            .partition(|texture| used.contains(&texture.id));
+        for texture in unused {
+            unsafe { gl::DeleteTextures(1, &texture.id) };
+        }
+        self.textures_loaded = kept;
+    }
+}
+
+/// Last mip level whose copy of a `padding` wide border is still a texel wide.
+fn max_mip_level(padding: u32) -> u32 {
+    31 - padding.max(1).leading_zeros()
+}
+
+/// Copies `image` to `x`, `y` of an RGBA page `page_width` wide, and repeats its edge
+/// pixels `padding` pixels out on every side.
+fn blit_padded(page: &mut [u8], page_width: u32, image: &RgbaImage, x: u32, y: u32, padding: u32) {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let padding = padding as i64;
+    for dy in -padding..height + padding {
+        let source_y = dy.max(0).min(height - 1) as u32;
+        let row = (y as i64 + dy) as usize * page_width as usize;
+        for dx in -padding..width + padding {
+            let source_x = dx.max(0).min(width - 1) as u32;
+            let target = (row + (x as i64 + dx) as usize) * 4;
+            page[target..target + 4].copy_from_slice(&image.get_pixel(source_x, source_y).data);
+        }
+    }
+}
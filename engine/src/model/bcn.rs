@@ -0,0 +1,603 @@
+use std::convert::TryInto;
+
+use super::texture::PixelFormat;
+
+const WEIGHTS2: [i32; 4] = [0, 21, 43, 64];
+const WEIGHTS3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS4: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Subset of each texel for the 64 two subset partitions of BC6H and BC7, 2 bits per texel.
+const PARTITIONS2: [u32; 64] = [
+    0x50505050, 0x40404040, 0x54545454, 0x54505040, 0x50404000, 0x55545450, 0x55545040, 0x54504000,
+    0x50400000, 0x55555450, 0x55544000, 0x54400000, 0x55555440, 0x55550000, 0x55555500, 0x55000000,
+    0x55150100, 0x00004054, 0x15010000, 0x00405054, 0x00004050, 0x15050100, 0x05010000, 0x40505054,
+    0x00404050, 0x05010100, 0x14141414, 0x05141450, 0x01155440, 0x00555500, 0x15014054, 0x05414150,
+    0x44444444, 0x55005500, 0x11441144, 0x05055050, 0x05500550, 0x11114444, 0x41144114, 0x44111144,
+    0x15055054, 0x01055040, 0x05041050, 0x05455150, 0x14414114, 0x50050550, 0x41411414, 0x00141400,
+    0x00041504, 0x00105410, 0x10541000, 0x04150400, 0x50410514, 0x41051450, 0x05415014, 0x14054150,
+    0x41050514, 0x41505014, 0x40011554, 0x54150140, 0x50505500, 0x00555050, 0x15151010, 0x54540404
+];
+
+/// Subset of each texel for the 64 three subset partitions of BC7.
+const PARTITIONS3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254
+];
+
+/// Texel whose index drops its top bit, for subset 1 of the two subset partitions.
+const ANCHORS2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15,  2,  8,  2,  2,  8,  8, 15,  2,  8,  2,  2,  8,  8,  2,  2,
+    15, 15,  6,  8,  2,  8, 15, 15,  2,  8,  2,  2,  2, 15, 15,  6,
+     6,  2,  6,  8, 15, 15,  2,  2, 15, 15, 15, 15, 15,  2,  2, 15
+];
+
+/// The same for subsets 1 and 2 of the three subset partitions.
+const ANCHORS3: [[usize; 64]; 2] = [[
+     3,  3, 15, 15,  8,  3, 15, 15,  8,  8,  6,  6,  6,  5,  3,  3,
+     3,  3,  8, 15,  3,  3,  6, 10,  5,  8,  8,  6,  8,  5, 15, 15,
+     8, 15,  3,  5,  6, 10,  8, 15, 15,  3, 15,  5, 15, 15, 15, 15,
+     3, 15,  5,  5,  5,  8,  5, 10,  5, 10,  8, 13, 15, 12,  3,  3
+], [
+    15,  8,  8,  3, 15, 15,  3,  8, 15, 15, 15, 15, 15, 15, 15,  8,
+    15,  8, 15,  3, 15,  8, 15,  8,  3, 15,  6, 10, 15, 15, 10,  8,
+    15,  3, 15, 10, 10,  8,  9, 10,  6, 15,  8, 15,  3,  6,  6,  8,
+    15,  3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,  3, 15, 15,  8
+]];
+
+/// Decodes one mip level into rows of `format.pixel_size()` bytes per texel,
+/// in the layout `PixelFormat::gl_format` uploads.
+pub fn decode(format: PixelFormat, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let block_size = format.block_size().expect("decode needs a block compressed format");
+    let pixel_size = format.pixel_size();
+    let blocks_x = (width + 3) / 4;
+    let blocks = blocks_x * ((height + 3) / 4);
+
+    let mut pixels = vec![0u8; width * height * pixel_size];
+    let mut texels = [0u8; 16 * 8];
+    for (i, block) in data.chunks_exact(block_size).take(blocks).enumerate() {
+        decode_block(format, block, &mut texels);
+        let (x0, y0) = (i % blocks_x * 4, i / blocks_x * 4);
+        for y in 0..4.min(height - y0) {
+            let columns = 4.min(width - x0);
+            let source = &texels[y * 4 * pixel_size..][..columns * pixel_size];
+            let offset = ((y0 + y) * width + x0) * pixel_size;
+            pixels[offset..offset + columns * pixel_size].copy_from_slice(source);
+        }
+    }
+    pixels
+}
+
+/// Flips a level upside down by reordering its blocks and the rows inside them, keeping
+/// it compressed. None for BC6H and BC7, whose rows can't be moved without decoding,
+/// and for heights that end in a partly used row of blocks.
+pub fn flip_blocks(format: PixelFormat, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let block_size = format.block_size()?;
+    let (width, height) = (width as usize, height as usize);
+    if height % 4 != 0 && height > 4 {
+        return None;
+    }
+    let blocks_x = (width + 3) / 4;
+    let blocks_y = (height + 3) / 4;
+    if data.len() < blocks_x * blocks_y * block_size {
+        return None;
+    }
+    let rows = height.min(4);
+
+    let mut flipped = Vec::with_capacity(data.len());
+    for by in (0..blocks_y).rev() {
+        for bx in 0..blocks_x {
+            let mut block = data[(by * blocks_x + bx) * block_size..][..block_size].to_vec();
+            match format {
+                PixelFormat::Bc1 => flip_color_block(&mut block, rows),
+                PixelFormat::Bc2 => {
+                    let alpha: Vec<u16> = (0..4).map(|r| u16::from_le_bytes([block[r * 2], block[r * 2 + 1]])).collect();
+                    for r in 0..rows {
+                        block[r * 2..r * 2 + 2].copy_from_slice(&alpha[rows - 1 - r].to_le_bytes());
+                    }
+                    flip_color_block(&mut block[8..], rows);
+                },
+                PixelFormat::Bc3 => {
+                    flip_alpha_block(&mut block, rows);
+                    flip_color_block(&mut block[8..], rows);
+                },
+                PixelFormat::Bc4 | PixelFormat::Bc4Signed => flip_alpha_block(&mut block, rows),
+                PixelFormat::Bc5 | PixelFormat::Bc5Signed => {
+                    flip_alpha_block(&mut block, rows);
+                    flip_alpha_block(&mut block[8..], rows);
+                },
+                _ => return None
+            }
+            flipped.extend_from_slice(&block);
+        }
+    }
+    Some(flipped)
+}
+
+/// BC1 color indices are a byte per row.
+fn flip_color_block(block: &mut [u8], rows: usize) {
+    block[4..4 + rows].reverse();
+}
+
+/// BC4 style alpha indices are 12 bits per row after the two endpoints.
+fn flip_alpha_block(block: &mut [u8], rows: usize) {
+    let mut bytes = [0u8; 8];
+    bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bytes);
+    let mut flipped = indices;
+    for r in 0..rows {
+        let row = (indices >> (12 * (rows - 1 - r))) & 0xfff;
+        flipped = (flipped & !(0xfff << (12 * r))) | (row << (12 * r));
+    }
+    block[2..8].copy_from_slice(&flipped.to_le_bytes()[..6]);
+}
+
+fn decode_block(format: PixelFormat, block: &[u8], texels: &mut [u8]) {
+    match format {
+        PixelFormat::Bc1 => decode_color(block, false, texels),
+        PixelFormat::Bc2 => {
+            decode_color(&block[8..], true, texels);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for i in 0..16 {
+                texels[i * 4 + 3] = ((alpha >> (i * 4)) & 0xf) as u8 * 17;
+            }
+        },
+        PixelFormat::Bc3 => {
+            decode_color(&block[8..], true, texels);
+            let alpha = decode_alpha(block);
+            for i in 0..16 {
+                texels[i * 4 + 3] = alpha[i];
+            }
+        },
+        PixelFormat::Bc4 => texels[..16].copy_from_slice(&decode_alpha(block)),
+        PixelFormat::Bc4Signed => texels[..16].copy_from_slice(&decode_signed_alpha(block)),
+        PixelFormat::Bc5 | PixelFormat::Bc5Signed => {
+            let decode_channel = if format == PixelFormat::Bc5 { decode_alpha } else { decode_signed_alpha };
+            let (red, green) = (decode_channel(block), decode_channel(&block[8..]));
+            for i in 0..16 {
+                texels[i * 2] = red[i];
+                texels[i * 2 + 1] = green[i];
+            }
+        },
+        PixelFormat::Bc6h | PixelFormat::Bc6hSigned => decode_bc6h(block, format == PixelFormat::Bc6hSigned, texels),
+        PixelFormat::Bc7 => decode_bc7(block, texels),
+        _ => unreachable!()
+    }
+}
+
+fn rgb565(color: u16) -> [i32; 3] {
+    let (r, g, b) = ((color >> 11) as i32 & 31, (color >> 5) as i32 & 63, color as i32 & 31);
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// The BC1 block, also the color half of BC2 and BC3, which always use four colors.
+/// Writes RGBA.
+fn decode_color(block: &[u8], four_colors: bool, texels: &mut [u8]) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mut palette = [[0u8; 4]; 4];
+    for c in 0..3 {
+        palette[0][c] = a[c] as u8;
+        palette[1][c] = b[c] as u8;
+        if c0 > c1 || four_colors {
+            palette[2][c] = ((2 * a[c] + b[c]) / 3) as u8;
+            palette[3][c] = ((a[c] + 2 * b[c]) / 3) as u8;
+        } else {
+            palette[2][c] = ((a[c] + b[c]) / 2) as u8;
+        }
+    }
+    palette[0][3] = 255;
+    palette[1][3] = 255;
+    palette[2][3] = 255;
+    // in three color mode the last entry is transparent black
+    palette[3][3] = if c0 > c1 || four_colors { 255 } else { 0 };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for i in 0..16 {
+        let color = palette[(indices >> (i * 2)) as usize & 3];
+        texels[i * 4..i * 4 + 4].copy_from_slice(&color);
+    }
+}
+
+/// The BC4 block, also the alpha half of BC3 and both halves of BC5.
+fn decode_alpha(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as i32, block[1] as i32);
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i as usize + 1] = ((7 - i) * a0 + i * a1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = ((5 - i) * a0 + i * a1) / 5;
+        }
+    }
+    let mut values = [0u8; 16];
+    let indices = alpha_indices(block);
+    for i in 0..16 {
+        values[i] = palette[(indices >> (i * 3)) as usize & 7] as u8;
+    }
+    values
+}
+
+/// BC4 with signed endpoints, the values are written as two's complement bytes.
+fn decode_signed_alpha(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = ((block[0] as i8 as i32).max(-127), (block[1] as i8 as i32).max(-127));
+    let mut palette = [a0, a1, 0, 0, 0, 0, -127, 127];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i as usize + 1] = ((7 - i) * a0 + i * a1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = ((5 - i) * a0 + i * a1) / 5;
+        }
+    }
+    let mut values = [0u8; 16];
+    let indices = alpha_indices(block);
+    for i in 0..16 {
+        values[i] = palette[(indices >> (i * 3)) as usize & 7] as i8 as u8;
+    }
+    values
+}
+
+fn alpha_indices(block: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[..6].copy_from_slice(&block[2..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Reads the fields of a 128 bit block, lowest bit first.
+struct BitReader {
+    bits: u128,
+    position: u32
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> BitReader {
+        BitReader { bits: u128::from_le_bytes(block[..16].try_into().unwrap()), position: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = ((self.bits >> self.position) & ((1u128 << count) - 1)) as u32;
+        self.position += count;
+        value
+    }
+}
+
+fn interpolate(a: i32, b: i32, index: u32, bits: u32) -> i32 {
+    let weight = match bits {
+        2 => WEIGHTS2[index as usize],
+        3 => WEIGHTS3[index as usize],
+        _ => WEIGHTS4[index as usize]
+    };
+    ((64 - weight) * a + weight * b + 32) >> 6
+}
+
+fn subset(subsets: usize, partition: usize, texel: usize) -> usize {
+    match subsets {
+        2 => (PARTITIONS2[partition] >> (texel * 2)) as usize & 3,
+        3 => (PARTITIONS3[partition] >> (texel * 2)) as usize & 3,
+        _ => 0
+    }
+}
+
+fn is_anchor(subsets: usize, partition: usize, texel: usize) -> bool {
+    texel == 0 || match subsets {
+        2 => texel == ANCHORS2[partition],
+        3 => texel == ANCHORS3[0][partition] || texel == ANCHORS3[1][partition],
+        _ => false
+    }
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// A p-bit, the shared lowest bit of all channels, per endpoint.
+    endpoint_pbits: bool,
+    /// One p-bit per subset, for both its endpoints.
+    shared_pbits: bool,
+    index_bits: u32,
+    /// Bits of the second index set, which modes 4 and 5 use for color or alpha.
+    index_bits2: u32
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0,
+              endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0,
+              endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0,
+              endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0,
+              endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6,
+              endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8,
+              endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7,
+              endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5,
+              endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 }
+];
+
+/// Writes RGBA. Blocks with an invalid mode decode to transparent black.
+fn decode_bc7(block: &[u8], texels: &mut [u8]) {
+    let mut bits = BitReader::new(block);
+    // the mode is the number of zero bits before the first one
+    let mode = match (0..8).find(|_| bits.read(1) == 1) {
+        Some(mode) => &BC7_MODES[mode],
+        None => {
+            for texel in texels[..64].iter_mut() {
+                *texel = 0;
+            }
+            return;
+        }
+    };
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let count = mode.subsets * 2;
+    let mut endpoints = [[0i32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints[..count].iter_mut() {
+            endpoint[channel] = bits.read(mode.color_bits) as i32;
+        }
+    }
+    for endpoint in endpoints[..count].iter_mut() {
+        endpoint[3] = bits.read(mode.alpha_bits) as i32;
+    }
+
+    let mut pbits = [0i32; 6];
+    if mode.endpoint_pbits {
+        for pbit in pbits[..count].iter_mut() {
+            *pbit = bits.read(1) as i32;
+        }
+    } else if mode.shared_pbits {
+        for subset in 0..mode.subsets {
+            let pbit = bits.read(1) as i32;
+            pbits[subset * 2] = pbit;
+            pbits[subset * 2 + 1] = pbit;
+        }
+    }
+    let has_pbits = (mode.endpoint_pbits || mode.shared_pbits) as u32;
+    for (endpoint, &pbit) in endpoints[..count].iter_mut().zip(pbits.iter()) {
+        for channel in 0..4 {
+            if channel == 3 && mode.alpha_bits == 0 {
+                endpoint[3] = 255;
+                continue;
+            }
+            let precision = if channel < 3 { mode.color_bits } else { mode.alpha_bits } + has_pbits;
+            let value = if has_pbits == 1 { endpoint[channel] << 1 | pbit } else { endpoint[channel] };
+            // repeat the top bits in the bits below to fill the byte
+            let value = value << (8 - precision);
+            endpoint[channel] = value | (value >> precision);
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, texel) as u32;
+        *index = bits.read(mode.index_bits - anchor);
+    }
+    let mut indices2 = [0u32; 16];
+    if mode.index_bits2 > 0 {
+        for (texel, index) in indices2.iter_mut().enumerate() {
+            *index = bits.read(mode.index_bits2 - (texel == 0) as u32);
+        }
+    }
+
+    for texel in 0..16 {
+        let subset = subset(mode.subsets, partition, texel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        let ((color, color_bits), (alpha, alpha_bits)) = if mode.index_bits2 == 0 {
+            ((indices[texel], mode.index_bits), (indices[texel], mode.index_bits))
+        } else if index_selection == 0 {
+            ((indices[texel], mode.index_bits), (indices2[texel], mode.index_bits2))
+        } else {
+            ((indices2[texel], mode.index_bits2), (indices[texel], mode.index_bits))
+        };
+        let mut rgba = [0u8; 4];
+        for channel in 0..3 {
+            rgba[channel] = interpolate(e0[channel], e1[channel], color, color_bits) as u8;
+        }
+        rgba[3] = interpolate(e0[3], e1[3], alpha, alpha_bits) as u8;
+        // modes 4 and 5 can store a color channel in the alpha endpoints and the other way around
+        if rotation > 0 {
+            rgba.swap(3, rotation as usize - 1);
+        }
+        texels[texel * 4..texel * 4 + 4].copy_from_slice(&rgba);
+    }
+}
+
+// Endpoint channels of BC6H: r, g and b of endpoints 0 to 3.
+const R0: usize = 0; const G0: usize = 1; const B0: usize = 2;
+const R1: usize = 3; const G1: usize = 4; const B1: usize = 5;
+const R2: usize = 6; const G2: usize = 7; const B2: usize = 8;
+const R3: usize = 9; const G3: usize = 10; const B3: usize = 11;
+
+struct Bc6hMode {
+    /// The 2 or 5 mode bits.
+    value: u32,
+    two_regions: bool,
+    /// Whether endpoints after the first are stored as deltas from it.
+    transformed: bool,
+    endpoint_bits: u32,
+    /// Precision of the r, g and b deltas.
+    delta_bits: [u32; 3],
+    /// Where the header bits go, in the order they are stored: the endpoint channel,
+    /// the lowest bit written and the number of bits.
+    fields: &'static [(usize, u32, u32)]
+}
+
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode { value: 0, two_regions: true, transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], fields: &[
+        (G2, 4, 1), (B2, 4, 1), (B3, 4, 1), (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 5), (G3, 4, 1), (G2, 0, 4),
+        (G1, 0, 5), (B3, 0, 1), (G3, 0, 4), (B1, 0, 5), (B3, 1, 1), (B2, 0, 4), (R2, 0, 5), (B3, 2, 1), (R3, 0, 5),
+        (B3, 3, 1)] },
+    Bc6hMode { value: 1, two_regions: true, transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], fields: &[
+        (G2, 5, 1), (G3, 4, 1), (G3, 5, 1), (R0, 0, 7), (B3, 0, 1), (B3, 1, 1), (B2, 4, 1), (G0, 0, 7), (B2, 5, 1),
+        (B3, 2, 1), (G2, 4, 1), (B0, 0, 7), (B3, 3, 1), (B3, 5, 1), (B3, 4, 1), (R1, 0, 6), (G2, 0, 4), (G1, 0, 6),
+        (G3, 0, 4), (B1, 0, 6), (B2, 0, 4), (R2, 0, 6), (R3, 0, 6)] },
+    Bc6hMode { value: 2, two_regions: true, transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 5), (R0, 10, 1), (G2, 0, 4), (G1, 0, 4), (G0, 10, 1),
+        (B3, 0, 1), (G3, 0, 4), (B1, 0, 4), (B0, 10, 1), (B3, 1, 1), (B2, 0, 4), (R2, 0, 5), (B3, 2, 1), (R3, 0, 5),
+        (B3, 3, 1)] },
+    Bc6hMode { value: 6, two_regions: true, transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 4), (R0, 10, 1), (G3, 4, 1), (G2, 0, 4), (G1, 0, 5),
+        (G0, 10, 1), (G3, 0, 4), (B1, 0, 4), (B0, 10, 1), (B3, 1, 1), (B2, 0, 4), (R2, 0, 4), (B3, 0, 1), (B3, 2, 1),
+        (R3, 0, 4), (G2, 4, 1), (B3, 3, 1)] },
+    Bc6hMode { value: 10, two_regions: true, transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 4), (R0, 10, 1), (B2, 4, 1), (G2, 0, 4), (G1, 0, 4),
+        (G0, 10, 1), (B3, 0, 1), (G3, 0, 4), (B1, 0, 5), (B0, 10, 1), (B2, 0, 4), (R2, 0, 4), (B3, 1, 1), (B3, 2, 1),
+        (R3, 0, 4), (B3, 4, 1), (B3, 3, 1)] },
+    Bc6hMode { value: 14, two_regions: true, transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], fields: &[
+        (R0, 0, 9), (B2, 4, 1), (G0, 0, 9), (G2, 4, 1), (B0, 0, 9), (B3, 4, 1), (R1, 0, 5), (G3, 4, 1), (G2, 0, 4),
+        (G1, 0, 5), (B3, 0, 1), (G3, 0, 4), (B1, 0, 5), (B3, 1, 1), (B2, 0, 4), (R2, 0, 5), (B3, 2, 1), (R3, 0, 5),
+        (B3, 3, 1)] },
+    Bc6hMode { value: 18, two_regions: true, transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], fields: &[
+        (R0, 0, 8), (G3, 4, 1), (B2, 4, 1), (G0, 0, 8), (B3, 2, 1), (G2, 4, 1), (B0, 0, 8), (B3, 3, 1), (B3, 4, 1),
+        (R1, 0, 6), (G2, 0, 4), (G1, 0, 5), (B3, 0, 1), (G3, 0, 4), (B1, 0, 5), (B3, 1, 1), (B2, 0, 4), (R2, 0, 6),
+        (R3, 0, 6)] },
+    Bc6hMode { value: 22, two_regions: true, transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], fields: &[
+        (R0, 0, 8), (B3, 0, 1), (B2, 4, 1), (G0, 0, 8), (G2, 5, 1), (G2, 4, 1), (B0, 0, 8), (G3, 5, 1), (B3, 4, 1),
+        (R1, 0, 5), (G3, 4, 1), (G2, 0, 4), (G1, 0, 6), (G3, 0, 4), (B1, 0, 5), (B3, 1, 1), (B2, 0, 4), (R2, 0, 5),
+        (B3, 2, 1), (R3, 0, 5), (B3, 3, 1)] },
+    Bc6hMode { value: 26, two_regions: true, transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], fields: &[
+        (R0, 0, 8), (B3, 1, 1), (B2, 4, 1), (G0, 0, 8), (B2, 5, 1), (G2, 4, 1), (B0, 0, 8), (B3, 5, 1), (B3, 4, 1),
+        (R1, 0, 5), (G3, 4, 1), (G2, 0, 4), (G1, 0, 5), (B3, 0, 1), (G3, 0, 4), (B1, 0, 6), (B2, 0, 4), (R2, 0, 5),
+        (B3, 2, 1), (R3, 0, 5), (B3, 3, 1)] },
+    Bc6hMode { value: 30, two_regions: true, transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], fields: &[
+        (R0, 0, 6), (G3, 4, 1), (B3, 0, 1), (B3, 1, 1), (B2, 4, 1), (G0, 0, 6), (G2, 5, 1), (B2, 5, 1), (B3, 2, 1),
+        (G2, 4, 1), (B0, 0, 6), (G3, 5, 1), (B3, 3, 1), (B3, 5, 1), (B3, 4, 1), (R1, 0, 6), (G2, 0, 4), (G1, 0, 6),
+        (G3, 0, 4), (B1, 0, 6), (B2, 0, 4), (R2, 0, 6), (R3, 0, 6)] },
+    Bc6hMode { value: 3, two_regions: false, transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 10), (G1, 0, 10), (B1, 0, 10)] },
+    Bc6hMode { value: 7, two_regions: false, transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 9), (R0, 10, 1), (G1, 0, 9), (G0, 10, 1), (B1, 0, 9),
+        (B0, 10, 1)] },
+    // the high bits of these two are stored highest first
+    Bc6hMode { value: 11, two_regions: false, transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 8), (R0, 11, 1), (R0, 10, 1), (G1, 0, 8), (G0, 11, 1),
+        (G0, 10, 1), (B1, 0, 8), (B0, 11, 1), (B0, 10, 1)] },
+    Bc6hMode { value: 15, two_regions: false, transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], fields: &[
+        (R0, 0, 10), (G0, 0, 10), (B0, 0, 10), (R1, 0, 4), (R0, 15, 1), (R0, 14, 1), (R0, 13, 1), (R0, 12, 1),
+        (R0, 11, 1), (R0, 10, 1), (G1, 0, 4), (G0, 15, 1), (G0, 14, 1), (G0, 13, 1), (G0, 12, 1), (G0, 11, 1),
+        (G0, 10, 1), (B1, 0, 4), (B0, 15, 1), (B0, 14, 1), (B0, 13, 1), (B0, 12, 1), (B0, 11, 1), (B0, 10, 1)] }
+];
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    (value << (32 - bits)) >> (32 - bits)
+}
+
+/// Scales an endpoint to the full 16 bit (unsigned) or 15 bit plus sign range.
+fn unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xffff
+        } else {
+            ((value << 15) + 0x4000) >> (bits - 1)
+        }
+    } else {
+        if bits >= 16 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let scaled = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7fff
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 { -scaled } else { scaled }
+    }
+}
+
+/// Turns an interpolated value into the bits of a half float.
+fn finish_unquantize(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else if value < 0 {
+        0x8000 | (((-value) * 31) >> 5) as u16
+    } else {
+        ((value * 31) >> 5) as u16
+    }
+}
+
+/// Writes RGB half floats. Blocks with a reserved mode decode to black.
+fn decode_bc6h(block: &[u8], signed: bool, texels: &mut [u8]) {
+    let mut bits = BitReader::new(block);
+    let mut value = bits.read(2);
+    if value > 1 {
+        value |= bits.read(3) << 2;
+    }
+    let mode = match BC6H_MODES.iter().find(|mode| mode.value == value) {
+        Some(mode) => mode,
+        None => {
+            for texel in texels[..96].iter_mut() {
+                *texel = 0;
+            }
+            return;
+        }
+    };
+
+    let mut endpoints = [0i32; 12];
+    for &(channel, lowest, count) in mode.fields {
+        endpoints[channel] |= (bits.read(count) << lowest) as i32;
+    }
+    let partition = if mode.two_regions { bits.read(5) as usize } else { 0 };
+    let count = if mode.two_regions { 4 } else { 2 };
+
+    let precision = mode.endpoint_bits;
+    if signed {
+        for channel in 0..3 {
+            endpoints[channel] = sign_extend(endpoints[channel], precision);
+        }
+    }
+    for endpoint in 1..count {
+        for channel in 0..3 {
+            let base = endpoints[channel];
+            let value = &mut endpoints[endpoint * 3 + channel];
+            if mode.transformed {
+                let delta = sign_extend(*value, mode.delta_bits[channel]);
+                *value = (base + delta) & ((1 << precision) - 1);
+                if signed {
+                    *value = sign_extend(*value, precision);
+                }
+            } else if signed {
+                *value = sign_extend(*value, precision);
+            }
+        }
+    }
+    for value in endpoints[..count * 3].iter_mut() {
+        *value = unquantize(*value, precision, signed);
+    }
+
+    let index_bits = if mode.two_regions { 3 } else { 4 };
+    let subsets = if mode.two_regions { 2 } else { 1 };
+    for texel in 0..16 {
+        let index = bits.read(index_bits - is_anchor(subsets, partition, texel) as u32);
+        let region = subset(subsets, partition, texel);
+        for channel in 0..3 {
+            let (a, b) = (endpoints[region * 6 + channel], endpoints[region * 6 + 3 + channel]);
+            let half = finish_unquantize(interpolate(a, b, index, index_bits), signed);
+            texels[(texel * 3 + channel) * 2..][..2].copy_from_slice(&half.to_le_bytes());
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use cgmath::prelude::*;
+use cgmath::Matrix4;
+use memmap::Mmap;
+
+use crate::model::material::{AlphaMode, Material};
+use crate::model::mesh::{Mesh, Vertex};
+use crate::model::model::{ImportOptions, MeshReport, Model, Node};
+use crate::model::normals::{NormalMode, NormalWeighting};
+use crate::model::texture::TextureSampler;
+
+// Layout of a cache file, all numbers little endian:
+//
+//   magic "LGLMESH\0", version u32, size of Vertex u32, content hash u64
+//   material count u32, then per material:
+//     name, ambient 3 x f32, specular 3 x f32, shininess f32, base color 4 x f32,
+//     metallic f32, roughness f32, emissive 3 x f32, alpha mode u32, alpha cutoff f32,
+//     double sided u32, reflectivity f32, ior f32, texture count u32, then per texture:
+//     type, path, has sampler u32, if 1: wrap s u32, wrap t u32, min filter u32,
+//     mag filter u32, anisotropy f32
+//   mesh count u32, then per mesh:
+//     name, material id i32 (-1 for none), report (normal mode u32, crease angle f32,
+//     weighting u32, defaulted uvs u32, 4 x u64 counts), vertex count u32, index count u32,
+//     padding up to 16 bytes, vertices as laid out in memory, indices
+//
+// Strings are a u32 byte length followed by UTF-8.
+
+const MAGIC: &[u8; 8] = b"LGLMESH\0";
+/// Bump whenever the layout above or the import pipeline changes the output.
+const VERSION: u32 = 5;
+
+/// The cache file that belongs to a model file, next to it.
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut name = source.file_name().unwrap_or_default().to_os_string();
+    name.push(".meshcache");
+    source.with_file_name(name)
+}
+
+/// Hash over the OBJ, the MTL files it references and the import options,
+/// so changing any of them invalidates the cache.
+pub fn content_hash(source: &Path, options: &ImportOptions) -> io::Result<u64> {
+    let obj = fs::read(source)?;
+    let mut hash = fnv1a(FNV_OFFSET, &obj);
+
+    let directory = source.parent().unwrap_or_else(|| Path::new(""));
+    for line in String::from_utf8_lossy(&obj).lines() {
+        let line = line.trim();
+        if line.starts_with("mtllib") {
+            let mtl = directory.join(line["mtllib".len()..].trim());
+            // a missing MTL is not an error for the loader either, it hashes as empty
+            hash = fnv1a(hash, &fs::read(&mtl).unwrap_or_default());
+        }
+    }
+
+    hash = fnv1a(hash, format!("{:?}", options).as_bytes());
+    Ok(hash)
+}
+
+/// Fills `model` from the cache file of `source` if there is one that is newer than the
+/// source files and was built from the same content. Returns whether it did.
+pub fn load(model: &mut Model, source: &Path) -> io::Result<bool> {
+    let cache = cache_path(source);
+    let cache_modified = match fs::metadata(&cache).and_then(|m| m.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return Ok(false)
+    };
+    if fs::metadata(source)?.modified()? > cache_modified {
+        return Ok(false);
+    }
+
+    let file = File::open(&cache)?;
+    let map = unsafe { Mmap::map(&file)? };
+    let mut reader = Reader { data: &map, pos: 0 };
+
+    if reader.bytes(MAGIC.len())? != MAGIC || reader.u32()? != VERSION
+        || reader.u32()? as usize != mem::size_of::<Vertex>() {
+        return Ok(false);
+    }
+    if reader.u64()? != content_hash(source, &model.options)? {
+        return Ok(false);
+    }
+
+    let material_count = reader.u32()?;
+    for _ in 0..material_count {
+        let mut material = Material {
+            name: reader.string()?,
+            ambient: reader.f32s3()?,
+            specular: reader.f32s3()?,
+            shininess: reader.f32()?,
+            base_color: [reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?],
+            metallic: reader.f32()?,
+            roughness: reader.f32()?,
+            emissive: reader.f32s3()?,
+            alpha_mode: match reader.u32()? {
+                1 => AlphaMode::Mask,
+                2 => AlphaMode::Blend,
+                _ => AlphaMode::Opaque
+            },
+            alpha_cutoff: reader.f32()?,
+            double_sided: reader.u32()? != 0,
+            reflectivity: reader.f32()?,
+            ior: reader.f32()?,
+            textures: Vec::new()
+        };
+        let texture_count = reader.u32()?;
+        for _ in 0..texture_count {
+            let type_ = reader.string()?;
+            let path = reader.string()?;
+            let sampler = match reader.u32()? {
+                0 => None,
+                _ => Some(TextureSampler {
+                    wrap_s: reader.u32()?,
+                    wrap_t: reader.u32()?,
+                    min_filter: reader.u32()?,
+                    mag_filter: reader.u32()?,
+                    anisotropy: reader.f32()?
+                })
+            };
+            material.textures.push(model.load_texture_with(&path, &type_, sampler));
+        }
+        model.materials.push(material);
+    }
+
+    let mesh_count = reader.u32()?;
+    for _ in 0..mesh_count {
+        let name = reader.string()?;
+        let material_id = match reader.u32()? as i32 {
+            -1 => None,
+            id => Some(id as usize)
+        };
+        let mode = reader.u32()?;
+        let crease_angle = reader.f32()?;
+        let weighting = match reader.u32()? {
+            0 => NormalWeighting::Uniform,
+            1 => NormalWeighting::Area,
+            2 => NormalWeighting::Angle,
+            _ => NormalWeighting::AreaAngle
+        };
+        let report = MeshReport {
+            name: name.clone(),
+            generated_normals: match mode {
+                1 => Some(NormalMode::Flat),
+                2 => Some(NormalMode::Smooth { crease_angle, weighting }),
+                _ => None
+            },
+            defaulted_tex_coords: reader.u32()? != 0,
+            vertices_before: reader.u64()? as usize,
+            indices_before: reader.u64()? as usize,
+            vertices_after: reader.u64()? as usize,
+            indices_after: reader.u64()? as usize,
+            // the cached data is already optimized if it was asked for, but the metrics are not kept
+            optimization: None
+        };
+        let vertex_count = reader.u32()? as usize;
+        let index_count = reader.u32()? as usize;
+        reader.align(16);
+        let vertex_bytes = reader.bytes(vertex_count * mem::size_of::<Vertex>())?;
+        let index_bytes = reader.bytes(index_count * mem::size_of::<u32>())?;
+
+        let textures = match material_id {
+            Some(material_id) => model.materials.get(material_id).map(|m| m.textures.clone()).unwrap_or_default(),
+            None => Vec::new()
+        };
+        let mut mesh = Mesh::from_bytes(vertex_bytes, index_bytes, textures);
+        mesh.material_id = material_id;
+
+        model.root_nodes.push(model.nodes.len());
+        model.nodes.push(Node {
+            name,
+            transform: Matrix4::identity(),
+            meshes: vec![model.meshes.len()],
+            children: Vec::new()
+        });
+        model.meshes.push(mesh);
+        model.reports.push(report);
+    }
+
+    println!("{}: loaded {} meshes from {}", source.display(), mesh_count, cache.display());
+    Ok(true)
+}
+
+/// Writes what `model` loaded from `source` to its cache file.
+/// Only meaningful for models that map one root node to one mesh, as OBJ files do.
+pub fn save(model: &Model, source: &Path) -> io::Result<()> {
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(MAGIC);
+    put_u32(&mut out, VERSION);
+    put_u32(&mut out, mem::size_of::<Vertex>() as u32);
+    out.extend_from_slice(&content_hash(source, &model.options)?.to_le_bytes());
+
+    put_u32(&mut out, model.materials.len() as u32);
+    for material in &model.materials {
+        put_string(&mut out, &material.name);
+        put_f32s(&mut out, &material.ambient);
+        put_f32s(&mut out, &material.specular);
+        put_f32s(&mut out, &[material.shininess]);
+        put_f32s(&mut out, &material.base_color);
+        put_f32s(&mut out, &[material.metallic, material.roughness]);
+        put_f32s(&mut out, &material.emissive);
+        put_u32(&mut out, match material.alpha_mode {
+            AlphaMode::Opaque => 0,
+            AlphaMode::Mask => 1,
+            AlphaMode::Blend => 2
+        });
+        put_f32s(&mut out, &[material.alpha_cutoff]);
+        put_u32(&mut out, material.double_sided as u32);
+        put_f32s(&mut out, &[material.reflectivity, material.ior]);
+        put_u32(&mut out, material.textures.len() as u32);
+        for texture in &material.textures {
+            put_string(&mut out, &texture.type_);
+            put_string(&mut out, &texture.path);
+            match texture.sampler {
+                Some(sampler) => {
+                    let settings = sampler.settings;
+                    put_u32(&mut out, 1);
+                    for &value in &[settings.wrap_s, settings.wrap_t, settings.min_filter, settings.mag_filter] {
+                        put_u32(&mut out, value);
+                    }
+                    put_f32s(&mut out, &[settings.anisotropy]);
+                },
+                None => put_u32(&mut out, 0)
+            }
+        }
+    }
+
+    put_u32(&mut out, model.meshes.len() as u32);
+    for (mesh, report) in model.meshes.iter().zip(&model.reports) {
+        put_string(&mut out, &report.name);
+        put_u32(&mut out, mesh.material_id.map(|id| id as i32).unwrap_or(-1) as u32);
+        let (mode, crease_angle, weighting) = match report.generated_normals {
+            None => (0, 0.0, NormalWeighting::AreaAngle),
+            Some(NormalMode::Flat) => (1, 0.0, NormalWeighting::AreaAngle),
+            Some(NormalMode::Smooth { crease_angle, weighting }) => (2, crease_angle, weighting)
+        };
+        put_u32(&mut out, mode);
+        put_f32s(&mut out, &[crease_angle]);
+        put_u32(&mut out, match weighting {
+            NormalWeighting::Uniform => 0,
+            NormalWeighting::Area => 1,
+            NormalWeighting::Angle => 2,
+            NormalWeighting::AreaAngle => 3
+        });
+        put_u32(&mut out, report.defaulted_tex_coords as u32);
+        for &count in &[report.vertices_before, report.indices_before, report.vertices_after, report.indices_after] {
+            out.extend_from_slice(&(count as u64).to_le_bytes());
+        }
+        put_u32(&mut out, mesh.vertices.len() as u32);
+        put_u32(&mut out, mesh.indices.len() as u32);
+        while out.len() % 16 != 0 {
+            out.push(0);
+        }
+        unsafe {
+            out.extend_from_slice(slice::from_raw_parts(mesh.vertices.as_ptr() as *const u8,
+                                                        mesh.vertices.len() * mem::size_of::<Vertex>()));
+            out.extend_from_slice(slice::from_raw_parts(mesh.indices.as_ptr() as *const u8,
+                                                        mesh.indices.len() * mem::size_of::<u32>()));
+        }
+    }
+
+    // write next to the target and rename, so a crash never leaves half a cache behind
+    let cache = cache_path(source);
+    let temporary = cache.with_extension("meshcache.tmp");
+    fs::write(&temporary, &out)?;
+    fs::rename(&temporary, &cache)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a, stable across runs and compiler versions unlike the std hasher.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32s(out: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.pos + len > self.data.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "mesh cache is truncated"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.pos = (self.pos + alignment - 1) / alignment * alignment;
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f32s3(&mut self) -> io::Result<[f32; 3]> {
+        Ok([self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
@@ -0,0 +1,124 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use super::texture::{ PixelFormat, TextureLevels };
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x20000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DIMENSION_TEXTURE2D: u32 = 3;
+
+/// Loads a DDS file, with the legacy header or the DX10 one that names a DXGI format.
+/// Only 2D textures are supported, of an array only the first layer is read.
+pub fn load_dds(path: &Path) -> Result<TextureLevels, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    parse_dds(&data)
+}
+
+fn parse_dds(data: &[u8]) -> Result<TextureLevels, String> {
+    if data.len() < 128 || &data[..4] != b"DDS " {
+        return Err("missing 'DDS ' magic".into());
+    }
+    let field = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let (flags, height, width) = (field(8), field(12), field(16));
+    if width == 0 || height == 0 {
+        return Err("empty image".into());
+    }
+    if field(112) & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        return Err("cube maps and volume textures are not supported".into());
+    }
+    let (pixel_flags, four_cc, bit_count) = (field(80), &data[84..88], field(88));
+    let (red_mask, blue_mask) = (field(92), field(100));
+
+    let mut offset: usize = 128;
+    let (format, srgb) = if pixel_flags & DDPF_FOURCC != 0 {
+        match four_cc {
+            b"DX10" => {
+                if data.len() < 148 {
+                    return Err("truncated DX10 header".into());
+                }
+                if field(132) != DIMENSION_TEXTURE2D {
+                    return Err(format!("resource dimension {} is not a 2D texture", field(132)));
+                }
+                offset = 148;
+                dxgi_format(field(128))?
+            },
+            b"DXT1" => (PixelFormat::Bc1, false),
+            b"DXT2" | b"DXT3" => (PixelFormat::Bc2, false),
+            b"DXT4" | b"DXT5" => (PixelFormat::Bc3, false),
+            b"ATI1" | b"BC4U" => (PixelFormat::Bc4, false),
+            b"BC4S" => (PixelFormat::Bc4Signed, false),
+            b"ATI2" | b"BC5U" => (PixelFormat::Bc5, false),
+            b"BC5S" => (PixelFormat::Bc5Signed, false),
+            // D3DFMT values stored in place of a FourCC
+            _ => match field(84) {
+                113 => (PixelFormat::Rgba16f, false),
+                116 => (PixelFormat::Rgba32f, false),
+                _ => return Err(format!("unsupported FourCC '{}'", String::from_utf8_lossy(four_cc)))
+            }
+        }
+    } else if pixel_flags & DDPF_RGB != 0 {
+        match (bit_count, red_mask, blue_mask) {
+            (32, 0xff, 0xff0000) => (PixelFormat::Rgba8, false),
+            (32, 0xff0000, 0xff) => (PixelFormat::Bgra8, false),
+            (24, 0xff, 0xff0000) => (PixelFormat::Rgb8, false),
+            (24, 0xff0000, 0xff) => (PixelFormat::Bgr8, false),
+            _ => return Err(format!("unsupported {} bit RGB layout", bit_count))
+        }
+    } else if pixel_flags & DDPF_LUMINANCE != 0 && bit_count == 8 {
+        (PixelFormat::R8, false)
+    } else {
+        return Err("unsupported pixel format".into());
+    };
+
+    // the count in the header is not always right, and a full chain ends at 1x1
+    let full_chain = 32 - width.max(height).leading_zeros();
+    let level_count = if flags & DDSD_MIPMAPCOUNT != 0 { field(28).max(1).min(full_chain) } else { 1 };
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count {
+        let size = format.level_size(width >> level, height >> level)
+            .ok_or_else(|| format!("level {} is too large", level))?;
+        let end = match offset.checked_add(size) {
+            Some(end) if end <= data.len() => end,
+            _ => return Err(format!("level {} runs past the end of the file", level))
+        };
+        levels.push(data[offset..end].to_vec());
+        offset = end;
+    }
+
+    Ok(TextureLevels { width, height, format, srgb, bottom_up: false, levels })
+}
+
+/// Format and whether it is sRGB, for the DXGI formats there is a `PixelFormat` for.
+/// Typeless formats are read as UNORM.
+fn dxgi_format(format: u32) -> Result<(PixelFormat, bool), String> {
+    Ok(match format {
+        2 => (PixelFormat::Rgba32f, false),
+        10 => (PixelFormat::Rgba16f, false),
+        27 | 28 => (PixelFormat::Rgba8, false),
+        29 => (PixelFormat::Rgba8, true),
+        48 | 49 => (PixelFormat::Rg8, false),
+        60 | 61 => (PixelFormat::R8, false),
+        70 | 71 => (PixelFormat::Bc1, false),
+        72 => (PixelFormat::Bc1, true),
+        73 | 74 => (PixelFormat::Bc2, false),
+        75 => (PixelFormat::Bc2, true),
+        76 | 77 => (PixelFormat::Bc3, false),
+        78 => (PixelFormat::Bc3, true),
+        79 | 80 => (PixelFormat::Bc4, false),
+        81 => (PixelFormat::Bc4Signed, false),
+        82 | 83 => (PixelFormat::Bc5, false),
+        84 => (PixelFormat::Bc5Signed, false),
+        87 | 90 => (PixelFormat::Bgra8, false),
+        91 => (PixelFormat::Bgra8, true),
+        94 | 95 => (PixelFormat::Bc6h, false),
+        96 => (PixelFormat::Bc6hSigned, false),
+        97 | 98 => (PixelFormat::Bc7, false),
+        99 => (PixelFormat::Bc7, true),
+        _ => return Err(format!("unsupported DXGI format {}", format))
+    })
+}
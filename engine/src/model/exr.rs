@@ -0,0 +1,245 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use inflate;
+
+use super::hdr_image::HdrImage;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+// version flags
+const TILED: u32 = 0x200;
+const NON_IMAGE: u32 = 0x800;
+const MULTI_PART: u32 = 0x1000;
+
+// pixel types
+const UINT: u32 = 0;
+const HALF: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Rle,
+    /// zlib, one scanline per chunk.
+    Zips,
+    /// zlib, 16 scanlines per chunk.
+    Zip
+}
+
+struct Channel {
+    name: String,
+    pixel_type: u32
+}
+
+impl Channel {
+    fn sample_size(&self) -> usize {
+        if self.pixel_type == HALF { 2 } else { 4 }
+    }
+}
+
+/// Loads the R, G and B channels, or Y as gray, of a single part scanline OpenEXR file
+/// compressed with RLE, ZIP or ZIPS or not at all.
+pub fn load_exr(path: &Path) -> Result<HdrImage, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    parse_exr(&data)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = match self.position.checked_add(count) {
+            Some(end) if end <= self.data.len() => end,
+            _ => return Err("unexpected end of file".into())
+        };
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// A nul terminated string.
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.data.get(self.position..).ok_or("unexpected end of file")?.iter().position(|&b| b == 0).ok_or("unterminated string")?;
+        let string = String::from_utf8_lossy(self.bytes(length)?).into_owned();
+        self.position += 1;
+        Ok(string)
+    }
+}
+
+fn parse_exr(data: &[u8]) -> Result<HdrImage, String> {
+    if data.len() < 8 || data[..4] != MAGIC {
+        return Err("missing OpenEXR magic".into());
+    }
+    let mut reader = Reader { data, position: 4 };
+    if reader.u32()? & (TILED | NON_IMAGE | MULTI_PART) != 0 {
+        return Err("tiled, deep and multi-part files are not supported".into());
+    }
+
+    let mut channels: Vec<Channel> = Vec::new();
+    let mut compression = Compression::None;
+    let mut window = None;
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _type = reader.string()?;
+        let size = reader.u32()? as usize;
+        let mut value = Reader { data: reader.bytes(size)?, position: 0 };
+        match name.as_str() {
+            "channels" => {
+                loop {
+                    let name = value.string()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let pixel_type = value.u32()?;
+                    value.bytes(4)?;
+                    if value.i32()? != 1 || value.i32()? != 1 {
+                        return Err(format!("channel {} is subsampled", name));
+                    }
+                    channels.push(Channel { name, pixel_type });
+                }
+            },
+            "compression" => {
+                compression = match value.bytes(1)?[0] {
+                    0 => Compression::None,
+                    1 => Compression::Rle,
+                    2 => Compression::Zips,
+                    3 => Compression::Zip,
+                    other => return Err(format!("compression {} is not supported", other))
+                };
+            },
+            "dataWindow" => window = Some([value.i32()?, value.i32()?, value.i32()?, value.i32()?]),
+            _ => {}
+        }
+    }
+
+    let window = window.ok_or("missing dataWindow")?;
+    // the window comes from the file, a broken one could overflow or be inside out
+    let extent = |min: i32, max: i32| max.checked_sub(min).and_then(|size| size.checked_add(1));
+    let (width, height) = match (extent(window[0], window[2]), extent(window[1], window[3])) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => (width as usize, height as usize),
+        _ => return Err(format!("data window {:?} is empty or too large", window))
+    };
+    let find = |name: &str| channels.iter().position(|channel| channel.name == name);
+    let sources = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err("no R, G and B or Y channels".into())
+    };
+    // channels are stored one after the other in each scanline, in the order listed
+    let mut offsets = Vec::with_capacity(channels.len());
+    let mut line_size = 0;
+    for channel in &channels {
+        offsets.push(line_size);
+        line_size = channel.sample_size().checked_mul(width).and_then(|size| size.checked_add(line_size))
+            .ok_or("scanlines are too large")?;
+    }
+
+    let lines_per_chunk = if compression == Compression::Zip { 16 } else { 1 };
+    let chunk_count = (height + lines_per_chunk - 1) / lines_per_chunk;
+    let mut chunk_offsets = Vec::with_capacity(chunk_count);
+    for _ in 0..chunk_count {
+        chunk_offsets.push(reader.u64()? as usize);
+    }
+
+    let pixel_count = width.checked_mul(height).and_then(|count| count.checked_mul(3)).ok_or("image is too large")?;
+    let mut pixels = vec![0.0f32; pixel_count];
+    for offset in chunk_offsets {
+        let mut chunk = Reader { data, position: offset };
+        let y = chunk.i32()?;
+        let first_line = match y.checked_sub(window[1]) {
+            Some(line) if line >= 0 && (line as usize) < height => line as usize,
+            _ => return Err(format!("chunk at line {} is outside the data window", y))
+        };
+        let size = chunk.u32()? as usize;
+        let lines = lines_per_chunk.min(height - first_line);
+        let block_size = line_size.checked_mul(lines).ok_or("chunk is too large")?;
+        let block = decompress(compression, chunk.bytes(size)?, block_size)?;
+        if block.len() < block_size {
+            return Err("chunk is shorter than its scanlines".into());
+        }
+
+        for line in 0..lines {
+            let source = &block[line * line_size..][..line_size];
+            // the file's first row is the top
+            let row = height - 1 - (first_line + line);
+            for x in 0..width {
+                for (component, &channel) in sources.iter().enumerate() {
+                    let size = channels[channel].sample_size();
+                    let sample = &source[offsets[channel] + x * size..][..size];
+                    pixels[(row * width + x) * 3 + component] = match channels[channel].pixel_type {
+                        HALF => half_to_f32(u16::from_le_bytes([sample[0], sample[1]])),
+                        UINT => u32::from_le_bytes(sample.try_into().unwrap()) as f32,
+                        _ => f32::from_le_bytes(sample.try_into().unwrap())
+                    };
+                }
+            }
+        }
+    }
+
+    Ok(HdrImage { width: width as u32, height: height as u32, pixels })
+}
+
+/// Undoes the compression of a chunk that should come out as `expected` bytes.
+fn decompress(compression: Compression, data: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    // chunks that would not get smaller are stored as they are
+    if compression == Compression::None || data.len() == expected {
+        return Ok(data.to_vec());
+    }
+    let mut bytes = match compression {
+        Compression::Rle => {
+            let mut bytes = Vec::with_capacity(expected);
+            let mut i = 0;
+            while i < data.len() {
+                let count = data[i] as i8 as i32;
+                i += 1;
+                if count < 0 {
+                    let end = (i + (-count) as usize).min(data.len());
+                    bytes.extend_from_slice(&data[i..end]);
+                    i = end;
+                } else if i < data.len() {
+                    bytes.extend(std::iter::repeat(data[i]).take(count as usize + 1));
+                    i += 1;
+                }
+            }
+            bytes
+        },
+        _ => inflate::inflate_bytes_zlib(data)?
+    };
+
+    // both store differences of consecutive bytes, of the even bytes followed by the odd ones
+    for i in 1..bytes.len() {
+        bytes[i] = (bytes[i - 1] as i32 + bytes[i] as i32 - 128) as u8;
+    }
+    let half = (bytes.len() + 1) / 2;
+    let interleaved = (0..bytes.len()).map(|i| if i % 2 == 0 { bytes[i / 2] } else { bytes[half + i / 2] }).collect();
+    Ok(interleaved)
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let exponent = (half >> 10) as u32 & 0x1f;
+    let mantissa = half as u32 & 0x3ff;
+    let magnitude = match exponent {
+        0 => mantissa as f32 / (1 << 24) as f32,
+        31 => if mantissa == 0 { std::f32::INFINITY } else { std::f32::NAN },
+        _ => f32::from_bits(((exponent + 112) << 23) | (mantissa << 13))
+    };
+    if half & 0x8000 != 0 { -magnitude } else { magnitude }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use cgmath::{vec2, vec3, vec4, Matrix4};
+use gl;
+use gltf;
+
+use crate::model::material::{AlphaMode, Material};
+use crate::model::mesh::{Mesh, Vertex, Texture};
+use crate::model::model::{optimize_mesh, MeshReport, Model, Node};
+use crate::model::texture::{create_texture, gpu_memory_size, TextureOptions, TextureSampler};
+use crate::model::normals;
+
+/// Loads a .gltf (with external or embedded buffers) or .glb file into `model`.
+/// Every glTF mesh primitive becomes one `Mesh`, nodes keep their names and local transforms.
+pub fn load_gltf(model: &mut Model, path: &Path) {
+    let (document, buffers, images) = gltf::import(path)
+        .unwrap_or_else(|err| panic!("Failed to load glTF {}: {}", path.display(), err));
+
+    // textures are created on first use, several materials may share one,
+    // keyed by texture index and whether it was uploaded as sRGB
+    let mut textures: HashMap<(usize, bool), u32> = HashMap::new();
+    for material in document.materials() {
+        let material = process_material(model, &material, &images, &mut textures);
+        model.materials.push(material);
+    }
+
+    // meshes: one engine mesh per primitive
+    let mut mesh_primitives: Vec<Vec<usize>> = Vec::new();
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for (p, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                println!("Skipping non-triangle primitive {} of mesh {:?}", p, mesh.name());
+                continue;
+            }
+            let name = format!("{}.{}", mesh.name().unwrap_or("mesh"), p);
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue
+            };
+            let file_normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+            let file_tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(|t| t.collect());
+            let tex_coords: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|t| t.into_f32().collect());
+            let colors: Option<Vec<[f32; 4]>> = reader.read_colors(0).map(|c| c.into_rgba_f32().collect());
+            let mut indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect()
+            };
+
+            let mut report = MeshReport {
+                name,
+                defaulted_tex_coords: tex_coords.is_none(),
+                vertices_before: positions.len(),
+                indices_before: indices.len(),
+                ..MeshReport::default()
+            };
+            let mut vertices: Vec<Vertex> = Vec::with_capacity(positions.len());
+            for (i, p) in positions.iter().enumerate() {
+                vertices.push(Vertex {
+                    position: vec3(p[0], p[1], p[2]),
+                    normal: match file_normals {
+                        Some(ref n) => vec3(n[i][0], n[i][1], n[i][2]),
+                        None => vec3(0.0, 0.0, 0.0)
+                    },
+                    tex_coords: match tex_coords {
+                        Some(ref t) => vec2(t[i][0], t[i][1]),
+                        None => vec2(0.0, 0.0)
+                    },
+                    color: match colors {
+                        Some(ref c) => vec4(c[i][0], c[i][1], c[i][2], c[i][3]),
+                        None => vec4(1.0, 1.0, 1.0, 1.0)
+                    },
+                    tangent: match file_tangents {
+                        Some(ref t) => vec4(t[i][0], t[i][1], t[i][2], t[i][3]),
+                        None => vec4(1.0, 0.0, 0.0, 1.0)
+                    }
+                });
+            }
+            if file_normals.is_none() {
+                normals::generate_normals(&mut vertices, &mut indices, model.options.normals);
+                report.generated_normals = Some(model.options.normals);
+                println!("{}: no normals in file, generated {:?} normals", report.name, model.options.normals);
+            }
+            // tangents from the file only fit the normals from the file
+            if file_tangents.is_none() || file_normals.is_none() {
+                normals::generate_tangents(&mut vertices, &indices);
+            }
+            report.vertices_after = vertices.len();
+            report.indices_after = indices.len();
+            if model.options.optimize {
+                report.optimization = Some(optimize_mesh(&mut vertices, &mut indices, &report.name));
+            }
+            model.reports.push(report);
+
+            let material_id = primitive.material().index();
+            let textures = match material_id {
+                Some(material_id) => model.materials[material_id].textures.clone(),
+                None => Vec::new()
+            };
+            let mut engine_mesh = Mesh::new(vertices, indices, textures);
+            engine_mesh.material_id = material_id;
+            primitives.push(model.meshes.len());
+            model.meshes.push(engine_mesh);
+        }
+        mesh_primitives.push(primitives);
+    }
+
+    // nodes keep the glTF order so child indices can be used as they are
+    for node in document.nodes() {
+        model.nodes.push(Node {
+            name: node.name().map(String::from).unwrap_or_else(|| format!("node{}", node.index())),
+            transform: Matrix4::from(node.transform().matrix()),
+            meshes: node.mesh().map(|mesh| mesh_primitives[mesh.index()].clone()).unwrap_or_default(),
+            children: node.children().map(|child| child.index()).collect()
+        });
+    }
+
+    let scene = document.default_scene().or_else(|| document.scenes().next());
+    match scene {
+        Some(scene) => model.root_nodes.extend(scene.nodes().map(|node| node.index())),
+        None => {
+            // no scene, every node that is nobody's child is a root
+            let mut is_child = vec![false; model.nodes.len()];
+            for node in &model.nodes {
+                for &child in &node.children {
+                    is_child[child] = true;
+                }
+            }
+            model.root_nodes.extend((0..model.nodes.len()).filter(|&i| !is_child[i]));
+        }
+    }
+}
+
+fn process_material(model: &mut Model, material: &gltf::Material, images: &[gltf::image::Data],
+                    textures: &mut HashMap<(usize, bool), u32>) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let mut result = Material {
+        name: material.name().unwrap_or("").into(),
+        base_color: pbr.base_color_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emissive: material.emissive_factor(),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend
+        },
+        alpha_cutoff: material.alpha_cutoff(),
+        double_sided: material.double_sided(),
+        ..Material::default()
+    };
+
+    let maps = [
+        (pbr.base_color_texture().map(|info| info.texture()), "texture_diffuse"),
+        (pbr.metallic_roughness_texture().map(|info| info.texture()), "texture_metallic_roughness"),
+        (material.normal_texture().map(|info| info.texture()), "texture_normal"),
+        (material.occlusion_texture().map(|info| info.texture()), "texture_occlusion"),
+        (material.emissive_texture().map(|info| info.texture()), "texture_emissive")
+    ];
+    for (texture, type_) in maps.iter() {
+        if let Some(texture) = texture {
+            let texture = load_texture(model, texture, type_, images, textures);
+            result.textures.push(texture);
+        }
+    }
+    result
+}
+
+fn load_texture(model: &mut Model, texture: &gltf::Texture, type_: &str, images: &[gltf::image::Data],
+                textures: &mut HashMap<(usize, bool), u32>) -> Texture {
+    let source = texture.source();
+    let path = match source.source() {
+        gltf::image::Source::Uri { uri, .. } => uri.to_string(),
+        gltf::image::Source::View { .. } => format!("#image{}", source.index())
+    };
+
+    // a texture used both as color and as data is uploaded twice
+    let options = TextureOptions::for_type(type_);
+    let key = (texture.index(), options.srgb);
+    let loaded = textures.get(&key).cloned();
+    let id = match loaded {
+        Some(id) => id,
+        None => {
+            let sampler = texture.sampler();
+            let defaults = TextureSampler::default();
+            let sampler = TextureSampler {
+                wrap_s: sampler.wrap_s().as_gl_enum(),
+                wrap_t: sampler.wrap_t().as_gl_enum(),
+                min_filter: sampler.min_filter().map(|f| f.as_gl_enum()).unwrap_or(defaults.min_filter),
+                mag_filter: sampler.mag_filter().map(|f| f.as_gl_enum()).unwrap_or(defaults.mag_filter),
+                ..defaults
+            };
+            let options = TextureOptions { mipmaps: sampler.uses_mipmaps(), sampler, ..options };
+            let id = unsafe { upload_image(&images[source.index()], &options) };
+            textures.insert(key, id);
+            id
+        }
+    };
+    let memory_size = match loaded {
+        Some(_) => model.textures_loaded.iter().find(|texture| texture.id == id).map_or(0, |texture| texture.memory_size),
+        None => unsafe { gpu_memory_size(id) }
+    };
+
+    let texture = Texture {
+        id,
+        type_: type_.into(),
+        path,
+        sampler: None,
+        memory_size
+    };
+    if loaded.is_none() {
+        model.textures_loaded.push(texture.clone());
+    }
+    texture
+}
+
+unsafe fn upload_image(image: &gltf::image::Data, options: &TextureOptions) -> u32 {
+    use gltf::image::Format;
+
+    // glTF puts uv (0, 0) at the first row of the image, which is where GL
+    // puts t = 0 as well when the image is uploaded unflipped.
+    let (format, type_) = match image.format {
+        Format::R8 => (gl::RED, gl::UNSIGNED_BYTE),
+        Format::R8G8 => (gl::RG, gl::UNSIGNED_BYTE),
+        Format::R8G8B8 => (gl::RGB, gl::UNSIGNED_BYTE),
+        Format::R8G8B8A8 => (gl::RGBA, gl::UNSIGNED_BYTE),
+        Format::B8G8R8 => (gl::BGR, gl::UNSIGNED_BYTE),
+        Format::B8G8R8A8 => (gl::BGRA, gl::UNSIGNED_BYTE),
+        Format::R16 => (gl::RED, gl::UNSIGNED_SHORT),
+        Format::R16G16 => (gl::RG, gl::UNSIGNED_SHORT),
+        Format::R16G16B16 => (gl::RGB, gl::UNSIGNED_SHORT),
+        Format::R16G16B16A16 => (gl::RGBA, gl::UNSIGNED_SHORT)
+    };
+    create_texture(image.width, image.height, format, type_, &image.pixels, options)
+}
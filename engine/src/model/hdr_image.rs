@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::mem;
+use std::path::Path;
+use std::slice;
+
+use gl;
+use image::hdr::HDRDecoder;
+
+use super::exr::load_exr;
+use super::texture::{ create_texture, TextureOptions };
+
+/// Linear RGB floats, first row at the bottom the way GL expects it.
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Three floats per pixel.
+    pub pixels: Vec<f32>
+}
+
+impl HdrImage {
+    /// Loads a Radiance `.hdr` or an OpenEXR `.exr` file, by extension.
+    pub fn open(path: &Path) -> Result<HdrImage, String> {
+        match extension(path).as_ref().map(String::as_str) {
+            Some("hdr") => load_radiance(path),
+            Some("exr") => load_exr(path),
+            _ => Err(format!("{} is not a .hdr or .exr file", path.display()))
+        }
+    }
+
+    /// Whether `path` names a file `open` reads.
+    pub fn is_hdr_path(path: &Path) -> bool {
+        match extension(path).as_ref().map(String::as_str) {
+            Some("hdr") | Some("exr") => true,
+            _ => false
+        }
+    }
+
+    /// Uploads into a new RGB16F texture, RGB32F without `options.half_float`.
+    pub unsafe fn create_texture(&self, options: &TextureOptions) -> u32 {
+        let bytes = slice::from_raw_parts(self.pixels.as_ptr() as *const u8, self.pixels.len() * mem::size_of::<f32>());
+        create_texture(self.width, self.height, gl::RGB, gl::FLOAT, bytes, options)
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase())
+}
+
+/// Radiance RGBE through `image`, which decodes the rows top first.
+fn load_radiance(path: &Path) -> Result<HdrImage, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let decoder = HDRDecoder::new(BufReader::new(file)).map_err(|err| err.to_string())?;
+    let metadata = decoder.metadata();
+    let (width, height) = (metadata.width, metadata.height);
+    let rows = decoder.read_image_hdr().map_err(|err| err.to_string())?;
+
+    let mut pixels = Vec::with_capacity(rows.len() * 3);
+    for row in rows.chunks(width.max(1) as usize).rev() {
+        for pixel in row {
+            pixels.extend_from_slice(&pixel.data);
+        }
+    }
+    Ok(HdrImage { width, height, pixels })
+}
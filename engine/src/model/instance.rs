@@ -0,0 +1,77 @@
+use std::mem;
+use std::os::raw::c_void;
+
+use cgmath::prelude::*;
+use cgmath::{ Matrix4, Vector4 };
+use gl;
+
+/// First of the four attribute locations the instance model matrix takes, one per column.
+pub const INSTANCE_MODEL_LOCATION: u32 = 5;
+pub const INSTANCE_COLOR_LOCATION: u32 = 9;
+
+/// Per instance data for `Mesh::draw_instanced`.
+/// Shaders read it as `layout (location = 5) in mat4` and `layout (location = 9) in vec4`,
+/// multiplied onto the `model` uniform and the vertex color.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Instance {
+    pub model: Matrix4<f32>,
+    pub color: Vector4<f32>
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Instance {
+            model: Matrix4::identity(),
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0)
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(model: Matrix4<f32>) -> Instance {
+        Instance {
+            model,
+            ..Instance::default()
+        }
+    }
+}
+
+/// Points the instance attributes of the bound VAO at `buffer`, advancing once per instance.
+/// The arrays stay disabled until an instanced draw turns them on.
+pub(crate) unsafe fn setup_instance_attributes(buffer: u32) {
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+    let stride = mem::size_of::<Instance>() as i32;
+    let column_size = mem::size_of::<Vector4<f32>>();
+    for column in 0..4 {
+        let location = INSTANCE_MODEL_LOCATION + column as u32;
+        gl::VertexAttribPointer(location, 4, gl::FLOAT, gl::FALSE, stride, (column * column_size) as *const c_void);
+        gl::VertexAttribDivisor(location, 1);
+    }
+    gl::VertexAttribPointer(INSTANCE_COLOR_LOCATION, 4, gl::FLOAT, gl::FALSE, stride,
+                            mem::size_of::<Matrix4<f32>>() as *const c_void);
+    gl::VertexAttribDivisor(INSTANCE_COLOR_LOCATION, 1);
+}
+
+/// Turns the instance arrays of the bound VAO on or off.
+pub(crate) unsafe fn enable_instance_arrays(enabled: bool) {
+    for location in INSTANCE_MODEL_LOCATION..=INSTANCE_COLOR_LOCATION {
+        if enabled {
+            gl::EnableVertexAttribArray(location);
+        } else {
+            gl::DisableVertexAttribArray(location);
+        }
+    }
+}
+
+/// Sets the values disabled instance attributes read to an identity matrix and white,
+/// so the same shaders work for plain draws. These are context state, not VAO state.
+pub(crate) unsafe fn reset_instance_attributes() {
+    let identity = Instance::default();
+    for column in 0..4 {
+        let c = identity.model[column];
+        gl::VertexAttrib4f(INSTANCE_MODEL_LOCATION + column as u32, c.x, c.y, c.z, c.w);
+    }
+    let c = identity.color;
+    gl::VertexAttrib4f(INSTANCE_COLOR_LOCATION, c.x, c.y, c.z, c.w);
+}
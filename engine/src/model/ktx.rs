@@ -0,0 +1,237 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use gl;
+
+use super::texture::{ PixelFormat, TextureLevels };
+
+const KTX1_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'1', b'1', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+
+// S3TC formats, from EXT_texture_compression_s3tc and EXT_texture_sRGB
+const COMPRESSED_RGB_S3TC_DXT1: u32 = 0x83F0;
+const COMPRESSED_RGBA_S3TC_DXT1: u32 = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT3: u32 = 0x83F2;
+const COMPRESSED_RGBA_S3TC_DXT5: u32 = 0x83F3;
+const COMPRESSED_SRGB_S3TC_DXT1: u32 = 0x8C4C;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: u32 = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: u32 = 0x8C4E;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: u32 = 0x8C4F;
+
+/// Loads a KTX 1 or KTX 2 file. Only 2D textures are supported, of an array only the
+/// first layer is read. KTX 2 files have to be without supercompression, which
+/// rules out Basis Universal.
+pub fn load_ktx(path: &Path) -> Result<TextureLevels, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    if data.starts_with(&KTX1_IDENTIFIER) {
+        parse_ktx1(&data)
+    } else if data.starts_with(&KTX2_IDENTIFIER) {
+        parse_ktx2(&data)
+    } else {
+        Err("missing KTX identifier".into())
+    }
+}
+
+/// KTX 1 names the GL format directly, in either byte order.
+fn parse_ktx1(data: &[u8]) -> Result<TextureLevels, String> {
+    if data.len() < 64 {
+        return Err("truncated header".into());
+    }
+    let swap = match u32::from_le_bytes(data[12..16].try_into().unwrap()) {
+        0x04030201 => false,
+        0x01020304 => true,
+        _ => return Err("invalid endianness field".into())
+    };
+    let field = |offset: usize| {
+        let value = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        if swap { value.swap_bytes() } else { value }
+    };
+    let (gl_type, type_size, gl_format, internal_format) = (field(16), field(20), field(24), field(28));
+    let (width, height) = (field(36), field(40).max(1));
+    if width == 0 || field(44) > 1 || field(52) > 1 {
+        return Err("only 2D textures are supported".into());
+    }
+    let (format, srgb) = ktx1_format(gl_type, gl_format, internal_format)?;
+
+    let key_values_end = match (field(60) as usize).checked_add(64) {
+        Some(end) if end <= data.len() => end,
+        _ => return Err("key/value data runs past the end of the file".into())
+    };
+    let bottom_up = is_bottom_up(&data[64..key_values_end], swap);
+
+    let full_chain = 32 - width.max(height).leading_zeros();
+    let level_count = field(56).max(1).min(full_chain);
+    let mut levels = Vec::with_capacity(level_count as usize);
+    let mut offset = key_values_end;
+    for level in 0..level_count {
+        let past_end = || format!("level {} runs past the end of the file", level);
+        if offset.checked_add(4).map_or(true, |end| end > data.len()) {
+            return Err(past_end());
+        }
+        let image_size = field(offset) as usize;
+        offset += 4;
+        let end = match offset.checked_add(image_size) {
+            Some(end) if end <= data.len() => end,
+            _ => return Err(past_end())
+        };
+        let (level_width, level_height) = ((width >> level).max(1), (height >> level).max(1));
+        let mut texels = data[offset..end].to_vec();
+
+        if format.block_size().is_none() {
+            // rows of plain texels are padded to 4 bytes
+            let row = (level_width as usize).checked_mul(format.pixel_size()).ok_or_else(past_end)?;
+            let padded = (row + 3) / 4 * 4;
+            if padded != row {
+                texels = texels.chunks(padded).flat_map(|r| r[..row.min(r.len())].iter().cloned()).collect();
+            }
+            if swap && type_size > 1 {
+                for value in texels.chunks_mut(type_size as usize) {
+                    value.reverse();
+                }
+            }
+        }
+        let level_size = format.level_size(level_width, level_height)
+            .ok_or_else(|| format!("level {} is too large", level))?;
+        if texels.len() < level_size {
+            return Err(format!("level {} holds {} bytes, {}x{} needs {}", level, texels.len(), level_width, level_height, level_size));
+        }
+        texels.truncate(level_size);
+        levels.push(texels);
+        // the padding after the last level may be missing, `end` is within the data
+        offset = end + (4 - image_size % 4) % 4;
+    }
+
+    Ok(TextureLevels { width, height, format, srgb, bottom_up, levels })
+}
+
+fn ktx1_format(gl_type: u32, gl_format: u32, internal_format: u32) -> Result<(PixelFormat, bool), String> {
+    if gl_type == 0 {
+        return Ok(match internal_format {
+            COMPRESSED_RGB_S3TC_DXT1 | COMPRESSED_RGBA_S3TC_DXT1 => (PixelFormat::Bc1, false),
+            COMPRESSED_SRGB_S3TC_DXT1 | COMPRESSED_SRGB_ALPHA_S3TC_DXT1 => (PixelFormat::Bc1, true),
+            COMPRESSED_RGBA_S3TC_DXT3 => (PixelFormat::Bc2, false),
+            COMPRESSED_SRGB_ALPHA_S3TC_DXT3 => (PixelFormat::Bc2, true),
+            COMPRESSED_RGBA_S3TC_DXT5 => (PixelFormat::Bc3, false),
+            COMPRESSED_SRGB_ALPHA_S3TC_DXT5 => (PixelFormat::Bc3, true),
+            gl::COMPRESSED_RED_RGTC1 => (PixelFormat::Bc4, false),
+            gl::COMPRESSED_SIGNED_RED_RGTC1 => (PixelFormat::Bc4Signed, false),
+            gl::COMPRESSED_RG_RGTC2 => (PixelFormat::Bc5, false),
+            gl::COMPRESSED_SIGNED_RG_RGTC2 => (PixelFormat::Bc5Signed, false),
+            gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT => (PixelFormat::Bc6h, false),
+            gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT => (PixelFormat::Bc6hSigned, false),
+            gl::COMPRESSED_RGBA_BPTC_UNORM => (PixelFormat::Bc7, false),
+            gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM => (PixelFormat::Bc7, true),
+            _ => return Err(format!("unsupported compressed format 0x{:X}", internal_format))
+        });
+    }
+    let srgb = internal_format == gl::SRGB8 || internal_format == gl::SRGB8_ALPHA8;
+    Ok(match (gl_type, gl_format) {
+        (gl::UNSIGNED_BYTE, gl::RED) => (PixelFormat::R8, false),
+        (gl::UNSIGNED_BYTE, gl::RG) => (PixelFormat::Rg8, false),
+        (gl::UNSIGNED_BYTE, gl::RGB) => (PixelFormat::Rgb8, srgb),
+        (gl::UNSIGNED_BYTE, gl::BGR) => (PixelFormat::Bgr8, srgb),
+        (gl::UNSIGNED_BYTE, gl::RGBA) => (PixelFormat::Rgba8, srgb),
+        (gl::UNSIGNED_BYTE, gl::BGRA) => (PixelFormat::Bgra8, srgb),
+        (gl::HALF_FLOAT, gl::RGBA) => (PixelFormat::Rgba16f, false),
+        (gl::FLOAT, gl::RGBA) => (PixelFormat::Rgba32f, false),
+        _ => return Err(format!("unsupported format 0x{:X} with type 0x{:X}", gl_format, gl_type))
+    })
+}
+
+/// KTX 2 names a Vulkan format and is always little endian.
+fn parse_ktx2(data: &[u8]) -> Result<TextureLevels, String> {
+    if data.len() < 80 {
+        return Err("truncated header".into());
+    }
+    let field = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let field64 = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap()) as usize;
+    let (vk_format, width, height) = (field(12), field(20), field(24).max(1));
+    if width == 0 || field(28) > 1 || field(36) > 1 {
+        return Err("only 2D textures are supported".into());
+    }
+    match field(44) {
+        0 => {},
+        scheme => return Err(format!("supercompression scheme {} is not supported", scheme))
+    }
+    let (format, srgb) = vk_format_of(vk_format)?;
+
+    let (key_values, key_values_length) = (field(56) as usize, field(60) as usize);
+    let key_values_end = match key_values.checked_add(key_values_length) {
+        Some(end) if end <= data.len() => end,
+        _ => return Err("key/value data runs past the end of the file".into())
+    };
+    let bottom_up = is_bottom_up(&data[key_values..key_values_end], false);
+
+    let full_chain = 32 - width.max(height).leading_zeros();
+    let level_count = field(40).max(1).min(full_chain);
+    if 80 + level_count as usize * 24 > data.len() {
+        return Err("truncated level index".into());
+    }
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count {
+        let (offset, length) = (field64(80 + level as usize * 24), field64(88 + level as usize * 24));
+        let size = format.level_size(width >> level, height >> level)
+            .ok_or_else(|| format!("level {} is too large", level))?;
+        let end = match offset.checked_add(size) {
+            Some(end) if length >= size && end <= data.len() => end,
+            _ => return Err(format!("level {} runs past the end of the file", level))
+        };
+        levels.push(data[offset..end].to_vec());
+    }
+
+    Ok(TextureLevels { width, height, format, srgb, bottom_up, levels })
+}
+
+fn vk_format_of(vk_format: u32) -> Result<(PixelFormat, bool), String> {
+    Ok(match vk_format {
+        0 => return Err("no format given, Basis Universal is not supported".into()),
+        9 => (PixelFormat::R8, false),
+        16 => (PixelFormat::Rg8, false),
+        23 => (PixelFormat::Rgb8, false),
+        29 => (PixelFormat::Rgb8, true),
+        30 => (PixelFormat::Bgr8, false),
+        37 => (PixelFormat::Rgba8, false),
+        43 => (PixelFormat::Rgba8, true),
+        44 => (PixelFormat::Bgra8, false),
+        50 => (PixelFormat::Bgra8, true),
+        97 => (PixelFormat::Rgba16f, false),
+        109 => (PixelFormat::Rgba32f, false),
+        131 | 133 => (PixelFormat::Bc1, false),
+        132 | 134 => (PixelFormat::Bc1, true),
+        135 => (PixelFormat::Bc2, false),
+        136 => (PixelFormat::Bc2, true),
+        137 => (PixelFormat::Bc3, false),
+        138 => (PixelFormat::Bc3, true),
+        139 => (PixelFormat::Bc4, false),
+        140 => (PixelFormat::Bc4Signed, false),
+        141 => (PixelFormat::Bc5, false),
+        142 => (PixelFormat::Bc5Signed, false),
+        143 => (PixelFormat::Bc6h, false),
+        144 => (PixelFormat::Bc6hSigned, false),
+        145 => (PixelFormat::Bc7, false),
+        146 => (PixelFormat::Bc7, true),
+        _ => return Err(format!("unsupported Vulkan format {}", vk_format))
+    })
+}
+
+/// Looks for the `KTXorientation` key, "S=r,T=u" in KTX 1 and "ru" in KTX 2 put the
+/// first row at the bottom. Without it the first row is the top.
+fn is_bottom_up(key_values: &[u8], swap: bool) -> bool {
+    let mut offset = 0;
+    while offset + 4 <= key_values.len() {
+        let length = u32::from_le_bytes(key_values[offset..offset + 4].try_into().unwrap());
+        let length = if swap { length.swap_bytes() } else { length } as usize;
+        // `end` is within the data, so stepping past it and its padding cannot overflow
+        let end = (offset + 4).saturating_add(length).min(key_values.len());
+        let entry = &key_values[offset + 4..end];
+        let mut parts = entry.splitn(2, |&b| b == 0);
+        if let (Some(b"KTXorientation"), Some(value)) = (parts.next(), parts.next()) {
+            let value = String::from_utf8_lossy(value);
+            let value = value.trim_end_matches('\0');
+            return value.contains("T=u") || value.as_bytes().get(1) == Some(&b'u');
+        }
+        offset = end + (4 - length % 4) % 4;
+    }
+    false
+}
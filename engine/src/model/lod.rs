@@ -0,0 +1,140 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector3, Vector4};
+
+use crate::model::model::Model;
+use crate::model::optimize;
+use crate::model::simplify;
+
+/// How levels of detail are built on load and picked when drawing.
+#[derive(Clone, Copy, Debug)]
+pub struct LodSettings {
+    /// Number of levels after the full mesh, 0 turns LODs off.
+    pub levels: usize,
+    /// Each level aims for this fraction of the triangles of the level before.
+    pub reduction: f32,
+    /// Simplification stops at this error, relative to the mesh size, even if the
+    /// triangle count was not reached.
+    pub max_error: f32,
+    /// Projected height in pixels below which level 1 is used. Every further level
+    /// kicks in at half the size of the one before.
+    pub screen_size: f32,
+    /// Fraction of a threshold the size has to move past it before the level changes,
+    /// so objects sitting near a threshold do not flicker between levels.
+    pub hysteresis: f32
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings {
+            levels: 0,
+            reduction: 0.5,
+            max_error: 0.02,
+            screen_size: 400.0,
+            hysteresis: 0.1
+        }
+    }
+}
+
+impl LodSettings {
+    fn threshold(&self, level: usize) -> f32 {
+        self.screen_size / (1 << level) as f32
+    }
+
+    /// Next level given the current one and the projected size in pixels.
+    pub fn select(&self, current: usize, screen_size: f32, available: usize) -> usize {
+        let mut level = current.min(available);
+        while level < available && screen_size < self.threshold(level) * (1.0 - self.hysteresis) {
+            level += 1;
+        }
+        while level > 0 && screen_size > self.threshold(level - 1) * (1.0 + self.hysteresis) {
+            level -= 1;
+        }
+        level
+    }
+}
+
+impl Model {
+    /// Simplifies every mesh into `options.lod.levels` coarser levels.
+    pub(crate) fn build_lods(&mut self) {
+        let settings = self.options.lod;
+        if settings.levels == 0 {
+            return;
+        }
+        for mesh in &mut self.meshes {
+            let mut lods: Vec<(Vec<u32>, f32)> = Vec::with_capacity(settings.levels);
+            let mut previous = mesh.indices.clone();
+            for _ in 0..settings.levels {
+                let target = ((previous.len() / 3) as f32 * settings.reduction) as usize * 3;
+                let (mut indices, error) = simplify::simplify(&mesh.vertices, &previous, target, settings.max_error);
+                if indices.len() >= previous.len() {
+                    // nothing left to simplify within the error bound
+                    break;
+                }
+                optimize::optimize_vertex_cache(&mut indices, mesh.vertices.len());
+                previous = indices.clone();
+                lods.push((indices, error));
+            }
+            let counts: Vec<String> = lods.iter().map(|(indices, _)| (indices.len() / 3).to_string()).collect();
+            println!("LODs: {} triangles -> {}", mesh.indices.len() / 3, counts.join(" -> "));
+            mesh.set_lods(lods);
+        }
+    }
+
+    /// Bounding sphere of all nodes, in model space.
+    pub(crate) fn compute_bounds(&mut self) {
+        let mut points: Vec<Vector3<f32>> = Vec::new();
+        let mut stack: Vec<(usize, Matrix4<f32>)> = self.root_nodes.iter()
+            .map(|&root| (root, Matrix4::identity()))
+            .collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let transform = parent * node.transform;
+            for &mesh in &node.meshes {
+                for vertex in &self.meshes[mesh].vertices {
+                    let p = vertex.position;
+                    points.push((transform * Vector4::new(p.x, p.y, p.z, 1.0)).truncate());
+                }
+            }
+            stack.extend(node.children.iter().map(|&child| (child, transform)));
+        }
+        if points.is_empty() {
+            return;
+        }
+
+        let (mut min, mut max) = (points[0], points[0]);
+        for p in &points {
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        let center = (min + max) * 0.5;
+        let radius = points.iter().map(|p| (p - center).magnitude()).fold(0.0, f32::max);
+        self.bounding_sphere = (center, radius);
+    }
+
+    /// Picks the level of detail to draw from how tall the model's bounding sphere is on screen.
+    pub fn update_lod(&mut self, model_view: &Matrix4<f32>, projection: &Matrix4<f32>, viewport_height: f32) {
+        let available = self.meshes.iter().map(|mesh| mesh.lods.len()).max().unwrap_or(0);
+        if available == 0 {
+            self.current_lod = 0;
+            return;
+        }
+
+        let (center, radius) = self.bounding_sphere;
+        let view_center = model_view * Vector4::new(center.x, center.y, center.z, 1.0);
+        // largest axis scale of the transform, so scaled models project correctly
+        let scale = model_view.x.truncate().magnitude()
+            .max(model_view.y.truncate().magnitude())
+            .max(model_view.z.truncate().magnitude());
+        let radius = radius * scale;
+        let distance = -view_center.z;
+
+        let screen_size = if distance <= radius {
+            // camera inside the sphere
+            viewport_height
+        } else {
+            // projection[1][1] is cot(fovy / 2)
+            radius / distance * projection.y.y * viewport_height
+        };
+        self.current_lod = self.options.lod.select(self.current_lod, screen_size, available);
+    }
+}
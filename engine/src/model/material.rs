@@ -0,0 +1,66 @@
+use super::mesh::Texture;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend
+}
+
+/// Surface description shared by the meshes that use it.
+/// OBJ/MTL files fill the phong part, glTF files the metallic-roughness part.
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+
+    // phong, from MTL
+    pub ambient: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+
+    // metallic-roughness, from glTF. base_color doubles as the MTL diffuse color and dissolve.
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+
+    // environment, used when the scene has a skybox
+    /// How much of the mirrored environment shows, 0 to 1.
+    pub reflectivity: f32,
+    /// Index of refraction, MTL `Ni`. Above 1, transparent surfaces refract the environment.
+    pub ior: f32,
+
+    /// Same naming as `Mesh::textures`, e.g. "texture_diffuse", "texture_normal".
+    pub textures: Vec<Texture>
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::new(),
+            ambient: [0.0; 3],
+            specular: [0.0; 3],
+            shininess: 0.0,
+            base_color: [1.0; 4],
+            metallic: 1.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            reflectivity: 0.0,
+            ior: 1.0,
+            textures: Vec::new()
+        }
+    }
+}
+
+impl Material {
+    pub fn texture(&self, type_: &str) -> Option<&Texture> {
+        self.textures.iter().find(|tex| tex.type_ == type_)
+    }
+}
@@ -2,14 +2,19 @@
 use gl;
 use gl::types::*;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::os::raw::c_void;
 use std::ptr;
 use std::mem;
 use cgmath::prelude::Zero;
-use cgmath::{ Vector3, Vector2 };
+use cgmath::{ Vector4, Vector3, Vector2 };
 use std::ffi::{ CString, CStr };
 
+use super::instance::{ self, Instance };
 use super::shader::Shader;
+use super::texture::Sampler;
+use crate::render::GlState;
 
 // REFACTOR PLEASE
 macro_rules! offset_of {
@@ -21,18 +26,36 @@ macro_rules! offset_of {
 
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct Vertex {
     pub position: Vector3<f32>,
     pub normal: Vector3<f32>,
-    pub tex_coords: Vector2<f32>
+    pub tex_coords: Vector2<f32>,
+    pub color: Vector4<f32>,
+    /// Direction of increasing u, w is the handedness of the bitangent: `cross(normal, tangent) * w`.
+    pub tangent: Vector4<f32>
 }
 
+/// Location, GL type and meaning of the vertex inputs a mesh feeds, from `Vertex` and
+/// from the instance buffer.
+pub const MESH_ATTRIBUTES: [(u32, GLenum, &str); 7] = [
+    (0, gl::FLOAT_VEC3, "Vertex::position"),
+    (1, gl::FLOAT_VEC3, "Vertex::normal"),
+    (2, gl::FLOAT_VEC2, "Vertex::tex_coords"),
+    (3, gl::FLOAT_VEC4, "Vertex::color"),
+    (4, gl::FLOAT_VEC4, "Vertex::tangent"),
+    (instance::INSTANCE_MODEL_LOCATION, gl::FLOAT_MAT4, "Instance::model"),
+    (instance::INSTANCE_COLOR_LOCATION, gl::FLOAT_VEC4, "Instance::color")
+];
+
 impl Default for Vertex {
     fn default() -> Self {
         Vertex {
             position: Vector3::zero(),
             normal: Vector3::zero(),
             tex_coords: Vector2::zero(),
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            tangent: Vector4::new(1.0, 0.0, 0.0, 1.0)
         }
     }
 }
@@ -41,18 +64,41 @@ impl Default for Vertex {
 pub struct Texture {
     pub id: u32,
     pub type_: String,
-    pub path: String
+    pub path: String,
+    /// Bound along with the texture when a material samples it differently from its
+    /// own modes, e.g. clamped by an MTL `-clamp on`.
+    pub sampler: Option<Sampler>,
+    /// Bytes the texture takes on the GPU, with its mipmaps.
+    pub memory_size: usize
 }
 
+/// A coarser level of detail. Its indices are stored after the full index list in the
+/// same element buffer and use the same vertices.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshLod {
+    /// Offset in indices, not bytes, into the element buffer.
+    pub index_offset: usize,
+    pub index_count: usize,
+    /// Simplification error relative to the mesh size.
+    pub error: f32
+}
 
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub textures: Vec<Texture>,
+    /// Index into `Model::materials`.
+    pub material_id: Option<usize>,
+    /// Levels of detail after the full mesh, each coarser than the one before.
+    pub lods: Vec<MeshLod>,
+    /// Middle of the bounding box, for depth sorting.
+    pub center: Vector3<f32>,
     pub VAO: u32, 
 
     VBO: u32, 
-    EBO: u32
+    EBO: u32,
+    /// Per instance data for `draw_instanced`, refilled on every instanced draw.
+    instance_buffer: u32
 }
 
 impl Mesh {
@@ -61,17 +107,62 @@ impl Mesh {
             vertices,
             indices,
             textures,
-            VAO: 0, VBO: 0, EBO: 0
+            material_id: None,
+            lods: Vec::new(),
+            center: Vector3::zero(),
+            VAO: 0, VBO: 0, EBO: 0, instance_buffer: 0
         };
 
         // gl: load all OpenGL function pointers
         // ---------------------------------------
         
-        unsafe { mesh.setup_mesh() }
+        unsafe {
+            let vertex_data = mesh.vertices.as_ptr() as *const c_void;
+            let vertex_bytes = mesh.vertices.len() * mem::size_of::<Vertex>();
+            let index_data = mesh.indices.as_ptr() as *const c_void;
+            let index_bytes = mesh.indices.len() * mem::size_of::<u32>();
+            mesh.setup_mesh(vertex_data, vertex_bytes, index_data, index_bytes)
+        }
+        mesh.center = bounds_center(&mesh.vertices);
         mesh
     }
 
-    unsafe fn setup_mesh(&mut self) {
+    /// Builds a mesh from raw `Vertex` and `u32` data, e.g. straight out of a memory mapped cache file.
+    /// The GL buffers are filled from the given bytes, the CPU side copies are made afterwards.
+    /// Lengths have to be multiples of the element sizes.
+    pub fn from_bytes(vertex_bytes: &[u8], index_bytes: &[u8], textures: Vec<Texture>) -> Mesh {
+        let mut mesh = Mesh {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            textures,
+            material_id: None,
+            lods: Vec::new(),
+            center: Vector3::zero(),
+            VAO: 0, VBO: 0, EBO: 0, instance_buffer: 0
+        };
+        let num_vertices = vertex_bytes.len() / mem::size_of::<Vertex>();
+        let num_indices = index_bytes.len() / mem::size_of::<u32>();
+
+        unsafe {
+            mesh.setup_mesh(vertex_bytes.as_ptr() as *const c_void, vertex_bytes.len(),
+                            index_bytes.as_ptr() as *const c_void, index_bytes.len());
+
+            // the bytes are not necessarily aligned for Vertex, copy them byte wise
+            mesh.vertices = Vec::with_capacity(num_vertices);
+            ptr::copy_nonoverlapping(vertex_bytes.as_ptr(), mesh.vertices.as_mut_ptr() as *mut u8,
+                                     num_vertices * mem::size_of::<Vertex>());
+            mesh.vertices.set_len(num_vertices);
+            mesh.indices = Vec::with_capacity(num_indices);
+            ptr::copy_nonoverlapping(index_bytes.as_ptr(), mesh.indices.as_mut_ptr() as *mut u8,
+                                     num_indices * mem::size_of::<u32>());
+            mesh.indices.set_len(num_indices);
+        }
+        mesh.center = bounds_center(&mesh.vertices);
+        mesh
+    }
+
+    unsafe fn setup_mesh(&mut self, vertex_data: *const c_void, vertex_bytes: usize,
+                         index_data: *const c_void, index_bytes: usize) {
         gl::GenVertexArrays(1, &mut self.VAO);
         gl::GenBuffers(1, &mut self.VBO);
         gl::GenBuffers(1, &mut self.EBO);
@@ -80,14 +171,14 @@ impl Mesh {
         gl::BindBuffer(gl::ARRAY_BUFFER, self.VBO);
 
         gl::BufferData( gl::ARRAY_BUFFER, 
-                        (self.vertices.len() * mem::size_of::<Vertex>()) as isize,
-                        &self.indices[0] as *const u32 as *const c_void,
+                        vertex_bytes as isize,
+                        vertex_data,
                         gl::STATIC_DRAW);
         
         gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.EBO);
         gl::BufferData( gl::ELEMENT_ARRAY_BUFFER,
-                        (self.indices.len() * mem::size_of::<u32>()) as isize,
-                        &self.indices[0] as *const u32 as *const c_void,
+                        index_bytes as isize,
+                        index_data,
                         gl::STATIC_DRAW);
 
 
@@ -102,43 +193,231 @@ impl Mesh {
         // vertex texture coords
         gl::EnableVertexAttribArray(2);	
         gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, vertex_size, offset_of!(Vertex, tex_coords) as *const c_void);
+        // vertex colors
+        gl::EnableVertexAttribArray(3);	
+        gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, vertex_size, offset_of!(Vertex, color) as *const c_void);
+        // vertex tangents
+        gl::EnableVertexAttribArray(4);	
+        gl::VertexAttribPointer(4, 4, gl::FLOAT, gl::FALSE, vertex_size, offset_of!(Vertex, tangent) as *const c_void);
+
+        // instance model matrix and color, off until an instanced draw
+        gl::GenBuffers(1, &mut self.instance_buffer);
+        instance::setup_instance_attributes(self.instance_buffer);
+        instance::reset_instance_attributes();
 
         gl::BindVertexArray(0);
     }
 
+    /// Deletes the vertex array and buffers, the mesh can't be drawn afterwards.
+    pub fn delete_buffers(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.VAO);
+            let buffers = [self.VBO, self.EBO, self.instance_buffer];
+            gl::DeleteBuffers(buffers.len() as i32, buffers.as_ptr());
+        }
+        self.VAO = 0;
+        self.VBO = 0;
+        self.EBO = 0;
+        self.instance_buffer = 0;
+    }
+
+    /// Replaces the levels of detail with the given (indices, error) pairs, finest first,
+    /// and re-uploads the element buffer.
+    pub fn set_lods(&mut self, lods: Vec<(Vec<u32>, f32)>) {
+        let mut all_indices = self.indices.clone();
+        self.lods.clear();
+        for (indices, error) in lods {
+            self.lods.push(MeshLod {
+                index_offset: all_indices.len(),
+                index_count: indices.len(),
+                error
+            });
+            all_indices.extend_from_slice(&indices);
+        }
+
+        unsafe {
+            // the element buffer binding is VAO state
+            gl::BindVertexArray(self.VAO);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.EBO);
+            gl::BufferData( gl::ELEMENT_ARRAY_BUFFER,
+                            (all_indices.len() * mem::size_of::<u32>()) as isize,
+                            all_indices.as_ptr() as *const c_void,
+                            gl::STATIC_DRAW);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    /// Uploads `vertices` again after they were changed in place. The count has to stay the same.
+    pub fn update_vertices(&self) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.VBO);
+            gl::BufferSubData(gl::ARRAY_BUFFER, 0,
+                              (self.vertices.len() * mem::size_of::<Vertex>()) as isize,
+                              self.vertices.as_ptr() as *const c_void);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
     pub unsafe fn draw(&self, shader: &Shader) {
-        let mut diffuse_nr: u32 = 0;
-        let mut specular_nr: u32 = 0;
+        self.draw_lod(shader, 0);
+    }
+
+    /// Draws level `lod`, 0 being the full mesh. Levels past the last one draw the last one.
+    pub unsafe fn draw_lod(&self, shader: &Shader, lod: usize) {
+        self.bind_textures(shader);
+
+        //draw mesh
+        let (offset, count) = self.lod_range(lod);
+        gl::BindVertexArray(self.VAO);
+        gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_INT, (offset * mem::size_of::<u32>()) as *const c_void);
+        gl::BindVertexArray(0);
+
+        self.unbind_samplers();
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    pub unsafe fn draw_instanced(&self, shader: &Shader, instances: &[Instance]) {
+        self.draw_lod_instanced(shader, 0, instances);
+    }
+
+    /// Draws level `lod` once per instance in a single draw call.
+    /// Each instance's model matrix is applied after the `model` uniform.
+    pub unsafe fn draw_lod_instanced(&self, shader: &Shader, lod: usize, instances: &[Instance]) {
+        if instances.is_empty() {
+            return;
+        }
+        self.bind_textures(shader);
+
+        gl::BindVertexArray(self.VAO);
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_buffer);
+        gl::BufferData( gl::ARRAY_BUFFER,
+                        (instances.len() * mem::size_of::<Instance>()) as isize,
+                        instances.as_ptr() as *const c_void,
+                        gl::STREAM_DRAW);
+        instance::enable_instance_arrays(true);
+
+        let (offset, count) = self.lod_range(lod);
+        gl::DrawElementsInstanced(gl::TRIANGLES, count as i32, gl::UNSIGNED_INT,
+                                  (offset * mem::size_of::<u32>()) as *const c_void, instances.len() as i32);
+
+        instance::enable_instance_arrays(false);
+        instance::reset_instance_attributes();
+        gl::BindVertexArray(0);
+
+        self.unbind_samplers();
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// Draws through the state cache of the render queue, which has already made the right
+    /// program current and set the `model` uniform.
+    pub(crate) unsafe fn draw_queued(&self, state: &mut GlState, shader: &Shader, lod: usize, instances: &[Instance]) {
+        let mut type_counts: HashMap<&str, u32> = HashMap::new();
+        for (i, texture) in self.textures.iter().enumerate() {
+            let number = {
+                let count = type_counts.entry(texture.type_.as_str()).or_insert(0);
+                *count += 1;
+                *count
+            };
+            with_material_uniform(&texture.type_, number, |name| {
+                if shader.has_uniform(name) {
+                    state.set_int(shader, name, i as i32);
+                }
+            });
+            state.bind_texture(i as u32, texture.id);
+            state.bind_sampler(i as u32, texture.sampler.map_or(0, |sampler| sampler.id));
+        }
+
+        let (offset, count) = self.lod_range(lod);
+        let offset = (offset * mem::size_of::<u32>()) as *const c_void;
+        state.bind_vertex_array(self.VAO);
+        if instances.len() > 1 {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_buffer);
+            gl::BufferData( gl::ARRAY_BUFFER,
+                            (instances.len() * mem::size_of::<Instance>()) as isize,
+                            instances.as_ptr() as *const c_void,
+                            gl::STREAM_DRAW);
+            instance::enable_instance_arrays(true);
+            gl::DrawElementsInstanced(gl::TRIANGLES, count as i32, gl::UNSIGNED_INT, offset, instances.len() as i32);
+            instance::enable_instance_arrays(false);
+            instance::reset_instance_attributes();
+        } else {
+            gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_INT, offset);
+        }
+        state.count_draw(count / 3, instances.len());
+    }
+
+    unsafe fn bind_textures(&self, shader: &Shader) {
+        // textures of one type are numbered from 1: texture_diffuse1, texture_diffuse2, ...
+        let mut type_counts: HashMap<&str, u32> = HashMap::new();
         for (i, texture) in self.textures.iter().enumerate() {
             gl::ActiveTexture(gl::TEXTURE0 + i as u32);
             let name = &texture.type_;
-            let number = match name.as_str() {
-                "texture_diffuse" => {
-                    diffuse_nr += 1;
-                    diffuse_nr
-                }
-                "texture_specular" => {
-                    specular_nr += 1;
-                    specular_nr
-                }
-                _ => panic!("unknown texture type")
+            let number = {
+                let count = type_counts.entry(name.as_str()).or_insert(0);
+                *count += 1;
+                *count
             };
-            let material_CString = CString::new(format!("material.{}{}", name, number)).expect("CString::new failed");
-            let material_CStr = CStr::from_bytes_with_nul_unchecked(material_CString.to_bytes_with_nul());
-            shader.setFloat(material_CStr, i as f32);
+            with_material_uniform(name, number, |material_CStr| {
+                // a shader may leave out maps the mesh has, e.g. Phong the PBR ones
+                if shader.has_uniform(material_CStr) {
+                    shader.setInt(material_CStr, i as i32);
+                }
+            });
             gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            gl::BindSampler(i as u32, texture.sampler.map_or(0, |sampler| sampler.id));
         }
+    }
 
-        //draw mesh
-        gl::BindVertexArray(self.VAO);
-        gl::DrawElements(gl::TRIANGLES, self.indices.len() as i32, gl::UNSIGNED_INT, ptr::null());
-        gl::BindVertexArray(0);
+    /// Unbinds the sampler objects `bind_textures` bound, so they don't apply to
+    /// whatever is drawn with these units next.
+    unsafe fn unbind_samplers(&self) {
+        for (i, texture) in self.textures.iter().enumerate() {
+            if texture.sampler.is_some() {
+                gl::BindSampler(i as u32, 0);
+            }
+        }
+    }
 
-        gl::ActiveTexture(gl::TEXTURE0);
+    /// Offset and count, in indices, of level `lod` in the element buffer.
+    fn lod_range(&self, lod: usize) -> (usize, usize) {
+        match lod.min(self.lods.len()) {
+            0 => (0, self.indices.len()),
+            level => (self.lods[level - 1].index_offset, self.lods[level - 1].index_count)
+        }
     }
 
 }
 
+fn bounds_center(vertices: &[Vertex]) -> Vector3<f32> {
+    if vertices.is_empty() {
+        return Vector3::zero();
+    }
+    let (mut min, mut max) = (vertices[0].position, vertices[0].position);
+    for v in vertices {
+        min = Vector3::new(min.x.min(v.position.x), min.y.min(v.position.y), min.z.min(v.position.z));
+        max = Vector3::new(max.x.max(v.position.x), max.y.max(v.position.y), max.z.max(v.position.z));
+    }
+    (min + max) * 0.5
+}
 
+thread_local! {
+    /// `material.<type><number>` names by texture type, built once instead of on every draw.
+    static MATERIAL_UNIFORMS: RefCell<HashMap<String, Vec<CString>>> = RefCell::new(HashMap::new());
+}
 
-
+/// Calls `f` with the name of the uniform the `number`th texture of `type_` is bound to,
+/// numbered from 1: material.texture_diffuse1, material.texture_diffuse2, ...
+fn with_material_uniform<R>(type_: &str, number: u32, f: impl FnOnce(&CStr) -> R) -> R {
+    MATERIAL_UNIFORMS.with(|names| {
+        let mut names = names.borrow_mut();
+        if !names.contains_key(type_) {
+            names.insert(type_.to_string(), Vec::new());
+        }
+        let numbered = names.get_mut(type_).unwrap();
+        while numbered.len() < number as usize {
+            let name = format!("material.{}{}", type_, numbered.len() + 1);
+            numbered.push(CString::new(name).expect("CString::new failed"));
+        }
+        f(&numbered[number as usize - 1])
+    })
+}
@@ -1,10 +1,69 @@
 pub mod model;
 pub use model::Model;
-pub use model::Scene;
+pub use model::{ Scene, Shading };
+pub use model::Node;
+pub use model::{ ImportOptions, MeshReport, MeshData, ModelFormat };
 
 pub mod mesh;
+pub use mesh::Mesh;
 pub use mesh::Vertex;
 pub use mesh::Texture;
+pub use mesh::MeshLod;
+
+pub mod texture;
+pub use texture::{ TextureOptions, TextureSampler, Sampler, MtlMap, PixelFormat, TextureLevels };
+
+pub mod bcn;
+
+pub mod dds;
+
+pub mod ktx;
+
+pub mod exr;
+
+pub mod hdr_image;
+pub use hdr_image::HdrImage;
+
+pub mod atlas;
+pub use atlas::{ Atlas, AtlasSettings, AtlasEntry, AtlasGroup };
+
+pub mod instance;
+pub use instance::Instance;
+
+pub mod material;
+pub use material::{ Material, AlphaMode };
 
 pub mod shader;
-pub use shader::Shader;
\ No newline at end of file
+pub use shader::{ Shader, ShaderVariants };
+
+pub mod preprocess;
+pub use preprocess::{ Preprocessor, ShaderSource };
+
+pub mod reflection;
+pub use reflection::{ ShaderReflection, Variable, UniformBlock };
+
+pub mod normals;
+pub use normals::{ NormalMode, NormalWeighting };
+
+pub mod weld;
+
+pub mod gltf_import;
+
+pub mod ply;
+
+pub mod stl;
+
+pub mod obj_export;
+pub use obj_export::{ ExportOptions, TextureExport };
+
+pub mod cache;
+
+pub mod optimize;
+pub use optimize::{ CacheMetrics, OptimizationReport };
+
+pub mod simplify;
+
+pub mod lod;
+pub use lod::LodSettings;
+pub mod primitives;
+pub use primitives::Geometry;
@@ -1,58 +1,299 @@
-use std::os::raw::c_void;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
-use cgmath::{vec2, vec3};
+use cgmath::{vec2, vec3, Matrix4, Vector3};
+use cgmath::prelude::*;
 use gl;
-use image;
-use image::DynamicImage::*;
-use image::GenericImage;
 use tobj;
 
+use crate::model::gltf_import;
+use crate::model::instance::Instance;
+use crate::model::lod::LodSettings;
+use crate::model::material::{AlphaMode, Material};
 use crate::model::mesh::{Mesh, Vertex, Texture};
+use crate::model::normals::{self, NormalMode};
+use crate::model::optimize::{self, OptimizationReport};
+use crate::model::texture::{gpu_memory_size, is_color_texture, load_texture, MtlMap, Sampler, TextureOptions, TextureSampler};
+use crate::model::{ cache, ply, stl, weld };
 use crate::model::Shader;
+use crate::render::{ shader_path, Cubemap, IblMaps, Light, Skybox };
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// Lighting model of the scene shader.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shading {
+    /// Blinn-Phong, from the MTL parameters.
+    Phong,
+    /// Metallic-roughness Cook-Torrance, with image based lighting once the scene has an environment.
+    /// The G-buffer only holds Phong parameters, so scenes shaded this way are drawn forward.
+    Pbr
+}
 
 pub struct Scene {
     pub shader: Shader,
-    pub root: Model
+    /// What `shader` was built for.
+    pub shading: Shading,
+    pub root: Model,
+    pub lights: Vec<Light>,
+    /// Background, and the environment materials reflect and refract.
+    pub skybox: Option<Skybox>,
+    /// Precomputed from the skybox by `set_environment`.
+    pub ibl: Option<IblMaps>
 }
 
 impl Scene {
     pub fn new(model_path: &str) -> Scene {
+        Scene::with_shading(model_path, Shading::Phong)
+    }
+
+    pub fn with_shading(model_path: &str, shading: Shading) -> Scene {
 
         // build and compile shaders
         // -------------------------
-        let shader = Shader::new(
-            "C:/Users/AdaMoe/Documents/rust_code/learnGL/engine/src/shaders/model.vert",
-            "C:/Users/AdaMoe/Documents/rust_code/learnGL/engine/src/shaders/model.frag");
+        let fragment = match shading {
+            Shading::Phong => "model.frag",
+            Shading::Pbr => "pbr.frag"
+        };
+        let shader = Shader::new(&shader_path("model.vert"), &shader_path(fragment));
+        if let Err(problems) = shader.check_vertex_layout() {
+            println!("model.vert with {} does not match the mesh layout:\n{}", fragment, problems);
+        }
 
         // load models
         // -----------
         let root = Model::new(model_path);
 
+        // a sun from above, casting shadows
+        let lights = vec![Light::directional(vec3(-0.3, -1.0, -0.5), vec3(1.0, 1.0, 1.0))];
+
         Scene {
             shader,
-            root
+            shading,
+            root,
+            lights,
+            skybox: None,
+            ibl: None
         }
     }
+
+    /// Uses `cubemap` as the sky, for reflections and, convolved, for image based lighting.
+    pub fn set_environment(&mut self, cubemap: Cubemap) {
+        self.ibl = Some(IblMaps::new(&cubemap));
+        self.skybox = Some(Skybox::new(cubemap));
+    }
+}
+
+/// Options for how a model file is turned into meshes.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportOptions {
+    /// Used for meshes that have no normals in the file.
+    pub normals: NormalMode,
+    /// Vertices closer than this in every attribute are merged. Zero only merges exact duplicates.
+    pub weld_epsilon: f32,
+    /// Load OBJ files from, and save them to, a binary cache file next to the source.
+    pub use_cache: bool,
+    /// Reorder triangles and vertices for the vertex cache, overdraw and vertex fetch.
+    pub optimize: bool,
+    pub lod: LodSettings
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            normals: NormalMode::default(),
+            weld_epsilon: 1e-5,
+            use_cache: true,
+            optimize: false,
+            lod: LodSettings::default()
+        }
+    }
+}
+
+/// What the loader had to fill in for a mesh.
+#[derive(Clone, Debug, Default)]
+pub struct MeshReport {
+    pub name: String,
+    /// Set when the file had no normals and they were computed instead.
+    pub generated_normals: Option<NormalMode>,
+    /// Set when the file had no texture coordinates and they were zeroed.
+    pub defaulted_tex_coords: bool,
+    /// One vertex per face corner, as the file describes it.
+    pub vertices_before: usize,
+    pub indices_before: usize,
+    /// After deduplication, normal generation and welding.
+    pub vertices_after: usize,
+    pub indices_after: usize,
+    /// Cache metrics, if the mesh went through `optimize::optimize_mesh`.
+    pub optimization: Option<OptimizationReport>
+}
+
+/// A single mesh as parsed from a file, before `Model` finishes it.
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub has_normals: bool,
+    pub report: MeshReport
+}
+
+/// File formats `Model` can load.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModelFormat {
+    Obj,
+    Gltf,
+    Ply,
+    Stl
+}
+
+impl Default for ModelFormat {
+    fn default() -> Self {
+        ModelFormat::Obj
+    }
+}
+
+impl ModelFormat {
+    /// Picks the format from the first bytes of the file, falling back to the extension.
+    pub fn detect(path: &Path) -> ModelFormat {
+        let mut magic = [0u8; 5];
+        let read = File::open(path).and_then(|mut file| file.read(&mut magic)).unwrap_or(0);
+        let magic = &magic[..read];
+        if magic.starts_with(b"glTF") {
+            return ModelFormat::Gltf;
+        }
+        if magic.starts_with(b"ply") {
+            return ModelFormat::Ply;
+        }
+        // "solid" also starts some binary STL headers, so this only settles ASCII vs binary later
+        if magic.starts_with(b"solid") {
+            return ModelFormat::Stl;
+        }
+
+        let extension = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "gltf" | "glb" => ModelFormat::Gltf,
+            "ply" => ModelFormat::Ply,
+            "stl" => ModelFormat::Stl,
+            _ => ModelFormat::Obj
+        }
+    }
+}
+
+/// A named, transformed entry in the model's hierarchy.
+/// Transforms are relative to the parent node.
+pub struct Node {
+    pub name: String,
+    pub transform: Matrix4<f32>,
+    /// Indices into `Model::meshes`.
+    pub meshes: Vec<usize>,
+    /// Indices into `Model::nodes`.
+    pub children: Vec<usize>
 }
 
-#[derive(Default)]
 pub struct Model {
     pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub nodes: Vec<Node>,
+    /// Indices into `nodes` of the nodes without a parent.
+    pub root_nodes: Vec<usize>,
     pub textures_loaded: Vec<Texture>,
-    directory: String
+    /// Sampler objects of the textures that override their modes, one per distinct setting.
+    pub samplers: Vec<Sampler>,
+    pub reports: Vec<MeshReport>,
+    /// Center and radius in model space.
+    pub bounding_sphere: (Vector3<f32>, f32),
+    /// Level of detail picked by `update_lod`.
+    pub current_lod: usize,
+    pub(crate) directory: String,
+    pub(crate) source_format: ModelFormat,
+    pub(crate) options: ImportOptions
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            nodes: Vec::new(),
+            root_nodes: Vec::new(),
+            textures_loaded: Vec::new(),
+            samplers: Vec::new(),
+            reports: Vec::new(),
+            bounding_sphere: (Vector3::zero(), 0.0),
+            current_lod: 0,
+            directory: String::new(),
+            source_format: ModelFormat::default(),
+            options: ImportOptions::default()
+        }
+    }
 }
 
 impl Model {
     pub fn new(path: &str) -> Model {
-        let mut model = Model::default();
+        Model::with_options(path, ImportOptions::default())
+    }
+
+    pub fn with_options(path: &str, options: ImportOptions) -> Model {
+        let mut model = Model {
+            options,
+            ..Model::default()
+        };
         model.load_model(path);
+        model.compute_bounds();
+        model.build_lods();
         model
     }
 
-    pub fn draw(&self, shader: &Shader) -> () {
-        for mesh in &self.meshes {
-            unsafe { mesh.draw(shader); }
+    /// Draws every node with `transform` as the parent of the root nodes.
+    /// Sets the "model" uniform per node.
+    pub fn draw(&self, shader: &Shader, transform: &Matrix4<f32>) -> () {
+        // nodes that use the same mesh, and so the same material, go into one instanced draw
+        let batches = self.collect_batches(transform);
+        for (mesh, instances) in self.meshes.iter().zip(&batches) {
+            unsafe {
+                match instances.len() {
+                    0 => {}
+                    1 => {
+                        shader.setMat4(c_str!("model"), &instances[0].model);
+                        mesh.draw_lod(shader, self.current_lod);
+                    }
+                    _ => {
+                        shader.setMat4(c_str!("model"), &Matrix4::identity());
+                        mesh.draw_lod_instanced(shader, self.current_lod, instances);
+                    }
+                }
+            }
+        }
+    }
+
+    /// World transforms of every use of each mesh, indexed like `meshes`.
+    pub(crate) fn collect_batches(&self, transform: &Matrix4<f32>) -> Vec<Vec<Instance>> {
+        let mut batches: Vec<Vec<Instance>> = vec![Vec::new(); self.meshes.len()];
+        for &root in &self.root_nodes {
+            self.collect_instances(root, transform, &mut batches);
+        }
+        batches
+    }
+
+    fn collect_instances(&self, node: usize, parent: &Matrix4<f32>, batches: &mut Vec<Vec<Instance>>) {
+        let node = &self.nodes[node];
+        let transform = parent * node.transform;
+        for &mesh in &node.meshes {
+            batches[mesh].push(Instance::new(transform));
+        }
+        for &child in &node.children {
+            self.collect_instances(child, &transform, batches);
         }
     }
 
@@ -61,101 +302,256 @@ impl Model {
         // retrieve the directory path of the filepath
         self.directory = path.parent().unwrap_or_else(|| Path::new("")).to_str().unwrap().into();
         println!("{}", &path.display());
+
+        self.source_format = ModelFormat::detect(path);
+        match self.source_format {
+            ModelFormat::Obj if self.options.use_cache => {
+                match cache::load(self, path) {
+                    Ok(true) => return,
+                    Ok(false) => {}
+                    Err(err) => {
+                        println!("Ignoring mesh cache for {}: {}", path.display(), err);
+                        self.clear();
+                    }
+                }
+                self.load_obj(path);
+                if let Err(err) = cache::save(self, path) {
+                    println!("Failed to write mesh cache for {}: {}", path.display(), err);
+                }
+            }
+            ModelFormat::Obj => self.load_obj(path),
+            ModelFormat::Gltf => gltf_import::load_gltf(self, path),
+            ModelFormat::Ply => {
+                let ply = ply::load_ply(path)
+                    .unwrap_or_else(|err| panic!("Failed to load PLY {}: {}", path.display(), err));
+                self.add_root_mesh(ply.vertices, ply.indices, ply.has_normals, None, ply.report);
+            }
+            ModelFormat::Stl => {
+                let stl = stl::load_stl(path)
+                    .unwrap_or_else(|err| panic!("Failed to load STL {}: {}", path.display(), err));
+                self.add_root_mesh(stl.vertices, stl.indices, stl.has_normals, None, stl.report);
+            }
+        }
+    }
+
+    /// Drops everything loaded so far, keeping the directory and options. The GL buffers,
+    /// textures and samplers created for it are deleted.
+    fn clear(&mut self) {
+        for mesh in &mut self.meshes {
+            mesh.delete_buffers();
+        }
+        for texture in &self.textures_loaded {
+            unsafe { gl::DeleteTextures(1, &texture.id) };
+        }
+        for sampler in &self.samplers {
+            unsafe { gl::DeleteSamplers(1, &sampler.id) };
+        }
+        self.meshes.clear();
+        self.materials.clear();
+        self.nodes.clear();
+        self.root_nodes.clear();
+        self.textures_loaded.clear();
+        self.samplers.clear();
+        self.reports.clear();
+    }
+
+    fn load_obj(&mut self, path: &Path) -> () {
         let obj = tobj::load_obj(&path, false);
         assert!(obj.is_ok());
         let (models, materials) = obj.unwrap();
 
+        for material in &materials {
+            let material = self.process_obj_material(material);
+            self.materials.push(material);
+        }
+
         for model in models {
             let mesh = &model.mesh;
-            let num_vertices = mesh.positions.len() / 3;
-
-            // data to fill
-            let mut vertices: Vec<Vertex> = Vec::with_capacity(num_vertices);
-            let indices: Vec<u32> = mesh.indices.clone();
-
-            let (p, n, t) = (&mesh.positions, &mesh.normals, &mesh.texcoords);
-            println!("Loading model with n: {} vertices", num_vertices);
-            for i in 0..num_vertices {
-                vertices.push(Vertex {
-                    position:  vec3(p[i*3], p[i*3+1], p[i*3+2]),
-                    normal:    vec3(n[i*3], n[i*3+1], n[i*3+2]),
-                    tex_coords: vec2(t[i*2], t[i*2+1])
-                })
-            }
-            println!("Vertices pushed");
-            // process material
-            let mut textures = Vec::new();
-            if let Some(material_id) = mesh.material_id {
-                let material = &materials[material_id];
-                
-                // 1. diffuse map
-                if !material.diffuse_texture.is_empty() {
-                    println!("texture_diffuse");
-                    let texture = self.loadMaterialTexture(&material.diffuse_texture, "texture_diffuse");
-                    textures.push(texture);
-                }
-                // 2. specular map
-                if !material.specular_texture.is_empty() {
-                    println!("texture_specular");
-                    let texture = self.loadMaterialTexture(&material.specular_texture, "texture_specular");
-                    textures.push(texture);
-                }
-                // 3. normal map
-                if !material.normal_texture.is_empty() {
-                    println!("texture_normal");
-                    let texture = self.loadMaterialTexture(&material.normal_texture, "texture_normal");
-                    textures.push(texture);
-                }
-                // NOTE: no height maps
-            }
 
-            self.meshes.push(Mesh::new(vertices, indices, textures));
+            let mut report = MeshReport {
+                name: model.name.clone(),
+                ..MeshReport::default()
+            };
+            let (vertices, indices, has_normals) = unify_obj_vertices(mesh, &mut report);
+            self.add_root_mesh(vertices, indices, has_normals, mesh.material_id, report);
         }
     }
 
-    fn loadMaterialTexture(&mut self, path: &str, tex_type: &str) -> Texture {
-        {
-            let texture = self.textures_loaded.iter().find(|tex| tex.path == path);
-            if let Some(texture) = texture {
-                texture.clone();
-            }
+    /// Finishes a freshly parsed mesh: generates normals if the file had none, welds it,
+    /// uploads it and adds it as a root node named after the report.
+    pub(crate) fn add_root_mesh(&mut self, mut vertices: Vec<Vertex>, mut indices: Vec<u32>, has_normals: bool,
+                                material_id: Option<usize>, mut report: MeshReport) {
+        println!("Loading model with n: {} vertices", vertices.len());
+
+        // fill in what the file left out
+        if report.defaulted_tex_coords {
+            println!("{}: no texture coordinates in file, defaulted to (0, 0)", report.name);
+        }
+        if !has_normals {
+            normals::generate_normals(&mut vertices, &mut indices, self.options.normals);
+            report.generated_normals = Some(self.options.normals);
+            println!("{}: no normals in file, generated {:?} normals", report.name, self.options.normals);
         }
+
+        // merge what is close enough to be the same vertex
+        weld::weld_vertices(&mut vertices, &mut indices, self.options.weld_epsilon);
+        normals::generate_tangents(&mut vertices, &indices);
+        report.vertices_after = vertices.len();
+        report.indices_after = indices.len();
+        println!("{}: vertices {} -> {}, indices {} -> {}",
+                 report.name, report.vertices_before, report.vertices_after,
+                 report.indices_before, report.indices_after);
+        if self.options.optimize {
+            report.optimization = Some(optimize_mesh(&mut vertices, &mut indices, &report.name));
+        }
+
+        let textures = match material_id {
+            Some(material_id) => self.materials[material_id].textures.clone(),
+            None => Vec::new()
+        };
+        let mut mesh = Mesh::new(vertices, indices, textures);
+        mesh.material_id = material_id;
+
+        // no hierarchy in these formats, every object becomes a root node
+        self.root_nodes.push(self.nodes.len());
+        self.nodes.push(Node {
+            name: report.name.clone(),
+            transform: Matrix4::identity(),
+            meshes: vec![self.meshes.len()],
+            children: Vec::new()
+        });
+        self.meshes.push(mesh);
+        self.reports.push(report);
+    }
+
+    fn process_obj_material(&mut self, material: &tobj::Material) -> Material {
+        let d = material.diffuse;
+        let mut result = Material {
+            name: material.name.clone(),
+            ambient: material.ambient,
+            specular: material.specular,
+            shininess: material.shininess,
+            base_color: [d[0], d[1], d[2], material.dissolve],
+            alpha_mode: if material.dissolve < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+            ior: material.optical_density,
+            // for the PBR shader: dielectric, as rough as the Phong exponent suggests
+            metallic: 0.0,
+            roughness: (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt(),
+            ..Material::default()
+        };
+
+        // 1. diffuse map
+        if !material.diffuse_texture.is_empty() {
+            println!("texture_diffuse");
+            let texture = self.loadMaterialTexture(&material.diffuse_texture, "texture_diffuse");
+            result.textures.push(texture);
+        }
+        // 2. specular map
+        if !material.specular_texture.is_empty() {
+            println!("texture_specular");
+            let texture = self.loadMaterialTexture(&material.specular_texture, "texture_specular");
+            result.textures.push(texture);
+        }
+        // 3. normal map
+        if !material.normal_texture.is_empty() {
+            println!("texture_normal");
+            let texture = self.loadMaterialTexture(&material.normal_texture, "texture_normal");
+            result.textures.push(texture);
+        }
+        // NOTE: no height maps
+        result
+    }
+
+    /// Loads the texture of an MTL map statement, options and file name, relative to the model.
+    pub(crate) fn loadMaterialTexture(&mut self, statement: &str, tex_type: &str) -> Texture {
+        let map = MtlMap::parse(statement);
+        self.load_texture_with(&map.file, tex_type, map.sampler())
+    }
+
+    /// Loads `path`, relative to the model, sampled through `sampler` if given.
+    /// An image already loaded into the same color space is shared, only the sampler differs.
+    pub(crate) fn load_texture_with(&mut self, path: &str, tex_type: &str, sampler: Option<TextureSampler>) -> Texture {
+        let srgb = is_color_texture(tex_type);
+        let loaded = self.textures_loaded.iter()
+            .find(|tex| tex.path == path && is_color_texture(&tex.type_) == srgb)
+            .map(|tex| (tex.id, tex.memory_size));
+        let (id, memory_size) = match loaded {
+            Some(loaded) => loaded,
+            None => unsafe {
+                let id = load_texture(&format!("{}/{}", self.directory, path), &TextureOptions::for_type(tex_type));
+                let memory_size = gpu_memory_size(id);
+                println!("{}: {} KiB", path, memory_size / 1024);
+                (id, memory_size)
+            }
+        };
         let texture = Texture {
-            id: unsafe { TextureFromFile(path, &self.directory) },
+            id,
             type_: tex_type.into(),
-            path: path.into()
+            path: path.into(),
+            sampler: sampler.map(|settings| self.sampler(settings)),
+            memory_size
         };
-        self.textures_loaded.push(texture.clone());
+        if loaded.is_none() {
+            self.textures_loaded.push(texture.clone());
+        }
         texture
     }
 
+    /// The sampler object for `settings`, created the first time it is asked for.
+    pub(crate) fn sampler(&mut self, settings: TextureSampler) -> Sampler {
+        if let Some(sampler) = self.samplers.iter().find(|sampler| sampler.settings == settings) {
+            return *sampler;
+        }
+        let sampler = Sampler::new(settings);
+        self.samplers.push(sampler);
+        sampler
+    }
+
+    /// Bytes all textures of the model take on the GPU.
+    pub fn texture_memory(&self) -> usize {
+        self.textures_loaded.iter().map(|texture| texture.memory_size).sum()
+    }
+
+}
+
+/// Runs the optimization pipeline and prints the cache metrics before and after.
+pub(crate) fn optimize_mesh(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, name: &str) -> OptimizationReport {
+    let optimization = optimize::optimize_mesh(vertices, indices);
+    println!("{}: ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}", name,
+             optimization.before.acmr, optimization.after.acmr,
+             optimization.before.atvr, optimization.after.atvr);
+    optimization
 }
 
-unsafe fn TextureFromFile(path: &str, directory: &str) -> u32 {
-    let filename = format!("{}/{}", directory, path);
-    let mut textureID = 0;
-    gl::GenTextures(1, &mut textureID);
-    
-    let img = image::open(&Path::new(&filename)).expect("Texture failed to load");
-    let img = img.flipv();
-    
-    let format = match img {
-        ImageLuma8(_) => gl::RED,
-        ImageLumaA8(_) => gl::RG,
-        ImageRgb8(_) => gl::RGB,
-        ImageRgba8(_) => gl::RGBA,
-    };
-    
-    let data = img.raw_pixels();
-
-    gl::BindTexture(gl::TEXTURE_2D, textureID);
-    gl::TexImage2D(gl::TEXTURE_2D, 0, format as i32, img.width() as i32, img.height() as i32,
-        0, format, gl::UNSIGNED_BYTE, &data[0] as *const u8 as *const c_void);
-
-    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
-    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-
-    textureID
-}
\ No newline at end of file
+/// OBJ files index positions, normals and texture coordinates separately.
+/// Builds one vertex per distinct (position, normal, uv) combination that the faces use.
+/// Also returns whether the mesh had normals at all.
+fn unify_obj_vertices(mesh: &tobj::Mesh, report: &mut MeshReport) -> (Vec<Vertex>, Vec<u32>, bool) {
+    let (p, n, t) = (&mesh.positions, &mesh.normals, &mesh.texcoords);
+    let has_normals = !n.is_empty() && mesh.normal_indices.len() == mesh.indices.len();
+    let has_tex_coords = !t.is_empty() && mesh.texcoord_indices.len() == mesh.indices.len();
+    report.defaulted_tex_coords = !has_tex_coords;
+    report.vertices_before = mesh.indices.len();
+    report.indices_before = mesh.indices.len();
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::with_capacity(mesh.indices.len());
+    let mut seen: HashMap<(u32, u32, u32), u32> = HashMap::new();
+    for corner in 0..mesh.indices.len() {
+        let pi = mesh.indices[corner];
+        let ni = if has_normals { mesh.normal_indices[corner] } else { 0 };
+        let ti = if has_tex_coords { mesh.texcoord_indices[corner] } else { 0 };
+        let index = *seen.entry((pi, ni, ti)).or_insert_with(|| {
+            let (pi, ni, ti) = (pi as usize, ni as usize, ti as usize);
+            vertices.push(Vertex {
+                position:  vec3(p[pi*3], p[pi*3+1], p[pi*3+2]),
+                normal:    if has_normals { vec3(n[ni*3], n[ni*3+1], n[ni*3+2]) } else { vec3(0.0, 0.0, 0.0) },
+                tex_coords: if has_tex_coords { vec2(t[ti*2], t[ti*2+1]) } else { vec2(0.0, 0.0) },
+                ..Vertex::default()
+            });
+            (vertices.len() - 1) as u32
+        });
+        indices.push(index);
+    }
+    (vertices, indices, has_normals)
+}
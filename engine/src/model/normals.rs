@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use cgmath::{ Vector3, Vector4, vec3 };
+
+use super::mesh::Vertex;
+
+/// How normals are generated for meshes that come without them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalMode {
+    /// Every triangle gets its own face normal, vertices are split per face.
+    Flat,
+    /// Normals are averaged over the faces sharing a vertex, as long as the
+    /// angle between the faces is below `crease_angle` (in degrees).
+    /// Faces meeting at a sharper angle keep a hard edge.
+    Smooth { crease_angle: f32, weighting: NormalWeighting }
+}
+
+/// How much each face contributes to a smoothed vertex normal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalWeighting {
+    /// Every face counts the same.
+    Uniform,
+    /// Bigger faces count more.
+    Area,
+    /// Faces count by the angle of their corner at the vertex.
+    Angle,
+    /// Area and corner angle multiplied.
+    AreaAngle
+}
+
+impl Default for NormalMode {
+    fn default() -> Self {
+        NormalMode::Smooth {
+            crease_angle: 60.0,
+            weighting: NormalWeighting::AreaAngle
+        }
+    }
+}
+
+/// Computes normals for a triangle list in place.
+/// Vertices are split wherever a vertex ends up with more than one normal
+/// (always for `Flat`, along creases for `Smooth`), so both lists may grow.
+pub fn generate_normals(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, mode: NormalMode) {
+    let num_triangles = indices.len() / 3;
+    indices.truncate(num_triangles * 3);
+
+    // per triangle: unit face normal, area and the angle at each corner
+    let mut face_normals: Vec<Vector3<f32>> = Vec::with_capacity(num_triangles);
+    let mut face_areas: Vec<f32> = Vec::with_capacity(num_triangles);
+    let mut corner_angles: Vec<f32> = Vec::with_capacity(num_triangles * 3);
+    for tri in indices.chunks(3) {
+        let p0 = vertices[tri[0] as usize].position;
+        let p1 = vertices[tri[1] as usize].position;
+        let p2 = vertices[tri[2] as usize].position;
+        let cross = (p1 - p0).cross(p2 - p0);
+        let length = cross.magnitude();
+        face_normals.push(if length > 0.0 { cross / length } else { Vector3::zero() });
+        face_areas.push(length * 0.5);
+        corner_angles.push(angle_between(p1 - p0, p2 - p0));
+        corner_angles.push(angle_between(p2 - p1, p0 - p1));
+        corner_angles.push(angle_between(p0 - p2, p1 - p2));
+    }
+
+    let mut new_vertices: Vec<Vertex> = Vec::with_capacity(vertices.len());
+    let mut new_indices: Vec<u32> = Vec::with_capacity(indices.len());
+
+    match mode {
+        NormalMode::Flat => {
+            for (t, tri) in indices.chunks(3).enumerate() {
+                for &i in tri {
+                    new_indices.push(new_vertices.len() as u32);
+                    new_vertices.push(Vertex {
+                        normal: face_normals[t],
+                        ..vertices[i as usize]
+                    });
+                }
+            }
+        }
+        NormalMode::Smooth { crease_angle, weighting } => {
+            let cos_crease = crease_angle.to_radians().cos();
+
+            // triangles around each position, vertices that only differ in uv still share faces
+            let mut position_ids: HashMap<[u32; 3], usize> = HashMap::new();
+            let mut vertex_position: Vec<usize> = Vec::with_capacity(vertices.len());
+            for vertex in vertices.iter() {
+                let key = position_key(vertex.position);
+                let next_id = position_ids.len();
+                vertex_position.push(*position_ids.entry(key).or_insert(next_id));
+            }
+            let mut faces_at: Vec<Vec<usize>> = vec![Vec::new(); position_ids.len()];
+            for (corner, &i) in indices.iter().enumerate() {
+                faces_at[vertex_position[i as usize]].push(corner);
+            }
+
+            // reuse a vertex when the same source vertex gets the same normal again
+            let mut emitted: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+            for (corner, &i) in indices.iter().enumerate() {
+                let t = corner / 3;
+                let own = face_normals[t];
+                let mut normal = Vector3::zero();
+                for &other in &faces_at[vertex_position[i as usize]] {
+                    let other_normal = face_normals[other / 3];
+                    if other / 3 != t && own.dot(other_normal) < cos_crease {
+                        continue;
+                    }
+                    let weight = match weighting {
+                        NormalWeighting::Uniform => 1.0,
+                        NormalWeighting::Area => face_areas[other / 3],
+                        NormalWeighting::Angle => corner_angles[other],
+                        NormalWeighting::AreaAngle => face_areas[other / 3] * corner_angles[other]
+                    };
+                    normal += other_normal * weight;
+                }
+                let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { own };
+
+                let key = (i, position_key(normal));
+                let index = match emitted.get(&key) {
+                    Some(&index) => index,
+                    None => {
+                        let index = new_vertices.len() as u32;
+                        new_vertices.push(Vertex {
+                            normal,
+                            ..vertices[i as usize]
+                        });
+                        emitted.insert(key, index);
+                        index
+                    }
+                };
+                new_indices.push(index);
+            }
+        }
+    }
+
+    *vertices = new_vertices;
+    *indices = new_indices;
+}
+
+/// Computes tangents from the texture coordinates, averaged over the triangles sharing a vertex
+/// and made perpendicular to the vertex normal. Call after the normals are final.
+/// Vertices whose texture coordinates give no direction get an arbitrary tangent perpendicular to the normal.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents: Vec<Vector3<f32>> = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents: Vec<Vector3<f32>> = vec![Vector3::zero(); vertices.len()];
+    for tri in indices.chunks(3) {
+        if tri.len() < 3 {
+            break;
+        }
+        let (v0, v1, v2) = (&vertices[tri[0] as usize], &vertices[tri[1] as usize], &vertices[tri[2] as usize]);
+        let (e1, e2) = (v1.position - v0.position, v2.position - v0.position);
+        let (d1, d2) = (v1.tex_coords - v0.tex_coords, v2.tex_coords - v0.tex_coords);
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < 1e-12 {
+            continue;
+        }
+        // not divided by det on purpose: bigger triangles weigh more, the sign keeps the direction
+        let sign = det.signum();
+        let tangent = (e1 * d2.y - e2 * d1.y) * sign;
+        let bitangent = (e2 * d1.x - e1 * d2.x) * sign;
+        for &i in tri {
+            tangents[i as usize] += tangent;
+            bitangents[i as usize] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let n = vertex.normal;
+        // Gram-Schmidt against the normal
+        let mut t = tangents[i] - n * n.dot(tangents[i]);
+        if t.magnitude2() < 1e-12 {
+            let axis = if n.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            t = axis - n * n.dot(axis);
+        }
+        let t = t.normalize();
+        let w = if n.cross(t).dot(bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = Vector4::new(t.x, t.y, t.z, w);
+    }
+}
+
+fn angle_between(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let denom = a.magnitude() * b.magnitude();
+    if denom > 0.0 {
+        (a.dot(b) / denom).max(-1.0).min(1.0).acos()
+    } else {
+        0.0
+    }
+}
+
+fn position_key(v: Vector3<f32>) -> [u32; 3] {
+    // +0.0 so -0.0 and 0.0 hash the same
+    let v = v + vec3(0.0, 0.0, 0.0);
+    [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+}
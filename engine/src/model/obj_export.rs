@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Vector4};
+use gl;
+use image;
+
+use crate::model::material::Material;
+use crate::model::mesh::Texture;
+use crate::model::model::{Model, ModelFormat};
+
+/// What to do with the texture files a material uses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureExport {
+    /// Write the original texture path, made absolute, into the MTL.
+    Reference,
+    /// Put a copy of every texture next to the OBJ and refer to it by file name.
+    Copy
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExportOptions {
+    pub textures: TextureExport
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            textures: TextureExport::Copy
+        }
+    }
+}
+
+impl Model {
+    /// Writes the model to `path` as OBJ, with the materials in an MTL file of the same name.
+    /// OBJ has no hierarchy, so node transforms are baked into positions and normals and
+    /// every node with meshes becomes an object named after the node.
+    /// Vertex colors are not written.
+    pub fn export_obj(&self, path: &str, options: ExportOptions) -> io::Result<()> {
+        let path = Path::new(path);
+        let directory = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let mtl_name = format!("{}.mtl", path.file_stem().and_then(|s| s.to_str()).unwrap_or("model"));
+
+        let material_names = material_names(&self.materials);
+        self.write_mtl(&directory.join(&mtl_name), &directory, &material_names, options)?;
+
+        let mut obj = BufWriter::new(File::create(path)?);
+        writeln!(obj, "# exported by engine")?;
+        writeln!(obj, "mtllib {}", mtl_name)?;
+
+        // OBJ indices are 1 based and global over the whole file
+        let mut offset: u32 = 1;
+        let mut names: HashMap<String, u32> = HashMap::new();
+        let mut stack: Vec<(usize, Matrix4<f32>)> = self.root_nodes.iter().rev()
+            .map(|&root| (root, Matrix4::identity()))
+            .collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let transform = parent * node.transform;
+            // normals go through the inverse transpose so non-uniform scale keeps them perpendicular
+            let upper = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+            let normal_matrix = upper.invert().map(|m| m.transpose()).unwrap_or(upper);
+
+            for (m, &mesh_index) in node.meshes.iter().enumerate() {
+                let mesh = &self.meshes[mesh_index];
+
+                // object names have to be unique or the importer merges them
+                let base = if node.meshes.len() > 1 { format!("{}.{}", node.name, m) } else { node.name.clone() };
+                let count = names.entry(base.clone()).or_insert(0);
+                let name = if *count == 0 { base.clone() } else { format!("{}.{}", base, count) };
+                *count += 1;
+                writeln!(obj, "o {}", name)?;
+
+                for vertex in &mesh.vertices {
+                    let p = transform * Vector4::new(vertex.position.x, vertex.position.y, vertex.position.z, 1.0);
+                    writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?;
+                }
+                for vertex in &mesh.vertices {
+                    writeln!(obj, "vt {} {}", vertex.tex_coords.x, vertex.tex_coords.y)?;
+                }
+                for vertex in &mesh.vertices {
+                    let n = normal_matrix * vertex.normal;
+                    let n = if n.magnitude2() > 0.0 { n.normalize() } else { n };
+                    writeln!(obj, "vn {} {} {}", n.x, n.y, n.z)?;
+                }
+
+                if let Some(material_id) = mesh.material_id {
+                    writeln!(obj, "usemtl {}", material_names[material_id])?;
+                }
+                for tri in mesh.indices.chunks(3) {
+                    if tri.len() < 3 {
+                        break;
+                    }
+                    let (a, b, c) = (tri[0] + offset, tri[1] + offset, tri[2] + offset);
+                    writeln!(obj, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+                }
+                offset += mesh.vertices.len() as u32;
+            }
+
+            for &child in node.children.iter().rev() {
+                stack.push((child, transform));
+            }
+        }
+        obj.flush()
+    }
+
+    fn write_mtl(&self, path: &Path, directory: &Path, material_names: &[String], options: ExportOptions) -> io::Result<()> {
+        let mut mtl = BufWriter::new(File::create(path)?);
+        // what each texture path was written as, several materials may share one
+        let mut written: HashMap<String, String> = HashMap::new();
+        // file names taken in `directory`, textures from different directories may share one
+        let mut used: HashSet<String> = HashSet::new();
+
+        writeln!(mtl, "# exported by engine")?;
+        for (material, name) in self.materials.iter().zip(material_names) {
+            writeln!(mtl)?;
+            writeln!(mtl, "newmtl {}", name)?;
+            let a = material.ambient;
+            let d = material.base_color;
+            let s = material.specular;
+            writeln!(mtl, "Ka {} {} {}", a[0], a[1], a[2])?;
+            writeln!(mtl, "Kd {} {} {}", d[0], d[1], d[2])?;
+            writeln!(mtl, "Ks {} {} {}", s[0], s[1], s[2])?;
+            writeln!(mtl, "Ns {}", material.shininess)?;
+            writeln!(mtl, "Ni {}", material.ior)?;
+            writeln!(mtl, "d {}", d[3])?;
+
+            for texture in &material.textures {
+                let statement = match texture.type_.as_str() {
+                    "texture_diffuse" => "map_Kd",
+                    "texture_specular" => "map_Ks",
+                    "texture_normal" => "map_Bump",
+                    _ => continue // MTL has nowhere to put PBR maps
+                };
+                let file = match written.get(&texture.path) {
+                    Some(file) => file.clone(),
+                    None => {
+                        let file = self.export_texture(texture, directory, &mut used, options)?;
+                        written.insert(texture.path.clone(), file.clone());
+                        file
+                    }
+                };
+                let clamp = texture.sampler.map_or(false, |sampler| sampler.settings.wrap_s == gl::CLAMP_TO_EDGE);
+                writeln!(mtl, "{} {}{}", statement, if clamp { "-clamp on " } else { "" }, file)?;
+            }
+        }
+        mtl.flush()
+    }
+
+    /// Returns the path to write into the MTL for `texture`. Copies get a file name not
+    /// in `used` yet, which they are added to.
+    fn export_texture(&self, texture: &Texture, directory: &Path, used: &mut HashSet<String>,
+                      options: ExportOptions) -> io::Result<String> {
+        let source = Path::new(&self.directory).join(&texture.path);
+        // embedded images have no file, and glTF textures are stored the other way up from
+        // what the OBJ loader expects, so both are written back out of GL instead
+        let from_gl = texture.path.starts_with('#') || self.source_format == ModelFormat::Gltf;
+
+        if options.textures == TextureExport::Reference && !from_gl {
+            let absolute = fs::canonicalize(&source).unwrap_or(source);
+            return Ok(absolute.to_string_lossy().into_owned());
+        }
+
+        let file_name: PathBuf = if from_gl {
+            let stem = Path::new(texture.path.trim_start_matches('#'))
+                .file_stem().and_then(|s| s.to_str()).unwrap_or("texture").to_string();
+            format!("{}_{}.png", stem, texture.id).into()
+        } else {
+            Path::new(&texture.path).file_name().map(PathBuf::from).unwrap_or_else(|| format!("texture_{}", texture.id).into())
+        };
+        let file_name = unique_file_name(&file_name, used);
+        let target = directory.join(&file_name);
+        if from_gl {
+            unsafe { save_gl_texture(texture.id, &target)? };
+        } else if fs::canonicalize(&source).ok() != fs::canonicalize(&target).ok() {
+            fs::copy(&source, &target)?;
+        }
+        Ok(file_name.to_string_lossy().into_owned())
+    }
+}
+
+/// `file_name`, or with a number appended to the stem if it is in `used` already.
+fn unique_file_name(file_name: &Path, used: &mut HashSet<String>) -> PathBuf {
+    let stem = file_name.file_stem().and_then(|s| s.to_str()).unwrap_or("texture");
+    let extension = file_name.extension().and_then(|e| e.to_str()).map(|e| format!(".{}", e)).unwrap_or_default();
+    let mut name = file_name.to_string_lossy().into_owned();
+    let mut count = 1;
+    while used.contains(&name) {
+        name = format!("{}_{}{}", stem, count, extension);
+        count += 1;
+    }
+    used.insert(name.clone());
+    PathBuf::from(name)
+}
+
+/// A name per material to write after `newmtl`. Names have to be unique or the importer
+/// merges the materials, later ones get a number appended.
+fn material_names(materials: &[Material]) -> Vec<String> {
+    let mut used: HashSet<String> = HashSet::new();
+    materials.iter().enumerate().map(|(index, material)| {
+        let base = if material.name.is_empty() || material.name.contains(char::is_whitespace) {
+            format!("material{}", index)
+        } else {
+            material.name.clone()
+        };
+        let mut name = base.clone();
+        let mut count = 1;
+        while used.contains(&name) {
+            name = format!("{}.{}", base, count);
+            count += 1;
+        }
+        used.insert(name.clone());
+        name
+    }).collect()
+}
+
+/// Reads level 0 of a 2D texture back as RGBA and saves it as PNG, last row first,
+/// matching the flip the OBJ loader does when it reads the file again.
+unsafe fn save_gl_texture(id: u32, path: &Path) -> io::Result<()> {
+    let (mut width, mut height) = (0, 0);
+    gl::BindTexture(gl::TEXTURE_2D, id);
+    gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+    gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut height);
+    let (width, height) = (width as usize, height as usize);
+
+    let mut pixels = vec![0u8; width * height * 4];
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::GetTexImage(gl::TEXTURE_2D, 0, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut c_void);
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    let row = width * 4;
+    let flipped: Vec<u8> = pixels.chunks(row).rev().flat_map(|r| r.iter().cloned()).collect();
+    image::save_buffer(path, &flipped, width as u32, height as u32, image::ColorType::RGBA(8))
+}
@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::mesh::Vertex;
+
+/// Size of the FIFO post-transform cache the metrics are measured against.
+/// Real hardware varies, 16 is a reasonable middle ground.
+const MEASURE_CACHE_SIZE: usize = 16;
+/// Size of the LRU cache the Forsyth scoring models.
+const FORSYTH_CACHE_SIZE: usize = 32;
+
+/// Post-transform vertex cache efficiency of an index list.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheMetrics {
+    /// Average cache miss ratio: vertices transformed per triangle. 0.5 is ideal for big grids, 3 is worst.
+    pub acmr: f32,
+    /// Average transform to vertex ratio: vertices transformed per vertex. 1 is ideal.
+    pub atvr: f32
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OptimizationReport {
+    pub before: CacheMetrics,
+    pub after: CacheMetrics
+}
+
+/// Reorders triangles for the vertex cache, then in clusters to reduce overdraw,
+/// then reorders vertices in the order they are first used.
+pub fn optimize_mesh(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) -> OptimizationReport {
+    let before = cache_metrics(indices, vertices.len());
+    optimize_vertex_cache(indices, vertices.len());
+    optimize_overdraw(indices, vertices, 1.05);
+    optimize_vertex_fetch(vertices, indices);
+    OptimizationReport {
+        before,
+        after: cache_metrics(indices, vertices.len())
+    }
+}
+
+/// Simulates a FIFO cache of `MEASURE_CACHE_SIZE` vertices over the triangle list.
+pub fn cache_metrics(indices: &[u32], vertex_count: usize) -> CacheMetrics {
+    let triangles = indices.len() / 3;
+    if triangles == 0 {
+        return CacheMetrics::default();
+    }
+    let mut cache: VecDeque<u32> = VecDeque::with_capacity(MEASURE_CACHE_SIZE);
+    let mut used = vec![false; vertex_count];
+    let mut misses = 0;
+    for &index in &indices[..triangles * 3] {
+        used[index as usize] = true;
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == MEASURE_CACHE_SIZE {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+    let unique = used.iter().filter(|&&u| u).count().max(1);
+    CacheMetrics {
+        acmr: misses as f32 / triangles as f32,
+        atvr: misses as f32 / unique as f32
+    }
+}
+
+/// Tom Forsyth's linear-speed vertex cache optimisation.
+/// Greedily emits the triangle whose vertices score highest, favouring vertices that are
+/// in the cache and vertices with few triangles left.
+pub fn optimize_vertex_cache(indices: &mut Vec<u32>, vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // triangles around each vertex
+    let mut live = vec![0u32; vertex_count];
+    for &index in &indices[..triangle_count * 3] {
+        live[index as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for v in 0..vertex_count {
+        offsets[v + 1] = offsets[v] + live[v] as usize;
+    }
+    let mut adjacency = vec![0usize; offsets[vertex_count]];
+    let mut fill = offsets.clone();
+    for t in 0..triangle_count {
+        for k in 0..3 {
+            let v = indices[t * 3 + k] as usize;
+            adjacency[fill[v]] = t;
+            fill[v] += 1;
+        }
+    }
+
+    let mut cache_position: Vec<i32> = vec![-1; vertex_count];
+    let mut vertex_score: Vec<f32> = (0..vertex_count).map(|v| forsyth_score(-1, live[v])).collect();
+    let mut triangle_score: Vec<f32> = (0..triangle_count)
+        .map(|t| (0..3).map(|k| vertex_score[indices[t * 3 + k] as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(FORSYTH_CACHE_SIZE + 3);
+    let mut output: Vec<u32> = Vec::with_capacity(triangle_count * 3);
+    let mut next_unemitted = 0;
+
+    let mut best = best_triangle(&triangle_score, &emitted, 0..triangle_count);
+    while let Some(t) = best {
+        emitted[t] = true;
+        let tri = [indices[t * 3], indices[t * 3 + 1], indices[t * 3 + 2]];
+        output.extend_from_slice(&tri);
+
+        // move the triangle's vertices to the front of the cache, drop the triangle from their adjacency
+        for &v in tri.iter().rev() {
+            if let Some(pos) = cache.iter().position(|&c| c == v) {
+                cache.remove(pos);
+            }
+            cache.insert(0, v);
+
+            let v = v as usize;
+            let range = offsets[v]..offsets[v] + live[v] as usize;
+            if let Some(pos) = adjacency[range.clone()].iter().position(|&a| a == t) {
+                adjacency.swap(range.start + pos, range.end - 1);
+            }
+            live[v] -= 1;
+        }
+
+        // rescore everything in (or just pushed out of) the cache
+        for (pos, &v) in cache.iter().enumerate() {
+            let v = v as usize;
+            cache_position[v] = if pos < FORSYTH_CACHE_SIZE { pos as i32 } else { -1 };
+            let score = forsyth_score(cache_position[v], live[v]);
+            let delta = score - vertex_score[v];
+            vertex_score[v] = score;
+            for &a in &adjacency[offsets[v]..offsets[v] + live[v] as usize] {
+                triangle_score[a] += delta;
+            }
+        }
+        cache.truncate(FORSYTH_CACHE_SIZE);
+
+        // next triangle: best one touching the cache, otherwise the first one left
+        let candidates = cache.iter().flat_map(|&v| {
+            let v = v as usize;
+            adjacency[offsets[v]..offsets[v] + live[v] as usize].iter().cloned()
+        });
+        best = best_triangle(&triangle_score, &emitted, candidates);
+        if best.is_none() {
+            while next_unemitted < triangle_count && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            if next_unemitted < triangle_count {
+                best = Some(next_unemitted);
+            }
+        }
+    }
+
+    *indices = output;
+}
+
+fn best_triangle<I: Iterator<Item = usize>>(scores: &[f32], emitted: &[bool], candidates: I) -> Option<usize> {
+    let mut best: Option<usize> = None;
+    for t in candidates {
+        if !emitted[t] && best.map_or(true, |b| scores[t] > scores[b]) {
+            best = Some(t);
+        }
+    }
+    best
+}
+
+fn forsyth_score(cache_position: i32, live_triangles: u32) -> f32 {
+    const LAST_TRIANGLE_SCORE: f32 = 0.75;
+    const CACHE_DECAY_POWER: f32 = 1.5;
+    const VALENCE_BOOST_SCALE: f32 = 2.0;
+    const VALENCE_BOOST_POWER: f32 = 0.5;
+
+    if live_triangles == 0 {
+        return -1.0;
+    }
+    let mut score = 0.0;
+    if cache_position >= 0 {
+        if cache_position < 3 {
+            // the last triangle's vertices get a fixed score so it is not simply repeated
+            score = LAST_TRIANGLE_SCORE;
+        } else {
+            let scale = 1.0 / (FORSYTH_CACHE_SIZE - 3) as f32;
+            score = (1.0 - (cache_position - 3) as f32 * scale).powf(CACHE_DECAY_POWER);
+        }
+    }
+    // finish off vertices with few triangles left
+    score + VALENCE_BOOST_SCALE * (live_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Splits a cache optimised index list into clusters and sorts the clusters so the ones
+/// facing outwards, which tend to occlude the rest, are drawn first.
+/// Clusters are only cut where the cache would be cold anyway, as long as their own
+/// ACMR stays within `threshold` of the whole mesh's.
+pub fn optimize_overdraw(indices: &mut Vec<u32>, vertices: &[Vertex], threshold: f32) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count < 2 {
+        return;
+    }
+    let overall = cache_metrics(indices, vertices.len()).acmr;
+
+    // cluster boundaries: triangles where all three vertices miss the cache
+    let mut starts: Vec<usize> = vec![0];
+    let mut cache: VecDeque<u32> = VecDeque::with_capacity(MEASURE_CACHE_SIZE);
+    let mut cluster_misses = 0;
+    for t in 0..triangle_count {
+        let mut misses = 0;
+        for k in 0..3 {
+            let index = indices[t * 3 + k];
+            if !cache.contains(&index) {
+                misses += 1;
+                if cache.len() == MEASURE_CACHE_SIZE {
+                    cache.pop_front();
+                }
+                cache.push_back(index);
+            }
+        }
+        let cluster_start = *starts.last().unwrap();
+        let cluster_length = t - cluster_start;
+        if misses == 3 && t > cluster_start
+            && cluster_misses as f32 / cluster_length as f32 <= overall * threshold {
+            starts.push(t);
+            cluster_misses = 0;
+        }
+        cluster_misses += misses;
+    }
+    starts.push(triangle_count);
+
+    // whole mesh centroid, weighted by triangle area
+    let mut centroid = Vector3::zero();
+    let mut total_area = 0.0;
+    for tri in indices.chunks(3).take(triangle_count) {
+        let (c, n) = triangle_centroid_normal(vertices, tri);
+        let area = n.magnitude();
+        centroid += c * area;
+        total_area += area;
+    }
+    if total_area > 0.0 {
+        centroid /= total_area;
+    }
+
+    // sort key: how much the cluster's average normal points away from the centroid
+    let mut clusters: Vec<(f32, usize, usize)> = Vec::with_capacity(starts.len() - 1);
+    for w in starts.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        let mut cluster_centroid = Vector3::zero();
+        let mut cluster_normal = Vector3::zero();
+        let mut area = 0.0;
+        for tri in indices[start * 3..end * 3].chunks(3) {
+            let (c, n) = triangle_centroid_normal(vertices, tri);
+            cluster_centroid += c * n.magnitude();
+            cluster_normal += n;
+            area += n.magnitude();
+        }
+        if area > 0.0 {
+            cluster_centroid /= area;
+        }
+        let key = if cluster_normal.magnitude2() > 0.0 {
+            (cluster_centroid - centroid).dot(cluster_normal.normalize())
+        } else {
+            0.0
+        };
+        clusters.push((key, start, end));
+    }
+    // stable, so clusters with equal keys keep their cache friendly order
+    clusters.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    for (_, start, end) in clusters {
+        output.extend_from_slice(&indices[start * 3..end * 3]);
+    }
+    *indices = output;
+}
+
+/// Centroid and area weighted (unnormalised) normal of a triangle.
+fn triangle_centroid_normal(vertices: &[Vertex], tri: &[u32]) -> (Vector3<f32>, Vector3<f32>) {
+    let p0 = vertices[tri[0] as usize].position;
+    let p1 = vertices[tri[1] as usize].position;
+    let p2 = vertices[tri[2] as usize].position;
+    ((p0 + p1 + p2) / 3.0, (p1 - p0).cross(p2 - p0) * 0.5)
+}
+
+/// Reorders vertices in the order the index list first references them, so the
+/// vertex fetch walks memory mostly forwards. Unreferenced vertices are dropped.
+pub fn optimize_vertex_fetch(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    let mut remap: Vec<u32> = vec![u32::max_value(); vertices.len()];
+    let mut reordered: Vec<Vertex> = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let old = *index as usize;
+        if remap[old] == u32::max_value() {
+            remap[old] = reordered.len() as u32;
+            reordered.push(vertices[old]);
+        }
+        *index = remap[old];
+    }
+    *vertices = reordered;
+}
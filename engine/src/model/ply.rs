@@ -0,0 +1,296 @@
+use std::fs;
+use std::path::Path;
+use std::str;
+
+use super::mesh::Vertex;
+use super::model::{MeshData, MeshReport};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8, U8, I16, U16, I32, U32, F32, F64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, String> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return Err(format!("unknown property type '{}'", name))
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8
+        }
+    }
+
+    /// What a color channel of this type is divided by to land in 0..1.
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::U8 | ScalarType::I8 => 255.0,
+            ScalarType::U16 | ScalarType::I16 => 65535.0,
+            _ => 1.0
+        }
+    }
+}
+
+enum Property {
+    Scalar { name: String, type_: ScalarType },
+    List { name: String, count_type: ScalarType, item_type: ScalarType }
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+/// Reads values one at a time from the body of an ASCII or binary PLY file.
+struct BodyReader<'a> {
+    encoding: Encoding,
+    data: &'a [u8],
+    pos: usize,
+    tokens: str::SplitWhitespace<'a>
+}
+
+impl<'a> BodyReader<'a> {
+    fn read(&mut self, type_: ScalarType) -> Result<f64, String> {
+        if self.encoding == Encoding::Ascii {
+            let token = self.tokens.next().ok_or("unexpected end of file")?;
+            return token.parse::<f64>().map_err(|_| format!("invalid number '{}'", token));
+        }
+
+        let size = type_.size();
+        if self.pos + size > self.data.len() {
+            return Err("unexpected end of file".into());
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.pos..self.pos + size]);
+        self.pos += size;
+        if self.encoding == Encoding::BinaryBigEndian {
+            bytes[..size].reverse();
+        }
+        Ok(match type_ {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// Loads the "vertex" and "face" elements of an ASCII or binary PLY file.
+/// Picks up normals (nx, ny, nz), colors (red, green, blue, alpha) and texture
+/// coordinates (s/t, u/v or texture_u/texture_v) when the vertices have them.
+/// Polygons are triangulated as fans.
+pub fn load_ply(path: &Path) -> Result<MeshData, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    let (encoding, elements, body_start) = parse_header(&data)?;
+
+    let body = &data[body_start..];
+    let mut reader = BodyReader {
+        encoding,
+        data: body,
+        pos: 0,
+        tokens: if encoding == Encoding::Ascii {
+            str::from_utf8(body).map_err(|_| "ASCII body is not valid text")?.split_whitespace()
+        } else {
+            "".split_whitespace()
+        }
+    };
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let (mut has_normals, mut has_tex_coords) = (false, false);
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                has_normals = element.has("nx") && element.has("ny") && element.has("nz");
+                has_tex_coords = (element.has("s") && element.has("t"))
+                    || (element.has("u") && element.has("v"))
+                    || (element.has("texture_u") && element.has("texture_v"));
+                vertices.reserve(element.count);
+                for _ in 0..element.count {
+                    let mut vertex = Vertex::default();
+                    for property in &element.properties {
+                        match property {
+                            Property::Scalar { name, type_ } => {
+                                let value = reader.read(*type_)?;
+                                let v = value as f32;
+                                let c = (value / type_.color_scale()) as f32;
+                                match name.as_str() {
+                                    "x" => vertex.position.x = v,
+                                    "y" => vertex.position.y = v,
+                                    "z" => vertex.position.z = v,
+                                    "nx" => vertex.normal.x = v,
+                                    "ny" => vertex.normal.y = v,
+                                    "nz" => vertex.normal.z = v,
+                                    "s" | "u" | "texture_u" => vertex.tex_coords.x = v,
+                                    "t" | "v" | "texture_v" => vertex.tex_coords.y = v,
+                                    "red" | "r" => vertex.color.x = c,
+                                    "green" | "g" => vertex.color.y = c,
+                                    "blue" | "b" => vertex.color.z = c,
+                                    "alpha" | "a" => vertex.color.w = c,
+                                    _ => {}
+                                }
+                            }
+                            Property::List { count_type, item_type, .. } => {
+                                skip_list(&mut reader, *count_type, *item_type)?;
+                            }
+                        }
+                    }
+                    vertices.push(vertex);
+                }
+            }
+            "face" => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        match property {
+                            Property::List { name, count_type, item_type }
+                                if name == "vertex_indices" || name == "vertex_index" => {
+                                let count = reader.read(*count_type)? as usize;
+                                let mut polygon = Vec::with_capacity(count);
+                                for _ in 0..count {
+                                    polygon.push(reader.read(*item_type)? as u32);
+                                }
+                                for i in 1..count.saturating_sub(1) {
+                                    indices.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+                                }
+                            }
+                            Property::List { count_type, item_type, .. } => {
+                                skip_list(&mut reader, *count_type, *item_type)?;
+                            }
+                            Property::Scalar { type_, .. } => {
+                                reader.read(*type_)?;
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                // edges, materials etc. still have to be read past in binary files
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        match property {
+                            Property::Scalar { type_, .. } => { reader.read(*type_)?; }
+                            Property::List { count_type, item_type, .. } => {
+                                skip_list(&mut reader, *count_type, *item_type)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(&bad) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+        return Err(format!("face references vertex {} of {}", bad, vertices.len()));
+    }
+
+    let report = MeshReport {
+        name: path.file_stem().and_then(|s| s.to_str()).unwrap_or("ply").into(),
+        defaulted_tex_coords: !has_tex_coords,
+        vertices_before: vertices.len(),
+        indices_before: indices.len(),
+        ..MeshReport::default()
+    };
+    Ok(MeshData { vertices, indices, has_normals, report })
+}
+
+impl Element {
+    fn has(&self, property: &str) -> bool {
+        self.properties.iter().any(|p| match p {
+            Property::Scalar { name, .. } | Property::List { name, .. } => name == property
+        })
+    }
+}
+
+fn skip_list(reader: &mut BodyReader, count_type: ScalarType, item_type: ScalarType) -> Result<(), String> {
+    let count = reader.read(count_type)? as usize;
+    for _ in 0..count {
+        reader.read(item_type)?;
+    }
+    Ok(())
+}
+
+/// Returns the encoding, the declared elements and where the body starts.
+fn parse_header(data: &[u8]) -> Result<(Encoding, Vec<Element>, usize), String> {
+    const END: &[u8] = b"end_header";
+    let end = data.windows(END.len()).position(|w| w == END).ok_or("no end_header")?;
+    // the body starts after the newline that ends "end_header"
+    let mut body_start = end + END.len();
+    while body_start < data.len() && data[body_start] != b'\n' {
+        body_start += 1;
+    }
+    body_start += 1;
+
+    let header = str::from_utf8(&data[..end]).map_err(|_| "header is not valid text")?;
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("missing 'ply' magic".into());
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(format!("unknown format '{}'", format))
+                });
+            }
+            ["element", name, count] => {
+                elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| format!("invalid element count '{}'", count))?,
+                    properties: Vec::new()
+                });
+            }
+            ["property", "list", count_type, item_type, name] => {
+                let element = elements.last_mut().ok_or("property before element")?;
+                element.properties.push(Property::List {
+                    name: name.to_string(),
+                    count_type: ScalarType::parse(count_type)?,
+                    item_type: ScalarType::parse(item_type)?
+                });
+            }
+            ["property", type_, name] => {
+                let element = elements.last_mut().ok_or("property before element")?;
+                element.properties.push(Property::Scalar {
+                    name: name.to_string(),
+                    type_: ScalarType::parse(type_)?
+                });
+            }
+            _ => {} // comment, obj_info, blank lines
+        }
+    }
+
+    let encoding = encoding.ok_or("missing format line")?;
+    Ok((encoding, elements, body_start.min(data.len())))
+}
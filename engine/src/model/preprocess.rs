@@ -0,0 +1,138 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::render::shader_path;
+
+/// Turns a GLSL file into the source handed to the compiler: puts the `#version` line and
+/// the `#define`s in front and expands `#include "file"` lines. Every file is included
+/// once, later includes of it are dropped, so include guards are not needed.
+#[derive(Clone, Debug)]
+pub struct Preprocessor {
+    /// Directories `#include` looks in after the directory of the including file.
+    pub roots: Vec<PathBuf>,
+    /// Replaces the `#version` of the files, e.g. "330 core".
+    pub version: String,
+    /// Defined in every shader, before the defines of a variant.
+    pub defines: Vec<(String, String)>
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Preprocessor {
+            roots: vec![PathBuf::from(shader_path(""))],
+            version: "330 core".into(),
+            defines: Vec::new()
+        }
+    }
+}
+
+/// Preprocessed source. `#line` directives number the lines of each file the way they
+/// are numbered in the file, with the file's index in `files` as the source string number.
+#[derive(Clone, Debug)]
+pub struct ShaderSource {
+    pub code: String,
+    /// The main file first, then the included ones in the order they were first included.
+    pub files: Vec<PathBuf>
+}
+
+impl Preprocessor {
+    pub fn process(&self, path: &Path, defines: &[(&str, &str)]) -> Result<ShaderSource, String> {
+        let mut source = ShaderSource { code: String::new(), files: Vec::new() };
+        writeln!(source.code, "#version {}", self.version).unwrap();
+        let own = self.defines.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+        for (name, value) in own.chain(defines.iter().cloned()) {
+            writeln!(source.code, "#define {} {}", name, value).unwrap();
+        }
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.expand(&path, &mut source, &mut Vec::new())?;
+        Ok(source)
+    }
+
+    /// Appends `path` to `source`. `stack` holds the files being expanded, to catch cycles.
+    fn expand(&self, path: &Path, source: &mut ShaderSource, stack: &mut Vec<PathBuf>) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let index = source.files.len();
+        source.files.push(path.to_path_buf());
+        stack.push(path.to_path_buf());
+
+        writeln!(source.code, "#line 1 {}", index).unwrap();
+        for (number, line) in text.lines().enumerate() {
+            let directive = line.trim_start();
+            if directive.starts_with("#version") {
+                // ours is already at the top, an empty line keeps the numbering
+                source.code.push('\n');
+                continue;
+            }
+            if !directive.starts_with("#include") {
+                source.code.push_str(line);
+                source.code.push('\n');
+                continue;
+            }
+
+            let location = format!("{}:{}", path.display(), number + 1);
+            let name = directive["#include".len()..].trim();
+            let name = if (name.starts_with('"') && name.ends_with('"')) || (name.starts_with('<') && name.ends_with('>')) {
+                &name[1..name.len().saturating_sub(1)]
+            } else {
+                ""
+            };
+            if name.is_empty() {
+                return Err(format!("{}: malformed #include", location));
+            }
+            let included = self.resolve(path, name).ok_or_else(|| format!("{}: cannot find {}", location, name))?;
+            if stack.contains(&included) {
+                return Err(format!("{}: {} includes itself", location, name));
+            }
+            if !source.files.contains(&included) {
+                self.expand(&included, source, stack)?;
+            }
+            writeln!(source.code, "#line {} {}", number + 2, index).unwrap();
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Looks for `name` next to `including`, then in the roots.
+    fn resolve(&self, including: &Path, name: &str) -> Option<PathBuf> {
+        let directory = including.parent().unwrap_or_else(|| Path::new(""));
+        std::iter::once(directory).chain(self.roots.iter().map(PathBuf::as_path))
+            .map(|root| root.join(name))
+            .find(|candidate| candidate.is_file())
+            .map(|found| fs::canonicalize(&found).unwrap_or(found))
+    }
+}
+
+impl ShaderSource {
+    /// Rewrites the locations in a compile log to file names. Drivers write them as
+    /// `0:12(5): error` or `ERROR: 0:12: ...` (Mesa, AMD) or `0(12) : error` (NVIDIA),
+    /// with the source string number in front.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| self.map_location(line).unwrap_or_else(|| line.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn map_location(&self, line: &str) -> Option<String> {
+        let bytes = line.as_bytes();
+        let digits = |from: usize| from + bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+        // only the first number on the line can be a location
+        let start = (0..bytes.len()).find(|&i| bytes[i].is_ascii_digit())?;
+        if start > 0 && bytes[start - 1].is_ascii_alphanumeric() {
+            return None;
+        }
+        let string_end = digits(start);
+        let separator = *bytes.get(string_end)?;
+        if separator != b':' && separator != b'(' {
+            return None;
+        }
+        let line_end = digits(string_end + 1);
+        if line_end == string_end + 1 {
+            return None;
+        }
+        let file = self.files.get(line[start..string_end].parse::<usize>().ok()?)?;
+        let rest = if separator == b'(' && bytes.get(line_end) == Some(&b')') { line_end + 1 } else { line_end };
+        Some(format!("{}{}:{}{}", &line[..start], file.display(), &line[string_end + 1..line_end], &line[rest..]))
+    }
+}
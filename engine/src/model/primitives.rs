@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::prelude::*;
+use cgmath::{ Vector3, Vector4, vec2, vec3 };
+
+use super::mesh::{ Mesh, Texture, Vertex };
+
+// Generated shapes are centered on the origin, wound counter clockwise seen from
+// outside and mapped so u runs around the shape and v from bottom to top.
+// Tangents point along increasing u.
+
+/// Vertices and indices of a generated shape.
+#[derive(Clone, Default)]
+pub struct Geometry {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>
+}
+
+impl Geometry {
+    /// Uploads the geometry.
+    pub fn into_mesh(self, textures: Vec<Texture>) -> Mesh {
+        Mesh::new(self.vertices, self.indices, textures)
+    }
+
+    /// Adds a grid of `(columns + 1) * (rows + 1)` vertices from `vertex(s, t)`, with `s` and `t`
+    /// running from 0 to 1, and the triangles between them. Triangles of zero area, such as at
+    /// the poles of a sphere, are left out.
+    fn surface<F: Fn(f32, f32) -> Vertex>(&mut self, columns: usize, rows: usize, vertex: F) {
+        let base = self.vertices.len() as u32;
+        for j in 0..=rows {
+            for i in 0..=columns {
+                self.vertices.push(vertex(i as f32 / columns as f32, j as f32 / rows as f32));
+            }
+        }
+        let stride = columns as u32 + 1;
+        for j in 0..rows as u32 {
+            for i in 0..columns as u32 {
+                let a = base + j * stride + i;
+                let (b, c, d) = (a + 1, a + stride + 1, a + stride);
+                self.triangle(a, b, c);
+                self.triangle(a, c, d);
+            }
+        }
+    }
+
+    /// Adds a flat disc of `sectors` triangles around `center`, facing `normal` (+y or -y).
+    fn disc(&mut self, center: Vector3<f32>, radius: f32, sectors: usize, normal: Vector3<f32>) {
+        let base = self.vertices.len() as u32;
+        // the cap is mapped from above for +y and from below for -y, so v runs along -z or +z
+        let v_sign = -normal.y;
+        self.vertices.push(vertex(center, normal, 0.5, 0.5, vec3(1.0, 0.0, 0.0)));
+        for i in 0..=sectors {
+            let phi = i as f32 / sectors as f32 * 2.0 * PI;
+            let (x, z) = (phi.cos(), -phi.sin());
+            let position = center + vec3(x, 0.0, z) * radius;
+            self.vertices.push(vertex(position, normal, 0.5 + 0.5 * x, 0.5 + 0.5 * z * v_sign, vec3(1.0, 0.0, 0.0)));
+        }
+        for i in 0..sectors as u32 {
+            let (a, b) = (base + 1 + i, base + 2 + i);
+            if normal.y > 0.0 {
+                self.triangle(base, a, b);
+            } else {
+                self.triangle(base, b, a);
+            }
+        }
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let p = |i: u32| self.vertices[i as usize].position;
+        if (p(b) - p(a)).cross(p(c) - p(a)).magnitude2() > 1e-12 {
+            self.indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+}
+
+fn vertex(position: Vector3<f32>, normal: Vector3<f32>, u: f32, v: f32, tangent: Vector3<f32>) -> Vertex {
+    Vertex {
+        position,
+        normal,
+        tex_coords: vec2(u, v),
+        tangent: Vector4::new(tangent.x, tangent.y, tangent.z, 1.0),
+        ..Vertex::default()
+    }
+}
+
+/// Unit direction at longitude `phi` and latitude `theta`, and the tangent along the longitude.
+fn spherical(phi: f32, theta: f32) -> (Vector3<f32>, Vector3<f32>) {
+    let direction = vec3(theta.cos() * phi.cos(), theta.sin(), -theta.cos() * phi.sin());
+    let tangent = vec3(-phi.sin(), 0.0, -phi.cos());
+    (direction, tangent)
+}
+
+/// Cube with edges of length `size`, every face split into `segments` x `segments` quads.
+/// Faces do not share vertices, so edges stay hard, and every face is mapped to the whole texture.
+pub fn cube(size: f32, segments: usize) -> Geometry {
+    let segments = segments.max(1);
+    // normal, u axis, v axis, with u x v = normal
+    let faces = [
+        (vec3( 1.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0), vec3(0.0, 1.0,  0.0)),
+        (vec3(-1.0, 0.0, 0.0), vec3(0.0, 0.0,  1.0), vec3(0.0, 1.0,  0.0)),
+        (vec3(0.0,  1.0, 0.0), vec3(1.0, 0.0,  0.0), vec3(0.0, 0.0, -1.0)),
+        (vec3(0.0, -1.0, 0.0), vec3(1.0, 0.0,  0.0), vec3(0.0, 0.0,  1.0)),
+        (vec3(0.0, 0.0,  1.0), vec3(1.0, 0.0,  0.0), vec3(0.0, 1.0,  0.0)),
+        (vec3(0.0, 0.0, -1.0), vec3(-1.0, 0.0, 0.0), vec3(0.0, 1.0,  0.0))
+    ];
+    let mut geometry = Geometry::default();
+    for &(normal, u_axis, v_axis) in &faces {
+        geometry.surface(segments, segments, |s, t| {
+            let position = (normal * 0.5 + u_axis * (s - 0.5) + v_axis * (t - 0.5)) * size;
+            vertex(position, normal, s, t, u_axis)
+        });
+    }
+    geometry
+}
+
+/// Sphere of `sectors` slices around the y axis and `stacks` rings from pole to pole.
+pub fn uv_sphere(radius: f32, sectors: usize, stacks: usize) -> Geometry {
+    let (sectors, stacks) = (sectors.max(3), stacks.max(2));
+    let mut geometry = Geometry::default();
+    geometry.surface(sectors, stacks, |s, t| {
+        let (direction, tangent) = spherical(s * 2.0 * PI, (t - 0.5) * PI);
+        vertex(direction * radius, direction, s, t, tangent)
+    });
+    geometry
+}
+
+/// Sphere made by splitting every triangle of an icosahedron into four, `subdivisions` times.
+/// Triangles are much more even than on a UV sphere. The texture seam and the poles get extra
+/// vertices so the equirectangular mapping does not wrap backwards.
+pub fn icosphere(radius: f32, subdivisions: usize) -> Geometry {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vector3<f32>> = [
+        vec3(-1.0, t, 0.0), vec3(1.0, t, 0.0), vec3(-1.0, -t, 0.0), vec3(1.0, -t, 0.0),
+        vec3(0.0, -1.0, t), vec3(0.0, 1.0, t), vec3(0.0, -1.0, -t), vec3(0.0, 1.0, -t),
+        vec3(t, 0.0, -1.0), vec3(t, 0.0, 1.0), vec3(-t, 0.0, -1.0), vec3(-t, 0.0, 1.0)
+    ].iter().map(|p| p.normalize()).collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1]
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vector3<f32>>| -> u32 {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push(((positions[a as usize] + positions[b as usize]) * 0.5).normalize());
+                positions.len() as u32 - 1
+            })
+        };
+        let mut next = Vec::with_capacity(triangles.len() * 4);
+        for &[a, b, c] in &triangles {
+            let ab = midpoint(a, b, &mut positions);
+            let bc = midpoint(b, c, &mut positions);
+            let ca = midpoint(c, a, &mut positions);
+            next.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+        }
+        triangles = next;
+    }
+
+    let u_of = |p: Vector3<f32>| {
+        let u = (-p.z).atan2(p.x) / (2.0 * PI);
+        if u < 0.0 { u + 1.0 } else { u }
+    };
+    let mut geometry = Geometry::default();
+    for p in &positions {
+        let u = u_of(*p);
+        let (_, tangent) = spherical(u * 2.0 * PI, 0.0);
+        let v = p.y.max(-1.0).min(1.0).asin() / PI + 0.5;
+        geometry.vertices.push(vertex(*p * radius, *p, u, v, tangent));
+    }
+
+    // the longitude is undefined at the poles, they get their u from the rest of the triangle
+    let is_pole = |v: &Vertex| v.normal.y.abs() > 0.9999;
+
+    // copies of seam vertices with u + 1, and per triangle copies of pole vertices
+    let mut wrapped: HashMap<u32, u32> = HashMap::new();
+    for tri in &mut triangles {
+        let us: Vec<f32> = tri.iter()
+            .map(|&i| &geometry.vertices[i as usize])
+            .filter(|v| !is_pole(v))
+            .map(|v| v.tex_coords.x)
+            .collect();
+        let span = us.iter().cloned().fold(0.0, f32::max) - us.iter().cloned().fold(1.0, f32::min);
+        if span > 0.5 {
+            for index in tri.iter_mut() {
+                let old = *index;
+                let original = geometry.vertices[old as usize];
+                if !is_pole(&original) && original.tex_coords.x < 0.5 {
+                    let vertices = &mut geometry.vertices;
+                    *index = *wrapped.entry(old).or_insert_with(|| {
+                        let mut copy = original;
+                        copy.tex_coords.x += 1.0;
+                        vertices.push(copy);
+                        vertices.len() as u32 - 1
+                    });
+                }
+            }
+        }
+        for k in 0..3 {
+            let pole = geometry.vertices[tri[k] as usize];
+            if is_pole(&pole) {
+                let (a, b) = (tri[(k + 1) % 3] as usize, tri[(k + 2) % 3] as usize);
+                let u = (geometry.vertices[a].tex_coords.x + geometry.vertices[b].tex_coords.x) * 0.5;
+                let (_, tangent) = spherical(u * 2.0 * PI, 0.0);
+                geometry.vertices.push(vertex(pole.position, pole.normal, u, pole.tex_coords.y, tangent));
+                tri[k] = geometry.vertices.len() as u32 - 1;
+            }
+        }
+        geometry.indices.extend_from_slice(tri);
+    }
+    geometry
+}
+
+/// Flat grid in the xz plane facing +y, `width` along x and `depth` along z,
+/// split into `x_segments` x `z_segments` quads.
+pub fn plane(width: f32, depth: f32, x_segments: usize, z_segments: usize) -> Geometry {
+    let mut geometry = Geometry::default();
+    geometry.surface(x_segments.max(1), z_segments.max(1), |s, t| {
+        let position = vec3((s - 0.5) * width, 0.0, (0.5 - t) * depth);
+        vertex(position, vec3(0.0, 1.0, 0.0), s, t, vec3(1.0, 0.0, 0.0))
+    });
+    geometry
+}
+
+/// Cylinder along the y axis with closed ends, `sectors` slices around and `stacks` rings along it.
+pub fn cylinder(radius: f32, height: f32, sectors: usize, stacks: usize) -> Geometry {
+    let sectors = sectors.max(3);
+    let mut geometry = Geometry::default();
+    geometry.surface(sectors, stacks.max(1), |s, t| {
+        let (direction, tangent) = spherical(s * 2.0 * PI, 0.0);
+        let position = direction * radius + vec3(0.0, (t - 0.5) * height, 0.0);
+        vertex(position, direction, s, t, tangent)
+    });
+    geometry.disc(vec3(0.0, height * 0.5, 0.0), radius, sectors, vec3(0.0, 1.0, 0.0));
+    geometry.disc(vec3(0.0, -height * 0.5, 0.0), radius, sectors, vec3(0.0, -1.0, 0.0));
+    geometry
+}
+
+/// Cone along the y axis with its base at `-height / 2` and its tip at `height / 2`.
+pub fn cone(radius: f32, height: f32, sectors: usize, stacks: usize) -> Geometry {
+    let sectors = sectors.max(3);
+    // the side normal leans up by the slope, the same at every height
+    let slope = radius.atan2(height);
+    let mut geometry = Geometry::default();
+    geometry.surface(sectors, stacks.max(1), |s, t| {
+        let phi = s * 2.0 * PI;
+        let (direction, tangent) = spherical(phi, 0.0);
+        let (normal, _) = spherical(phi, slope);
+        let position = direction * radius * (1.0 - t) + vec3(0.0, (t - 0.5) * height, 0.0);
+        vertex(position, normal, s, t, tangent)
+    });
+    geometry.disc(vec3(0.0, -height * 0.5, 0.0), radius, sectors, vec3(0.0, -1.0, 0.0));
+    geometry
+}
+
+/// Cylinder along the y axis capped with half spheres. `height` is the length of the straight
+/// part, so the whole capsule is `height + 2 * radius` tall. `stacks` rings make up each half sphere.
+pub fn capsule(radius: f32, height: f32, sectors: usize, stacks: usize) -> Geometry {
+    let (sectors, stacks) = (sectors.max(3), stacks.max(1));
+    let total = height + 2.0 * radius;
+    let rows = 2 * stacks + 1;
+    let mut geometry = Geometry::default();
+    geometry.surface(sectors, rows, |s, t| {
+        // rows 0..=stacks are the lower half sphere, the row after starts the upper one
+        let row = (t * rows as f32).round() as usize;
+        let (theta, offset) = if row <= stacks {
+            ((row as f32 / stacks as f32 - 1.0) * PI * 0.5, -height * 0.5)
+        } else {
+            ((row - stacks - 1) as f32 / stacks as f32 * PI * 0.5, height * 0.5)
+        };
+        let (direction, tangent) = spherical(s * 2.0 * PI, theta);
+        let position = direction * radius + vec3(0.0, offset, 0.0);
+        vertex(position, direction, s, (position.y + total * 0.5) / total, tangent)
+    });
+    geometry
+}
+
+/// Torus around the y axis. `major_radius` is the distance from the center to the middle of
+/// the tube, `minor_radius` the radius of the tube.
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: usize, minor_segments: usize) -> Geometry {
+    let mut geometry = Geometry::default();
+    geometry.surface(major_segments.max(3), minor_segments.max(3), |s, t| {
+        // t goes around the tube starting on the outside, so v wraps once around it
+        let (normal, tangent) = spherical(s * 2.0 * PI, t * 2.0 * PI);
+        let (ring, _) = spherical(s * 2.0 * PI, 0.0);
+        let position = ring * major_radius + normal * minor_radius;
+        vertex(position, normal, s, t, tangent)
+    });
+    geometry
+}
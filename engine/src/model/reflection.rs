@@ -0,0 +1,207 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
+
+use gl;
+use gl::types::*;
+
+/// An active uniform or vertex attribute. Arrays are listed once per element, by the
+/// element's name, and once more without the index for the first element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Variable {
+    pub location: i32,
+    /// GL type enum, e.g. FLOAT_VEC3 or SAMPLER_2D.
+    pub type_: GLenum,
+    /// Elements, 1 for anything but an array.
+    pub size: i32
+}
+
+#[derive(Clone, Debug)]
+pub struct UniformBlock {
+    pub index: u32,
+    pub binding: i32,
+    /// Bytes the block's buffer has to hold.
+    pub data_size: i32,
+    pub members: HashMap<String, Variable>
+}
+
+/// What a linked program uses, read once after linking.
+#[derive(Debug, Default)]
+pub struct ShaderReflection {
+    pub program: u32,
+    /// Uniforms outside of blocks.
+    pub uniforms: HashMap<String, Variable>,
+    pub attributes: HashMap<String, Variable>,
+    pub blocks: HashMap<String, UniformBlock>,
+    /// Uniform names already reported, each is reported once.
+    reported: RefCell<HashSet<String>>
+}
+
+impl ShaderReflection {
+    pub unsafe fn new(program: u32) -> ShaderReflection {
+        let mut reflection = ShaderReflection { program, ..ShaderReflection::default() };
+
+        let mut count = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCKS, &mut count);
+        let mut block_uniforms = HashSet::new();
+        for index in 0..count as u32 {
+            let name = read_name(program, gl::UNIFORM_BLOCK_NAME_LENGTH, |length, written, buffer| {
+                gl::GetActiveUniformBlockName(program, index, length, written, buffer)
+            }, Some(index));
+            let block_iv = |parameter| {
+                let mut value = 0;
+                gl::GetActiveUniformBlockiv(program, index, parameter, &mut value);
+                value
+            };
+            let mut indices = vec![0i32; block_iv(gl::UNIFORM_BLOCK_ACTIVE_UNIFORMS) as usize];
+            if !indices.is_empty() {
+                gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_ACTIVE_UNIFORM_INDICES, indices.as_mut_ptr());
+            }
+            let mut members = HashMap::new();
+            for &uniform in &indices {
+                let (member, variable) = active_uniform(program, uniform as u32);
+                // members have no location, they live in the block's buffer
+                members.insert(member, Variable { location: -1, ..variable });
+                block_uniforms.insert(uniform as u32);
+            }
+            reflection.blocks.insert(name, UniformBlock {
+                index,
+                binding: block_iv(gl::UNIFORM_BLOCK_BINDING),
+                data_size: block_iv(gl::UNIFORM_BLOCK_DATA_SIZE),
+                members
+            });
+        }
+
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+        for index in (0..count as u32).filter(|index| !block_uniforms.contains(index)) {
+            let (name, variable) = active_uniform(program, index);
+            insert_array(&mut reflection.uniforms, &name, variable, |element| {
+                let element = std::ffi::CString::new(element).unwrap();
+                gl::GetUniformLocation(program, element.as_ptr())
+            });
+        }
+
+        gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut count);
+        for index in 0..count as u32 {
+            let (mut size, mut type_) = (0, 0);
+            let name = read_name(program, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, |length, written, buffer| {
+                gl::GetActiveAttrib(program, index, length, written, &mut size, &mut type_, buffer)
+            }, None);
+            if name.starts_with("gl_") {
+                continue;
+            }
+            let location = gl::GetAttribLocation(program, std::ffi::CString::new(name.as_str()).unwrap().as_ptr());
+            reflection.attributes.insert(name, Variable { location, type_, size });
+        }
+        reflection
+    }
+
+    /// Location of `name` if it is an active uniform of one of the `accepted` types.
+    /// Anything else is reported, once per name, and gives -1, which GL ignores.
+    pub fn location(&self, name: &CStr, accepted: &[GLenum], setter: &str) -> i32 {
+        let name = name.to_str().unwrap_or("");
+        match self.uniforms.get(name) {
+            Some(variable) if accepted.contains(&variable.type_) => variable.location,
+            Some(variable) => {
+                self.report(name, &format!("is a {}, {} does not set that", type_name(variable.type_), setter));
+                -1
+            }
+            None => {
+                // a program that failed to build already said so
+                if self.program != 0 {
+                    self.report(name, "is not an active uniform, misspelled or unused and optimized out");
+                }
+                -1
+            }
+        }
+    }
+
+    fn report(&self, name: &str, problem: &str) {
+        if self.reported.borrow_mut().insert(name.to_string()) {
+            println!("shader {}: uniform {} {}", self.program, name, problem);
+        }
+    }
+
+    /// Checks the active attributes against `expected` locations and types, e.g. those
+    /// `Vertex` and `Instance` feed. Returns a line per attribute that does not match.
+    pub fn check_attributes(&self, expected: &[(u32, GLenum, &str)]) -> Result<(), String> {
+        let mut names: Vec<&String> = self.attributes.keys().collect();
+        names.sort();
+        let problems: Vec<String> = names.into_iter().filter_map(|name| {
+            let attribute = &self.attributes[name];
+            match expected.iter().find(|&&(location, _, _)| location as i32 == attribute.location) {
+                None => Some(format!("attribute {} at location {} is not fed by anything", name, attribute.location)),
+                Some(&(_, type_, what)) if type_ != attribute.type_ =>
+                    Some(format!("attribute {} is a {}, {} at location {} is a {}",
+                                 name, type_name(attribute.type_), what, attribute.location, type_name(type_))),
+                Some(_) => None
+            }
+        }).collect();
+        if problems.is_empty() { Ok(()) } else { Err(problems.join("\n")) }
+    }
+}
+
+/// Name, type and size of active uniform `index`. Arrays come named after their first element.
+unsafe fn active_uniform(program: u32, index: u32) -> (String, Variable) {
+    let (mut size, mut type_) = (0, 0);
+    let name = read_name(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, |length, written, buffer| {
+        gl::GetActiveUniform(program, index, length, written, &mut size, &mut type_, buffer)
+    }, None);
+    (name, Variable { location: -1, type_, size })
+}
+
+/// Adds `name` and, for an array, every element and the bare name of the first.
+unsafe fn insert_array<F: Fn(&str) -> i32>(map: &mut HashMap<String, Variable>, name: &str, variable: Variable, location: F) {
+    if variable.size <= 1 && !name.ends_with("[0]") {
+        map.insert(name.to_string(), Variable { location: location(name), ..variable });
+        return;
+    }
+    let base = name.trim_end_matches("[0]");
+    for element in 0..variable.size.max(1) {
+        let element_name = format!("{}[{}]", base, element);
+        map.insert(element_name.clone(), Variable { location: location(&element_name), ..variable });
+    }
+    map.insert(base.to_string(), Variable { location: location(base), ..variable });
+}
+
+/// Reads a name through `get`, with a buffer as long as `max_length` says. Block names
+/// have their own length per block, given through `block`.
+unsafe fn read_name<F: FnMut(GLsizei, *mut GLsizei, *mut GLchar)>(program: u32, max_length: GLenum, mut get: F, block: Option<u32>) -> String {
+    let mut length = 0;
+    match block {
+        Some(index) => gl::GetActiveUniformBlockiv(program, index, max_length, &mut length),
+        None => gl::GetProgramiv(program, max_length, &mut length)
+    }
+    let mut buffer = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    get(buffer.len() as GLsizei, &mut written, buffer.as_mut_ptr() as *mut GLchar);
+    buffer.truncate(written.max(0) as usize);
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+/// Sampler types, which take an int like INT uniforms do.
+pub const SAMPLER_TYPES: [GLenum; 14] = [
+    gl::SAMPLER_1D, gl::SAMPLER_2D, gl::SAMPLER_3D, gl::SAMPLER_CUBE,
+    gl::SAMPLER_1D_SHADOW, gl::SAMPLER_2D_SHADOW, gl::SAMPLER_1D_ARRAY, gl::SAMPLER_2D_ARRAY,
+    gl::SAMPLER_2D_ARRAY_SHADOW, gl::SAMPLER_CUBE_SHADOW, gl::SAMPLER_2D_MULTISAMPLE,
+    gl::INT_SAMPLER_2D, gl::UNSIGNED_INT_SAMPLER_2D, gl::SAMPLER_BUFFER
+];
+
+pub fn type_name(type_: GLenum) -> String {
+    match type_ {
+        gl::FLOAT => "float".into(),
+        gl::FLOAT_VEC2 => "vec2".into(),
+        gl::FLOAT_VEC3 => "vec3".into(),
+        gl::FLOAT_VEC4 => "vec4".into(),
+        gl::INT => "int".into(),
+        gl::BOOL => "bool".into(),
+        gl::FLOAT_MAT3 => "mat3".into(),
+        gl::FLOAT_MAT4 => "mat4".into(),
+        gl::SAMPLER_2D => "sampler2D".into(),
+        gl::SAMPLER_CUBE => "samplerCube".into(),
+        gl::SAMPLER_2D_ARRAY_SHADOW => "sampler2DArrayShadow".into(),
+        gl::SAMPLER_CUBE_SHADOW => "samplerCubeShadow".into(),
+        other if SAMPLER_TYPES.contains(&other) => "sampler".into(),
+        other => format!("type 0x{:X}", other)
+    }
+}
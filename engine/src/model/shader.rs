@@ -1,67 +1,105 @@
 #![allow(non_snake_case)]
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{CString, CStr};
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::ptr;
-use std::str;
+use std::time::SystemTime;
 
 use gl;
 use gl::types::*;
 
 use cgmath::{Matrix, Matrix4, Vector3};
-use cgmath::prelude::*;
+
+use super::mesh::MESH_ATTRIBUTES;
+use super::preprocess::{ Preprocessor, ShaderSource };
+use super::reflection::{ ShaderReflection, SAMPLER_TYPES };
 
 pub struct Shader {
-    pub ID: u32
+    pub ID: u32,
+    /// Active uniforms, attributes and blocks, the setters look their locations up here.
+    pub reflection: ShaderReflection,
+    /// Where the program came from, to build it again when a file changes.
+    origin: Option<ShaderOrigin>,
+    /// Last value set of every uniform, set again on the program that replaces this one.
+    values: RefCell<HashMap<String, UniformValue>>
 }
 
 #[allow(dead_code)]
 impl Shader {
     pub fn new(vertexPath: &str, fragmentPath: &str) -> Shader {
-        let mut shader = Shader { ID: 0 };
-        // 1. retrieve the vertex/fragment source code from filesystem
-        let mut vShaderFile = File::open(vertexPath)
-            .unwrap_or_else(|_| panic!("Failed to open {}", vertexPath));
-        let mut fShaderFile = File::open(fragmentPath)
-            .unwrap_or_else(|_| panic!("Failed to open {}", fragmentPath));
-        let mut vertexCode = String::new();
-        let mut fragmentCode = String::new();
-        vShaderFile
-            .read_to_string(&mut vertexCode)
-            .expect("Failed to read vertex shader");
-        fShaderFile
-            .read_to_string(&mut fragmentCode)
-            .expect("Failed to read fragment shader");
-
-        let vShaderCode = CString::new(vertexCode.as_bytes()).unwrap();
-        let fShaderCode = CString::new(fragmentCode.as_bytes()).unwrap();
-
-
-    // 2. compile shaders
-        unsafe {
-            // vertex shader
-            let vertex = gl::CreateShader(gl::VERTEX_SHADER);
-            gl::ShaderSource(vertex, 1, &vShaderCode.as_ptr(), ptr::null());
-            gl::CompileShader(vertex);
-            shader.checkCompileErrors(vertex, "VERTEX");
-            // fragment Shader
-            let fragment = gl::CreateShader(gl::FRAGMENT_SHADER);
-            gl::ShaderSource(fragment, 1, &fShaderCode.as_ptr(), ptr::null());
-            gl::CompileShader(fragment);
-            shader.checkCompileErrors(fragment, "FRAGMENT");
-            // shader Program
-            let ID = gl::CreateProgram();
-            gl::AttachShader(ID, vertex);
-            gl::AttachShader(ID, fragment);
-            gl::LinkProgram(ID);
-            shader.checkCompileErrors(ID, "PROGRAM");
-            // delete the shaders as they're linked into our program now and no longer necessary
-            gl::DeleteShader(vertex);
-            gl::DeleteShader(fragment);
-            shader.ID = ID;
+        Shader::with_defines(vertexPath, fragmentPath, &[])
+    }
+
+    /// Compiles with `defines` added after those of the default `Preprocessor`.
+    /// Errors are printed and leave a shader with program 0, which `reload_if_changed`
+    /// still builds again once the files change.
+    pub fn with_defines(vertexPath: &str, fragmentPath: &str, defines: &[(&str, &str)]) -> Shader {
+        Shader::from_origin(ShaderOrigin::new(&Preprocessor::default(), Path::new(vertexPath), Path::new(fragmentPath), defines))
+    }
+
+    /// Preprocesses, compiles and links. The error holds the compiler's log with the
+    /// locations pointing into the original files.
+    pub fn build(preprocessor: &Preprocessor, vertexPath: &Path, fragmentPath: &Path,
+                 defines: &[(&str, &str)]) -> Result<Shader, String> {
+        let mut origin = ShaderOrigin::new(preprocessor, vertexPath, fragmentPath, defines);
+        let ID = origin.link()?;
+        Ok(Shader::linked(ID, Some(origin)))
+    }
+
+    fn from_origin(mut origin: ShaderOrigin) -> Shader {
+        match origin.link() {
+            Ok(ID) => Shader::linked(ID, Some(origin)),
+            Err(err) => {
+                println!("{}", err);
+                Shader::linked(0, Some(origin))
+            }
         }
+    }
 
-        return shader
+    fn linked(ID: u32, origin: Option<ShaderOrigin>) -> Shader {
+        let reflection = if ID == 0 { ShaderReflection::default() } else { unsafe { ShaderReflection::new(ID) } };
+        Shader { ID, reflection, origin, values: RefCell::new(HashMap::new()) }
+    }
+
+    /// Builds the program again if one of its files, includes too, changed since the last
+    /// build. A new program that fails to build is reported and the old one kept. One that
+    /// builds replaces the old one, gets the uniform values the old one had and is made
+    /// current if the old one was. Returns the program that was replaced and deleted,
+    /// for `GlState::forget_program`.
+    pub fn reload_if_changed(&mut self) -> Option<u32> {
+        let origin = match self.origin.as_mut() {
+            Some(origin) if origin.changed() => origin,
+            _ => return None
+        };
+        let ID = match origin.link() {
+            Ok(ID) => ID,
+            Err(err) => {
+                println!("{}\nKeeping the previous version of {}", err, origin.fragmentPath.display());
+                return None;
+            }
+        };
+        println!("Reloaded {} and {}", origin.vertexPath.display(), origin.fragmentPath.display());
+
+        let old = mem::replace(&mut self.ID, ID);
+        unsafe {
+            let mut current = 0;
+            gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut current);
+            self.reflection = ShaderReflection::new(ID);
+
+            gl::UseProgram(ID);
+            for (name, value) in self.values.borrow().iter() {
+                let name = CString::new(name.as_str()).unwrap();
+                value.apply(self.location(&name, value));
+            }
+            gl::UseProgram(if current as u32 == old { ID } else { current as u32 });
+            if old != 0 {
+                gl::DeleteProgram(old);
+            }
+        }
+        Some(old)
     }
 
     /// activate the shader
@@ -73,54 +111,270 @@ impl Shader {
     /// utility uniform functions
     /// ------------------------------------------------------------------------
     pub unsafe fn setBool(&self, name: &CStr, value: bool) {
-        gl::Uniform1i(gl::GetUniformLocation(self.ID, name.as_ptr()), value as i32);
+        self.setUniform(name, UniformValue::Bool(value));
     }
     /// ------------------------------------------------------------------------
     pub unsafe fn setInt(&self, name: &CStr, value: i32) {
-        gl::Uniform1i(gl::GetUniformLocation(self.ID, name.as_ptr()), value);
+        self.setUniform(name, UniformValue::Int(value));
     }
     /// ------------------------------------------------------------------------
     pub unsafe fn setFloat(&self, name: &CStr, value: f32) {
-        gl::Uniform1f(gl::GetUniformLocation(self.ID, name.as_ptr()), value);
+        self.setUniform(name, UniformValue::Float(value));
+    }
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setVec2(&self, name: &CStr, x: f32, y: f32) {
+        self.setUniform(name, UniformValue::Vec2([x, y]));
     }
     /// ------------------------------------------------------------------------
     pub unsafe fn setVector3(&self, name: &CStr, value: &Vector3<f32>) {
-        gl::Uniform3fv(gl::GetUniformLocation(self.ID, name.as_ptr()), 1, value.as_ptr());
+        self.setUniform(name, UniformValue::Vec3([value.x, value.y, value.z]));
     }
     /// ------------------------------------------------------------------------
     pub unsafe fn setVec3(&self, name: &CStr, x: f32, y: f32, z: f32) {
-        gl::Uniform3f(gl::GetUniformLocation(self.ID, name.as_ptr()), x, y, z);
+        self.setUniform(name, UniformValue::Vec3([x, y, z]));
     }
     /// ------------------------------------------------------------------------
-    pub unsafe fn setMat4(&self, name: &CStr, mat: &Matrix4<f32>) {
-        gl::UniformMatrix4fv(gl::GetUniformLocation(self.ID, name.as_ptr()), 1, gl::FALSE, mat.as_ptr());
+    pub unsafe fn setVec4(&self, name: &CStr, x: f32, y: f32, z: f32, w: f32) {
+        self.setUniform(name, UniformValue::Vec4([x, y, z, w]));
     }
-    
-    /// utility function for checking shader compilation/linking errors.
     /// ------------------------------------------------------------------------
-    unsafe fn checkCompileErrors(&self, shader: u32, type_: &str) {
-        let mut success = gl::FALSE as GLint;
-        let mut infoLog = Vec::with_capacity(1024);
-        infoLog.set_len(1024 - 1); // subtract 1 to skip the trailing null character
-        if type_ != "PROGRAM" {
-            gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetShaderInfoLog(shader, 1024, ptr::null_mut(), infoLog.as_mut_ptr() as *mut GLchar);
-                println!("ERROR::SHADER_COMPILATION_ERROR of type: {}\n{}\n \
-                          -- --------------------------------------------------- -- ",
-                         type_,
-                         str::from_utf8(&infoLog).unwrap());
+    pub unsafe fn setMat4(&self, name: &CStr, mat: &Matrix4<f32>) {
+        self.setUniform(name, UniformValue::Mat4(*mat));
+    }
+
+    /// Whether `name` is an active uniform. Callers setting uniforms that only some of
+    /// their shaders declare check this first, the setters report anything missing.
+    pub fn has_uniform(&self, name: &CStr) -> bool {
+        name.to_str().map_or(false, |name| self.reflection.uniforms.contains_key(name))
+    }
+
+    /// Location setInt would set `name` at, -1 after reporting it if it has none or is
+    /// not an int or sampler. For callers that cache values themselves, e.g. `GlState`.
+    pub(crate) fn int_location(&self, name: &CStr) -> i32 {
+        self.location(name, &UniformValue::Int(0))
+    }
+
+    unsafe fn setUniform(&self, name: &CStr, value: UniformValue) {
+        let location = self.location(name, &value);
+        value.apply(location);
+        if location != -1 {
+            let mut values = self.values.borrow_mut();
+            match values.get_mut(name.to_str().unwrap_or("")) {
+                Some(slot) => *slot = value,
+                None => { values.insert(name.to_string_lossy().into_owned(), value); }
             }
+        }
+    }
 
-        } else {
-            gl::GetProgramiv(shader, gl::LINK_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetProgramInfoLog(shader, 1024, ptr::null_mut(), infoLog.as_mut_ptr() as *mut GLchar);
-                println!("ERROR::PROGRAM_LINKING_ERROR of type: {}\n{}\n \
-                          -- --------------------------------------------------- -- ",
-                         type_,
-                         str::from_utf8(&infoLog).unwrap());
+    fn location(&self, name: &CStr, value: &UniformValue) -> i32 {
+        let (accepted, setter): (&[GLenum], &str) = match value {
+            UniformValue::Bool(_) => (&[gl::BOOL, gl::INT], "setBool"),
+            // ints also set which unit a sampler reads
+            UniformValue::Int(_) => {
+                let mut accepted = vec![gl::INT, gl::BOOL];
+                accepted.extend_from_slice(&SAMPLER_TYPES);
+                return self.reflection.location(name, &accepted, "setInt");
             }
+            UniformValue::Float(_) => (&[gl::FLOAT], "setFloat"),
+            UniformValue::Vec2(_) => (&[gl::FLOAT_VEC2], "setVec2"),
+            UniformValue::Vec3(_) => (&[gl::FLOAT_VEC3], "setVec3"),
+            UniformValue::Vec4(_) => (&[gl::FLOAT_VEC4], "setVec4"),
+            UniformValue::Mat4(_) => (&[gl::FLOAT_MAT4], "setMat4")
+        };
+        self.reflection.location(name, accepted, setter)
+    }
+
+    /// Checks that the vertex inputs are at the locations and of the types `Vertex`
+    /// and `Instance` feed them with, see `MESH_ATTRIBUTES`.
+    pub fn check_vertex_layout(&self) -> Result<(), String> {
+        self.reflection.check_attributes(&MESH_ATTRIBUTES)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum UniformValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat4(Matrix4<f32>)
+}
+
+impl UniformValue {
+    /// Sets it on the current program.
+    unsafe fn apply(&self, location: i32) {
+        match self {
+            UniformValue::Bool(value) => gl::Uniform1i(location, *value as i32),
+            UniformValue::Int(value) => gl::Uniform1i(location, *value),
+            UniformValue::Float(value) => gl::Uniform1f(location, *value),
+            UniformValue::Vec2(v) => gl::Uniform2f(location, v[0], v[1]),
+            UniformValue::Vec3(v) => gl::Uniform3f(location, v[0], v[1], v[2]),
+            UniformValue::Vec4(v) => gl::Uniform4f(location, v[0], v[1], v[2], v[3]),
+            UniformValue::Mat4(mat) => gl::UniformMatrix4fv(location, 1, gl::FALSE, mat.as_ptr())
+        }
+    }
+}
+
+/// The files and settings a program is built from, and when the files were last changed.
+struct ShaderOrigin {
+    preprocessor: Preprocessor,
+    vertexPath: PathBuf,
+    fragmentPath: PathBuf,
+    defines: Vec<(String, String)>,
+    /// Both stages' files with their includes, as of the last build.
+    files: Vec<(PathBuf, Option<SystemTime>)>
+}
+
+impl ShaderOrigin {
+    fn new(preprocessor: &Preprocessor, vertexPath: &Path, fragmentPath: &Path, defines: &[(&str, &str)]) -> ShaderOrigin {
+        ShaderOrigin {
+            preprocessor: preprocessor.clone(),
+            vertexPath: vertexPath.to_path_buf(),
+            fragmentPath: fragmentPath.to_path_buf(),
+            defines: defines.iter().map(|&(name, value)| (name.into(), value.into())).collect(),
+            files: vec![(vertexPath.to_path_buf(), None), (fragmentPath.to_path_buf(), None)]
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.files.iter().any(|(path, modified)| modifiedTime(path) != *modified)
+    }
+
+    /// Builds a program from the files as they are now and remembers which files those
+    /// were and when they changed, whether it builds or not.
+    fn link(&mut self) -> Result<u32, String> {
+        let defines: Vec<(&str, &str)> = self.defines.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        let sources = self.preprocessor.process(&self.vertexPath, &defines)
+            .and_then(|vertex| Ok((vertex, self.preprocessor.process(&self.fragmentPath, &defines)?)));
+        if let Ok((vertex, fragment)) = &sources {
+            let mut files: Vec<PathBuf> = vertex.files.iter().chain(&fragment.files).cloned().collect();
+            files.sort();
+            files.dedup();
+            self.files = files.into_iter().map(|path| (path, None)).collect();
+        }
+        for (path, modified) in &mut self.files {
+            *modified = modifiedTime(path);
+        }
+        let (vertex, fragment) = sources?;
+        unsafe { linkProgram(&vertex, &fragment) }
+    }
+}
+
+fn modifiedTime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+unsafe fn linkProgram(vertexSource: &ShaderSource, fragmentSource: &ShaderSource) -> Result<u32, String> {
+    let vertex = compileStage(gl::VERTEX_SHADER, vertexSource, "VERTEX")?;
+    let fragment = match compileStage(gl::FRAGMENT_SHADER, fragmentSource, "FRAGMENT") {
+        Ok(fragment) => fragment,
+        Err(err) => {
+            gl::DeleteShader(vertex);
+            return Err(err);
+        }
+    };
+    // shader Program
+    let ID = gl::CreateProgram();
+    gl::AttachShader(ID, vertex);
+    gl::AttachShader(ID, fragment);
+    gl::LinkProgram(ID);
+    // delete the shaders as they're linked into our program now and no longer necessary
+    gl::DeleteShader(vertex);
+    gl::DeleteShader(fragment);
+
+    let mut success = gl::FALSE as GLint;
+    gl::GetProgramiv(ID, gl::LINK_STATUS, &mut success);
+    if success != gl::TRUE as GLint {
+        let log = infoLog(ID, gl::GetProgramiv, gl::GetProgramInfoLog);
+        gl::DeleteProgram(ID);
+        return Err(format!("ERROR::PROGRAM_LINKING_ERROR of {} and {}\n{}\n \
+                            -- --------------------------------------------------- -- ",
+                           vertexSource.files[0].display(), fragmentSource.files[0].display(), log));
+    }
+    Ok(ID)
+}
+
+unsafe fn compileStage(kind: GLenum, source: &ShaderSource, type_: &str) -> Result<u32, String> {
+    let code = CString::new(source.code.as_bytes())
+        .map_err(|_| format!("{}: nul byte in source", source.files[0].display()))?;
+    let shader = gl::CreateShader(kind);
+    gl::ShaderSource(shader, 1, &code.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+
+    let mut success = gl::FALSE as GLint;
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+    if success != gl::TRUE as GLint {
+        let log = infoLog(shader, gl::GetShaderiv, gl::GetShaderInfoLog);
+        gl::DeleteShader(shader);
+        return Err(format!("ERROR::SHADER_COMPILATION_ERROR of type: {}\n{}\n \
+                            -- --------------------------------------------------- -- ",
+                           type_, source.map_log(&log)));
+    }
+    Ok(shader)
+}
+
+/// The info log of a shader or program, as long as it is.
+unsafe fn infoLog(object: u32,
+                  getIv: unsafe fn(GLuint, GLenum, *mut GLint),
+                  getLog: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar)) -> String {
+    let mut length = 0;
+    getIv(object, gl::INFO_LOG_LENGTH, &mut length);
+    let mut log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    getLog(object, log.len() as GLsizei, &mut written, log.as_mut_ptr() as *mut GLchar);
+    log.truncate(written.max(0) as usize);
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+/// A vertex and fragment shader pair compiled once for every set of defines it is asked
+/// for, e.g. `HAS_NORMAL_MAP` or `MAX_LIGHTS`, the first time it is asked for.
+pub struct ShaderVariants {
+    pub vertexPath: PathBuf,
+    pub fragmentPath: PathBuf,
+    pub preprocessor: Preprocessor,
+    /// By the sorted defines.
+    variants: HashMap<Vec<(String, String)>, Shader>
+}
+
+impl ShaderVariants {
+    pub fn new(vertexPath: &str, fragmentPath: &str) -> ShaderVariants {
+        ShaderVariants {
+            vertexPath: vertexPath.into(),
+            fragmentPath: fragmentPath.into(),
+            preprocessor: Preprocessor::default(),
+            variants: HashMap::new()
+        }
+    }
+
+    /// The variant for `defines`, in any order. A variant that fails to compile prints
+    /// its errors once and stays at program 0 until `reload_if_changed` builds it.
+    pub fn get(&mut self, defines: &[(&str, &str)]) -> &Shader {
+        let mut key: Vec<(String, String)> = defines.iter().map(|&(name, value)| (name.into(), value.into())).collect();
+        key.sort();
+        let (preprocessor, vertexPath, fragmentPath) = (&self.preprocessor, &self.vertexPath, &self.fragmentPath);
+        self.variants.entry(key).or_insert_with(|| {
+            Shader::from_origin(ShaderOrigin::new(preprocessor, vertexPath, fragmentPath, defines))
+        })
+    }
+
+    /// Reloads every compiled variant whose files changed, see `Shader::reload_if_changed`.
+    /// Returns the programs that were replaced.
+    pub fn reload_if_changed(&mut self) -> Vec<u32> {
+        self.variants.values_mut().filter_map(Shader::reload_if_changed).collect()
+    }
+
+    /// Number of variants compiled so far.
+    pub fn compiled_count(&self) -> usize {
+        self.variants.len()
+    }
+}
+
+impl Drop for ShaderVariants {
+    fn drop(&mut self) {
+        for shader in self.variants.values() {
+            unsafe { gl::DeleteProgram(shader.ID) };
         }
     }
 }
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::mesh::Vertex;
+
+/// Error quadric of Garland and Heckbert, the upper triangle of a symmetric 4x4 matrix.
+#[derive(Clone, Copy, Default)]
+struct Quadric {
+    a2: f64, ab: f64, ac: f64, ad: f64,
+    b2: f64, bc: f64, bd: f64,
+    c2: f64, cd: f64,
+    d2: f64
+}
+
+impl Quadric {
+    /// Squared distance to the plane `n . p + d = 0`, scaled by `weight`.
+    fn from_plane(n: Vector3<f64>, d: f64, weight: f64) -> Quadric {
+        Quadric {
+            a2: n.x * n.x * weight, ab: n.x * n.y * weight, ac: n.x * n.z * weight, ad: n.x * d * weight,
+            b2: n.y * n.y * weight, bc: n.y * n.z * weight, bd: n.y * d * weight,
+            c2: n.z * n.z * weight, cd: n.z * d * weight,
+            d2: d * d * weight
+        }
+    }
+
+    fn add(&mut self, o: &Quadric) {
+        self.a2 += o.a2; self.ab += o.ab; self.ac += o.ac; self.ad += o.ad;
+        self.b2 += o.b2; self.bc += o.bc; self.bd += o.bd;
+        self.c2 += o.c2; self.cd += o.cd;
+        self.d2 += o.d2;
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let (x, y, z) = (p.x, p.y, p.z);
+        let e = self.a2 * x * x + 2.0 * self.ab * x * y + 2.0 * self.ac * x * z + 2.0 * self.ad * x
+              + self.b2 * y * y + 2.0 * self.bc * y * z + 2.0 * self.bd * y
+              + self.c2 * z * z + 2.0 * self.cd * z
+              + self.d2;
+        e.abs()
+    }
+}
+
+/// Quadric error metric decimation by half-edge collapses onto existing vertices,
+/// so the vertex list is left as it is and only a new index list is produced.
+/// Vertices on open boundaries and on UV/normal seams (several vertices sharing one
+/// position) never move, which keeps both intact.
+/// Stops at `target_index_count` or when the next collapse would cost more than
+/// `max_error`, given relative to the mesh size. Returns the indices and the error reached,
+/// also relative to the mesh size.
+pub fn simplify(vertices: &[Vertex], indices: &[u32], target_index_count: usize, max_error: f32) -> (Vec<u32>, f32) {
+    let mut indices: Vec<u32> = indices[..indices.len() / 3 * 3].to_vec();
+    if indices.len() <= target_index_count || vertices.is_empty() {
+        return (indices, 0.0);
+    }
+
+    // errors are compared relative to the bounding box diagonal
+    let (mut min, mut max) = (vertices[0].position, vertices[0].position);
+    for v in vertices {
+        min = Vector3::new(min.x.min(v.position.x), min.y.min(v.position.y), min.z.min(v.position.z));
+        max = Vector3::new(max.x.max(v.position.x), max.y.max(v.position.y), max.z.max(v.position.z));
+    }
+    let extent = ((max - min).magnitude() as f64).max(1e-12);
+    let max_error = max_error as f64 * extent;
+
+    // vertices sharing a position are one group; groups of more than one vertex are seams
+    let mut group_of: Vec<usize> = Vec::with_capacity(vertices.len());
+    let mut groups: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut group_size: Vec<u32> = Vec::new();
+    for v in vertices {
+        let p = v.position;
+        let key = [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+        let next = groups.len();
+        let group = *groups.entry(key).or_insert(next);
+        if group == next {
+            group_size.push(0);
+        }
+        group_size[group] += 1;
+        group_of.push(group);
+    }
+
+    let position = |v: u32| -> Vector3<f64> { vertices[v as usize].position.cast().unwrap() };
+
+    // plane quadrics of the faces around each group, and open edges
+    let mut quadrics: Vec<Quadric> = vec![Quadric::default(); groups.len()];
+    let mut edge_count: HashMap<(usize, usize), u32> = HashMap::new();
+    for tri in indices.chunks(3) {
+        let (p0, p1, p2) = (position(tri[0]), position(tri[1]), position(tri[2]));
+        let n = (p1 - p0).cross(p2 - p0);
+        let area = n.magnitude();
+        if area > 0.0 {
+            let n = n / area;
+            // unweighted, so the error stays a (summed) squared distance
+            let q = Quadric::from_plane(n, -n.dot(p0), 1.0);
+            for &v in tri {
+                quadrics[group_of[v as usize]].add(&q);
+            }
+        }
+        for k in 0..3 {
+            let (a, b) = (group_of[tri[k] as usize], group_of[tri[(k + 1) % 3] as usize]);
+            *edge_count.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    let mut border = vec![false; groups.len()];
+    for (&(a, b), &count) in &edge_count {
+        if count == 1 {
+            border[a] = true;
+            border[b] = true;
+        }
+    }
+    let locked: Vec<bool> = group_of.iter().map(|&g| group_size[g] > 1 || border[g]).collect();
+
+    // collapse in passes, each vertex takes part in at most one collapse per pass
+    let mut remap: Vec<u32> = (0..vertices.len() as u32).collect();
+    let mut reached = 0.0f64;
+    loop {
+        let mut triangles_of: Vec<Vec<usize>> = vec![Vec::new(); vertices.len()];
+        for (t, tri) in indices.chunks(3).enumerate() {
+            for &v in tri {
+                triangles_of[v as usize].push(t);
+            }
+        }
+
+        let mut candidates: Vec<(f64, u32, u32)> = Vec::new();
+        for tri in indices.chunks(3) {
+            for k in 0..3 {
+                let (from, to) = (tri[k], tri[(k + 1) % 3]);
+                for &(from, to) in &[(from, to), (to, from)] {
+                    if locked[from as usize] {
+                        continue;
+                    }
+                    let mut q = quadrics[group_of[from as usize]];
+                    q.add(&quadrics[group_of[to as usize]]);
+                    candidates.push((q.error(position(to)), from, to));
+                }
+            }
+        }
+        if candidates.is_empty() {
+            break;
+        }
+        candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut touched = vec![false; vertices.len()];
+        let mut collapses = 0;
+        let mut remaining = indices.len();
+        for &(cost, from, to) in &candidates {
+            if cost > max_error * max_error || remaining <= target_index_count {
+                break;
+            }
+            if touched[from as usize] || touched[to as usize] {
+                continue;
+            }
+            if flips(&indices, &triangles_of[from as usize], &remap, vertices, from, to) {
+                continue;
+            }
+            remap[from as usize] = to;
+            touched[from as usize] = true;
+            touched[to as usize] = true;
+            let to_group = group_of[to as usize];
+            let from_quadric = quadrics[group_of[from as usize]];
+            quadrics[to_group].add(&from_quadric);
+            reached = reached.max(cost.sqrt());
+            collapses += 1;
+            // every interior collapse removes two triangles
+            remaining = remaining.saturating_sub(6);
+        }
+        if collapses == 0 {
+            break;
+        }
+
+        // apply the pass, dropping the triangles that collapsed
+        let mut next: Vec<u32> = Vec::with_capacity(indices.len());
+        for tri in indices.chunks(3) {
+            let (a, b, c) = (remap[tri[0] as usize], remap[tri[1] as usize], remap[tri[2] as usize]);
+            if a != b && b != c && a != c {
+                next.extend_from_slice(&[a, b, c]);
+            }
+        }
+        indices = next;
+        for v in 0..remap.len() {
+            remap[v] = v as u32;
+        }
+        if indices.len() <= target_index_count {
+            break;
+        }
+    }
+
+    (indices, (reached / extent) as f32)
+}
+
+/// Whether moving `from` onto `to` turns any of the remaining triangles around `from` over.
+fn flips(indices: &[u32], around: &[usize], remap: &[u32], vertices: &[Vertex], from: u32, to: u32) -> bool {
+    let p = |v: u32| vertices[remap[v as usize] as usize].position;
+    for &t in around {
+        let tri = &indices[t * 3..t * 3 + 3];
+        if tri.contains(&to) {
+            continue;
+        }
+        let before = (p(tri[1]) - p(tri[0])).cross(p(tri[2]) - p(tri[0]));
+        let moved: Vec<Vector3<f32>> = tri.iter()
+            .map(|&v| if v == from { vertices[to as usize].position } else { p(v) })
+            .collect();
+        let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+        if before.dot(after) <= 0.0 {
+            return true;
+        }
+    }
+    false
+}
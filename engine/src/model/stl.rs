@@ -0,0 +1,120 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::str;
+
+use cgmath::prelude::*;
+use cgmath::{vec3, Vector3};
+
+use super::mesh::Vertex;
+use super::model::{MeshData, MeshReport};
+
+/// Loads an ASCII or binary STL file.
+/// Every facet gets its stored normal, or one computed from the winding when the stored
+/// normal is zero or points away from it. Vertices come out unshared, one per facet
+/// corner; `Model` welds them afterwards.
+pub fn load_stl(path: &Path) -> Result<MeshData, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+
+    let facets = if is_binary(&data) { parse_binary(&data)? } else { parse_ascii(&data)? };
+
+    let mut vertices: Vec<Vertex> = Vec::with_capacity(facets.len() * 3);
+    for (stored, corners) in &facets {
+        let winding = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+        let normal = if stored.magnitude2() > 0.0 && (winding.magnitude2() == 0.0 || stored.dot(winding) > 0.0) {
+            stored.normalize()
+        } else if winding.magnitude2() > 0.0 {
+            winding.normalize()
+        } else {
+            Vector3::zero()
+        };
+        for &position in corners {
+            vertices.push(Vertex {
+                position,
+                normal,
+                ..Vertex::default()
+            });
+        }
+    }
+    let indices: Vec<u32> = (0..vertices.len() as u32).collect();
+
+    let report = MeshReport {
+        name: path.file_stem().and_then(|s| s.to_str()).unwrap_or("stl").into(),
+        defaulted_tex_coords: true,
+        vertices_before: vertices.len(),
+        indices_before: indices.len(),
+        ..MeshReport::default()
+    };
+    Ok(MeshData { vertices, indices, has_normals: true, report })
+}
+
+type Facet = (Vector3<f32>, [Vector3<f32>; 3]);
+
+/// Binary files have an 80 byte header and a triangle count that has to match the size.
+/// ASCII is only trusted when the size does not match, as plenty of binary
+/// exporters start their header with "solid" too.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    data.len() == 84 + count * 50 || !data.starts_with(b"solid")
+}
+
+fn parse_binary(data: &[u8]) -> Result<Vec<Facet>, String> {
+    if data.len() < 84 {
+        return Err("file too short for a binary STL header".into());
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    if data.len() < 84 + count * 50 {
+        return Err(format!("header says {} triangles but the file is too short", count));
+    }
+
+    let read_vec = |offset: usize| {
+        let f = |o: usize| f32::from_le_bytes(data[o..o + 4].try_into().unwrap());
+        vec3(f(offset), f(offset + 4), f(offset + 8))
+    };
+    let mut facets = Vec::with_capacity(count);
+    for i in 0..count {
+        // normal, three corners, then a 2 byte attribute count we ignore
+        let base = 84 + i * 50;
+        facets.push((read_vec(base), [read_vec(base + 12), read_vec(base + 24), read_vec(base + 36)]));
+    }
+    Ok(facets)
+}
+
+fn parse_ascii(data: &[u8]) -> Result<Vec<Facet>, String> {
+    let text = str::from_utf8(data).map_err(|_| "ASCII STL is not valid text")?;
+    let mut tokens = text.split_whitespace();
+    let next_vec = |tokens: &mut str::SplitWhitespace| -> Result<Vector3<f32>, String> {
+        let mut v = [0.0f32; 3];
+        for c in v.iter_mut() {
+            let token = tokens.next().ok_or("unexpected end of file")?;
+            *c = token.parse().map_err(|_| format!("invalid number '{}'", token))?;
+        }
+        Ok(vec3(v[0], v[1], v[2]))
+    };
+
+    let mut facets = Vec::new();
+    let mut normal = Vector3::zero();
+    let mut corners: Vec<Vector3<f32>> = Vec::with_capacity(3);
+    while let Some(token) = tokens.next() {
+        match token {
+            "facet" => {
+                // "facet normal nx ny nz"
+                tokens.next();
+                normal = next_vec(&mut tokens)?;
+                corners.clear();
+            }
+            "vertex" => corners.push(next_vec(&mut tokens)?),
+            "endfacet" => {
+                // polygons with more than three corners are fanned
+                for i in 1..corners.len().saturating_sub(1) {
+                    facets.push((normal, [corners[0], corners[i], corners[i + 1]]));
+                }
+            }
+            _ => {} // solid, outer loop, endloop, endsolid, names
+        }
+    }
+    Ok(facets)
+}
@@ -0,0 +1,568 @@
+use std::cell::{ Cell, RefCell };
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::os::raw::{ c_char, c_void };
+use std::path::Path;
+
+use gl;
+use image;
+use image::DynamicImage::*;
+use image::GenericImage;
+
+use super::bcn;
+use super::dds::load_dds;
+use super::ktx::load_ktx;
+use super::hdr_image::HdrImage;
+
+/// From EXT/ARB_texture_filter_anisotropic, core only since 4.6.
+const TEXTURE_MAX_ANISOTROPY: u32 = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: u32 = 0x84FF;
+
+/// Wrap and filter modes for a texture, as GL enums.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureSampler {
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+    pub min_filter: u32,
+    pub mag_filter: u32,
+    /// Samples taken along the axis of anisotropy, 1 turns it off.
+    /// Clamped to what the driver supports, ignored without the extension.
+    pub anisotropy: f32
+}
+
+impl Default for TextureSampler {
+    fn default() -> Self {
+        TextureSampler {
+            wrap_s: gl::REPEAT,
+            wrap_t: gl::REPEAT,
+            min_filter: gl::LINEAR_MIPMAP_LINEAR,
+            mag_filter: gl::LINEAR,
+            anisotropy: 8.0
+        }
+    }
+}
+
+impl TextureSampler {
+    /// The default filtering, clamped to the edge instead of repeating.
+    pub fn clamped() -> TextureSampler {
+        TextureSampler { wrap_s: gl::CLAMP_TO_EDGE, wrap_t: gl::CLAMP_TO_EDGE, ..TextureSampler::default() }
+    }
+
+    pub fn uses_mipmaps(&self) -> bool {
+        match self.min_filter {
+            gl::NEAREST | gl::LINEAR => false,
+            _ => true
+        }
+    }
+
+    /// The same sampler with the minification filter reading level 0 only, for
+    /// textures without mipmaps, which would otherwise be incomplete.
+    pub fn without_mipmaps(&self) -> TextureSampler {
+        let min_filter = match self.min_filter {
+            gl::NEAREST_MIPMAP_NEAREST | gl::NEAREST_MIPMAP_LINEAR => gl::NEAREST,
+            gl::LINEAR_MIPMAP_NEAREST | gl::LINEAR_MIPMAP_LINEAR => gl::LINEAR,
+            filter => filter
+        };
+        TextureSampler { min_filter, ..*self }
+    }
+
+    /// Sets the modes on the texture bound to `target`.
+    pub unsafe fn apply(&self, target: u32) {
+        gl::TexParameteri(target, gl::TEXTURE_WRAP_S, self.wrap_s as i32);
+        gl::TexParameteri(target, gl::TEXTURE_WRAP_T, self.wrap_t as i32);
+        gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, self.min_filter as i32);
+        gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, self.mag_filter as i32);
+        let max = max_anisotropy();
+        if max > 1.0 {
+            gl::TexParameterf(target, TEXTURE_MAX_ANISOTROPY, self.anisotropy.max(1.0).min(max));
+        }
+    }
+}
+
+/// How to create a texture from an image.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureOptions {
+    /// Generate the full mip chain. Without it a mipmapped minification filter falls
+    /// back to its level 0 version.
+    pub mipmaps: bool,
+    /// The image holds sRGB encoded color, e.g. a diffuse or emissive map, and is
+    /// decoded to linear when sampled. Data like normals or roughness stays linear.
+    /// Only 8 bit RGB and RGBA images have sRGB formats, others are always linear.
+    pub srgb: bool,
+    /// Float images are stored as 16 bit halves, which keeps color in half the memory.
+    /// Turn it off for 32 bit floats where the range or precision of halves is not enough.
+    pub half_float: bool,
+    /// DDS and KTX files store the top row first. Block compressed ones are flipped as
+    /// blocks, or decoded where that can't be done: BC6H and BC7 always, and chains with
+    /// a level whose height is not a multiple of 4. With this on those are uploaded
+    /// compressed and top row first instead, for meshes whose V coordinate runs downwards.
+    pub allow_top_down: bool,
+    pub sampler: TextureSampler
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            mipmaps: true,
+            srgb: false,
+            half_float: true,
+            allow_top_down: false,
+            sampler: TextureSampler::default()
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Defaults for a material texture of `type_`, sRGB for the color maps.
+    pub fn for_type(type_: &str) -> TextureOptions {
+        TextureOptions { srgb: is_color_texture(type_), ..TextureOptions::default() }
+    }
+}
+
+/// Whether a material texture of `type_` holds color rather than data.
+pub fn is_color_texture(type_: &str) -> bool {
+    match type_ {
+        "texture_diffuse" | "texture_emissive" => true,
+        _ => false
+    }
+}
+
+/// A GL sampler object. While bound to a unit its modes replace those of whatever
+/// texture is bound there, so one image can be sampled differently by several materials.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub id: u32,
+    pub settings: TextureSampler
+}
+
+impl Sampler {
+    pub fn new(settings: TextureSampler) -> Sampler {
+        let mut id = 0;
+        unsafe {
+            gl::GenSamplers(1, &mut id);
+            gl::SamplerParameteri(id, gl::TEXTURE_WRAP_S, settings.wrap_s as i32);
+            gl::SamplerParameteri(id, gl::TEXTURE_WRAP_T, settings.wrap_t as i32);
+            gl::SamplerParameteri(id, gl::TEXTURE_MIN_FILTER, settings.min_filter as i32);
+            gl::SamplerParameteri(id, gl::TEXTURE_MAG_FILTER, settings.mag_filter as i32);
+            let max = max_anisotropy();
+            if max > 1.0 {
+                gl::SamplerParameterf(id, TEXTURE_MAX_ANISOTROPY, settings.anisotropy.max(1.0).min(max));
+            }
+        }
+        Sampler { id, settings }
+    }
+}
+
+/// Sampling options given in front of the file name of an MTL map statement,
+/// e.g. `map_Kd -clamp on -bm 0.5 wood.png`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MtlMap {
+    pub file: String,
+    /// `-clamp on`: clamp the texture coordinates to [0, 1] instead of repeating.
+    pub clamp: bool
+}
+
+impl MtlMap {
+    /// Splits the options off `statement`, which is everything after the keyword.
+    /// Options other than `-clamp` are skipped along with their arguments.
+    pub fn parse(statement: &str) -> MtlMap {
+        let mut tokens = statement.split_whitespace().peekable();
+        let mut clamp = false;
+        while let Some(&token) = tokens.peek() {
+            if !token.starts_with('-') || token.len() < 2 {
+                break;
+            }
+            tokens.next();
+            match token {
+                "-clamp" => clamp = tokens.next() == Some("on"),
+                "-blendu" | "-blendv" | "-cc" | "-imfchan" | "-type" => { tokens.next(); },
+                "-bm" | "-boost" | "-texres" => { tokens.next(); },
+                "-mm" => { tokens.next(); tokens.next(); },
+                // -o, -s and -t take one to three numbers
+                "-o" | "-s" | "-t" => {
+                    for _ in 0..3 {
+                        match tokens.peek() {
+                            Some(value) if value.parse::<f32>().is_ok() => { tokens.next(); },
+                            _ => break
+                        }
+                    }
+                },
+                option => println!("Unknown MTL map option {}", option)
+            }
+        }
+        // file names may contain spaces
+        let file = tokens.collect::<Vec<_>>().join(" ");
+        MtlMap { file, clamp }
+    }
+
+    /// Sampler the options ask for, None if the texture's own modes do.
+    pub fn sampler(&self) -> Option<TextureSampler> {
+        if self.clamp { Some(TextureSampler::clamped()) } else { None }
+    }
+}
+
+// Queried from the context current on this thread the first time they are needed.
+// The engine has one context, made current once.
+thread_local! {
+    static EXTENSIONS: RefCell<Option<HashSet<String>>> = RefCell::new(None);
+    static MAX_ANISOTROPY: Cell<Option<f32>> = Cell::new(None);
+}
+
+/// Largest anisotropy the driver supports, 1 without the extension.
+pub unsafe fn max_anisotropy() -> f32 {
+    if let Some(max) = MAX_ANISOTROPY.with(Cell::get) {
+        return max;
+    }
+    let mut max = 1.0;
+    if has_extension(&["GL_EXT_texture_filter_anisotropic", "GL_ARB_texture_filter_anisotropic"]) {
+        gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+    }
+    MAX_ANISOTROPY.with(|cached| cached.set(Some(max)));
+    max
+}
+
+/// Whether the context has any of the extensions in `names`.
+pub unsafe fn has_extension(names: &[&str]) -> bool {
+    EXTENSIONS.with(|extensions| {
+        let mut extensions = extensions.borrow_mut();
+        let extensions = extensions.get_or_insert_with(|| {
+            let mut count = 0;
+            gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+            (0..count.max(0) as u32).filter_map(|i| {
+                let name = gl::GetStringi(gl::EXTENSIONS, i);
+                if name.is_null() { None } else { Some(CStr::from_ptr(name as *const c_char).to_string_lossy().into_owned()) }
+            }).collect()
+        });
+        names.iter().any(|wanted| extensions.contains(*wanted))
+    })
+}
+
+unsafe fn gl_version() -> (i32, i32) {
+    let (mut major, mut minor) = (0, 0);
+    gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+    gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    (major, minor)
+}
+
+/// Loads an image file into a new 2D texture, flipped so the first row is at t = 1.
+/// DDS, KTX and KTX2 files keep their mip chains and block compression, Radiance .hdr
+/// and OpenEXR files become float textures, anything else goes through `image`.
+pub unsafe fn load_texture(path: &str, options: &TextureOptions) -> u32 {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+    let container = match extension.as_ref().map(String::as_str) {
+        Some("dds") => Some(load_dds(Path::new(path))),
+        Some("ktx") | Some("ktx2") => Some(load_ktx(Path::new(path))),
+        _ => None
+    };
+    if let Some(levels) = container {
+        let levels = levels.unwrap_or_else(|err| panic!("Texture {} failed to load: {}", path, err));
+        return create_texture_levels(&levels, options);
+    }
+    if HdrImage::is_hdr_path(Path::new(path)) {
+        let image = HdrImage::open(Path::new(path)).unwrap_or_else(|err| panic!("Texture {} failed to load: {}", path, err));
+        return image.create_texture(options);
+    }
+
+    let img = image::open(&Path::new(path)).expect("Texture failed to load");
+    let img = img.flipv();
+
+    let format = match img {
+        ImageLuma8(_) => gl::RED,
+        ImageLumaA8(_) => gl::RG,
+        ImageRgb8(_) => gl::RGB,
+        ImageRgba8(_) => gl::RGBA,
+    };
+
+    let data = img.raw_pixels();
+
+    create_texture(img.width(), img.height(), format, gl::UNSIGNED_BYTE, &data, options)
+}
+
+/// Creates a 2D texture from raw pixel rows, in a sized internal format picked from
+/// `format`, `type_`, `options.srgb` and `options.half_float`, then builds the mipmaps and sets the sampler state.
+pub unsafe fn create_texture(width: u32, height: u32, format: u32, type_: u32, data: &[u8], options: &TextureOptions) -> u32 {
+    let mut textureID = 0;
+    gl::GenTextures(1, &mut textureID);
+
+    gl::BindTexture(gl::TEXTURE_2D, textureID);
+    // rows of RED/RGB data are not necessarily 4 byte aligned
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format(format, type_, options) as i32, width as i32, height as i32,
+        0, format, type_, &data[0] as *const u8 as *const c_void);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+
+    if options.mipmaps {
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+        options.sampler.apply(gl::TEXTURE_2D);
+    } else {
+        options.sampler.without_mipmaps().apply(gl::TEXTURE_2D);
+    }
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    textureID
+}
+
+fn internal_format(format: u32, type_: u32, options: &TextureOptions) -> u32 {
+    let srgb = options.srgb;
+    match (format, type_) {
+        (gl::RED, gl::UNSIGNED_BYTE) => gl::R8,
+        (gl::RG, gl::UNSIGNED_BYTE) => gl::RG8,
+        (gl::RGB, gl::UNSIGNED_BYTE) | (gl::BGR, gl::UNSIGNED_BYTE) => if srgb { gl::SRGB8 } else { gl::RGB8 },
+        (gl::RGBA, gl::UNSIGNED_BYTE) | (gl::BGRA, gl::UNSIGNED_BYTE) => if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 },
+        (gl::RED, gl::UNSIGNED_SHORT) => gl::R16,
+        (gl::RG, gl::UNSIGNED_SHORT) => gl::RG16,
+        (gl::RGB, gl::UNSIGNED_SHORT) | (gl::BGR, gl::UNSIGNED_SHORT) => gl::RGB16,
+        (gl::RGBA, gl::UNSIGNED_SHORT) | (gl::BGRA, gl::UNSIGNED_SHORT) => gl::RGBA16,
+        (gl::RED, gl::FLOAT) => if options.half_float { gl::R16F } else { gl::R32F },
+        (gl::RG, gl::FLOAT) => if options.half_float { gl::RG16F } else { gl::RG32F },
+        (gl::RGB, gl::FLOAT) | (gl::BGR, gl::FLOAT) => if options.half_float { gl::RGB16F } else { gl::RGB32F },
+        (gl::RGBA, gl::FLOAT) | (gl::BGRA, gl::FLOAT) => if options.half_float { gl::RGBA16F } else { gl::RGBA32F },
+        (gl::BGR, _) => gl::RGB,
+        (gl::BGRA, _) => gl::RGBA,
+        (format, _) => format
+    }
+}
+
+/// Layout of the texels in `TextureLevels`, plain or in 4x4 blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    R8,
+    Rg8,
+    Rgb8,
+    Bgr8,
+    Rgba8,
+    Bgra8,
+    Rgba16f,
+    Rgba32f,
+    /// DXT1, RGB with 1 bit alpha.
+    Bc1,
+    /// DXT3, RGB with explicit 4 bit alpha.
+    Bc2,
+    /// DXT5, RGB with interpolated alpha.
+    Bc3,
+    /// One channel, e.g. a height or roughness map.
+    Bc4,
+    Bc4Signed,
+    /// Two channels, mostly the x and y of normal maps.
+    Bc5,
+    Bc5Signed,
+    /// HDR RGB, stored as half floats.
+    Bc6h,
+    Bc6hSigned,
+    /// RGBA at the quality of BC1 to BC3 or better, at the size of BC3.
+    Bc7
+}
+
+impl PixelFormat {
+    /// Bytes per 4x4 block, None if not block compressed.
+    pub fn block_size(&self) -> Option<usize> {
+        match *self {
+            PixelFormat::Bc1 | PixelFormat::Bc4 | PixelFormat::Bc4Signed => Some(8),
+            PixelFormat::Bc2 | PixelFormat::Bc3 | PixelFormat::Bc5 | PixelFormat::Bc5Signed |
+            PixelFormat::Bc6h | PixelFormat::Bc6hSigned | PixelFormat::Bc7 => Some(16),
+            _ => None
+        }
+    }
+
+    /// Bytes per texel, decoded for the block compressed formats.
+    pub fn pixel_size(&self) -> usize {
+        match *self {
+            PixelFormat::R8 | PixelFormat::Bc4 | PixelFormat::Bc4Signed => 1,
+            PixelFormat::Rg8 | PixelFormat::Bc5 | PixelFormat::Bc5Signed => 2,
+            PixelFormat::Rgb8 | PixelFormat::Bgr8 => 3,
+            PixelFormat::Bc6h | PixelFormat::Bc6hSigned => 6,
+            PixelFormat::Rgba16f => 8,
+            PixelFormat::Rgba32f => 16,
+            _ => 4
+        }
+    }
+
+    /// Bytes a `width` x `height` level takes in this format, None if that does not fit
+    /// in a usize, which only a broken file asks for.
+    pub fn level_size(&self, width: u32, height: u32) -> Option<usize> {
+        let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+        match self.block_size() {
+            Some(block_size) => ((width + 3) / 4).checked_mul((height + 3) / 4)?.checked_mul(block_size),
+            None => width.checked_mul(height)?.checked_mul(self.pixel_size())
+        }
+    }
+
+    /// Internal format, format and type to upload plain or decoded texels with.
+    pub fn gl_format(&self, srgb: bool) -> (u32, u32, u32) {
+        let rgba8 = if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
+        match *self {
+            PixelFormat::R8 | PixelFormat::Bc4 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+            PixelFormat::Bc4Signed => (gl::R8_SNORM, gl::RED, gl::BYTE),
+            PixelFormat::Rg8 | PixelFormat::Bc5 => (gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+            PixelFormat::Bc5Signed => (gl::RG8_SNORM, gl::RG, gl::BYTE),
+            PixelFormat::Rgb8 => (if srgb { gl::SRGB8 } else { gl::RGB8 }, gl::RGB, gl::UNSIGNED_BYTE),
+            PixelFormat::Bgr8 => (if srgb { gl::SRGB8 } else { gl::RGB8 }, gl::BGR, gl::UNSIGNED_BYTE),
+            PixelFormat::Bgra8 => (rgba8, gl::BGRA, gl::UNSIGNED_BYTE),
+            PixelFormat::Rgba16f => (gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+            PixelFormat::Rgba32f => (gl::RGBA32F, gl::RGBA, gl::FLOAT),
+            PixelFormat::Bc6h | PixelFormat::Bc6hSigned => (gl::RGB16F, gl::RGB, gl::HALF_FLOAT),
+            PixelFormat::Rgba8 | PixelFormat::Bc1 | PixelFormat::Bc2 | PixelFormat::Bc3 | PixelFormat::Bc7 =>
+                (rgba8, gl::RGBA, gl::UNSIGNED_BYTE)
+        }
+    }
+
+    /// Internal format to upload the blocks as they are, None if not block compressed.
+    pub fn compressed_format(&self, srgb: bool) -> Option<u32> {
+        Some(match *self {
+            PixelFormat::Bc1 => if srgb { COMPRESSED_SRGB_ALPHA_S3TC_DXT1 } else { COMPRESSED_RGBA_S3TC_DXT1 },
+            PixelFormat::Bc2 => if srgb { COMPRESSED_SRGB_ALPHA_S3TC_DXT3 } else { COMPRESSED_RGBA_S3TC_DXT3 },
+            PixelFormat::Bc3 => if srgb { COMPRESSED_SRGB_ALPHA_S3TC_DXT5 } else { COMPRESSED_RGBA_S3TC_DXT5 },
+            PixelFormat::Bc4 => gl::COMPRESSED_RED_RGTC1,
+            PixelFormat::Bc4Signed => gl::COMPRESSED_SIGNED_RED_RGTC1,
+            PixelFormat::Bc5 => gl::COMPRESSED_RG_RGTC2,
+            PixelFormat::Bc5Signed => gl::COMPRESSED_SIGNED_RG_RGTC2,
+            PixelFormat::Bc6h => gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT,
+            PixelFormat::Bc6hSigned => gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT,
+            PixelFormat::Bc7 => if srgb { gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM } else { gl::COMPRESSED_RGBA_BPTC_UNORM },
+            _ => return None
+        })
+    }
+
+    /// Whether the context samples the blocks as they are. RGTC is core since 3.0,
+    /// BPTC since 4.2, S3TC has never been core.
+    pub unsafe fn is_supported(&self, srgb: bool) -> bool {
+        match *self {
+            PixelFormat::Bc1 | PixelFormat::Bc2 | PixelFormat::Bc3 =>
+                has_extension(&["GL_EXT_texture_compression_s3tc"])
+                    && (!srgb || has_extension(&["GL_EXT_texture_sRGB", "GL_EXT_texture_compression_s3tc_srgb"])),
+            PixelFormat::Bc4 | PixelFormat::Bc4Signed | PixelFormat::Bc5 | PixelFormat::Bc5Signed => true,
+            PixelFormat::Bc6h | PixelFormat::Bc6hSigned | PixelFormat::Bc7 =>
+                gl_version() >= (4, 2) || has_extension(&["GL_ARB_texture_compression_bptc"]),
+            _ => false
+        }
+    }
+}
+
+// S3TC formats, from EXT_texture_compression_s3tc and EXT_texture_sRGB.
+const COMPRESSED_RGBA_S3TC_DXT1: u32 = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT3: u32 = 0x83F2;
+const COMPRESSED_RGBA_S3TC_DXT5: u32 = 0x83F3;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: u32 = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: u32 = 0x8C4E;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: u32 = 0x8C4F;
+
+/// Texels with their mip chain, as stored in a DDS or KTX file.
+pub struct TextureLevels {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    /// The file marks the color as sRGB encoded.
+    pub srgb: bool,
+    /// The first row is the bottom of the image, the way GL wants it. Both containers
+    /// default to the top first.
+    pub bottom_up: bool,
+    /// Level 0 first, each half the size of the one before.
+    pub levels: Vec<Vec<u8>>
+}
+
+/// Creates a 2D texture from `levels`. Block compressed levels are uploaded as they are
+/// if the context supports the format and every level can be flipped, or
+/// `options.allow_top_down`, and all decoded otherwise, so the levels share one format.
+/// A mip chain in the file is used as is, without one the mipmaps are generated if
+/// `options.mipmaps` and the texture is not compressed. sRGB if either the file or
+/// `options` asks for it.
+pub unsafe fn create_texture_levels(image: &TextureLevels, options: &TextureOptions) -> u32 {
+    let format = image.format;
+    let srgb = image.srgb || options.srgb;
+    for (level, data) in image.levels.iter().enumerate() {
+        let (width, height) = ((image.width >> level).max(1), (image.height >> level).max(1));
+        let size = format.level_size(width, height).expect("texture level size overflows");
+        assert!(data.len() >= size, "level {} of a {}x{} texture is too short", level, image.width, image.height);
+    }
+
+    // the blocks of every level, flipped unless they already are bottom up
+    let blocks: Option<Vec<Vec<u8>>> = if format.block_size().is_none() || !format.is_supported(srgb) {
+        None
+    } else if image.bottom_up || options.allow_top_down {
+        Some(image.levels.clone())
+    } else {
+        let flipped: Option<Vec<Vec<u8>>> = image.levels.iter().enumerate().map(|(level, data)| {
+            bcn::flip_blocks(format, (image.width >> level).max(1), (image.height >> level).max(1), data)
+        }).collect();
+        if flipped.is_none() {
+            println!("A {:?} texture can't be flipped as blocks and is decoded, see TextureOptions::allow_top_down", format);
+        }
+        flipped
+    };
+    let compressed = blocks.is_some();
+
+    let mut textureID = 0;
+    gl::GenTextures(1, &mut textureID);
+    gl::BindTexture(gl::TEXTURE_2D, textureID);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+
+    for (level, data) in image.levels.iter().enumerate() {
+        let width = (image.width >> level).max(1);
+        let height = (image.height >> level).max(1);
+        let size = format.level_size(width, height).unwrap();
+
+        if let Some(blocks) = &blocks {
+            gl::CompressedTexImage2D(gl::TEXTURE_2D, level as i32, format.compressed_format(srgb).unwrap(),
+                                     width as i32, height as i32, 0, size as i32,
+                                     blocks[level].as_ptr() as *const c_void);
+            continue;
+        }
+
+        // plain texels, or blocks the context can't sample or that can't be flipped as blocks
+        let data = &data[..size];
+        let mut texels = if format.block_size().is_some() {
+            bcn::decode(format, width, height, data)
+        } else {
+            data.to_vec()
+        };
+        if !image.bottom_up {
+            let row = width as usize * format.pixel_size();
+            texels = texels.chunks(row).rev().flat_map(|r| r.iter().cloned()).collect();
+        }
+        let (internal_format, pixel_format, type_) = format.gl_format(srgb);
+        gl::TexImage2D(gl::TEXTURE_2D, level as i32, internal_format as i32, width as i32, height as i32,
+                       0, pixel_format, type_, texels.as_ptr() as *const c_void);
+    }
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+
+    if image.levels.len() > 1 {
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, image.levels.len() as i32 - 1);
+        options.sampler.apply(gl::TEXTURE_2D);
+    } else if options.mipmaps && !compressed {
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+        options.sampler.apply(gl::TEXTURE_2D);
+    } else {
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, 0);
+        options.sampler.without_mipmaps().apply(gl::TEXTURE_2D);
+    }
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    textureID
+}
+
+/// Bytes the levels of a 2D texture take on the GPU, the compressed size for compressed
+/// ones, the size of the requested format otherwise, which the driver may pad.
+pub unsafe fn gpu_memory_size(texture: u32) -> usize {
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    let mut size = 0;
+    for level in 0..16 {
+        let query = |parameter| {
+            let mut value = 0;
+            gl::GetTexLevelParameteriv(gl::TEXTURE_2D, level, parameter, &mut value);
+            value.max(0) as usize
+        };
+        let (width, height) = (query(gl::TEXTURE_WIDTH), query(gl::TEXTURE_HEIGHT));
+        if width == 0 || height == 0 {
+            break;
+        }
+        size += if query(gl::TEXTURE_COMPRESSED) != 0 {
+            query(gl::TEXTURE_COMPRESSED_IMAGE_SIZE)
+        } else {
+            let bits: usize = [gl::TEXTURE_RED_SIZE, gl::TEXTURE_GREEN_SIZE, gl::TEXTURE_BLUE_SIZE, gl::TEXTURE_ALPHA_SIZE,
+                               gl::TEXTURE_DEPTH_SIZE, gl::TEXTURE_STENCIL_SIZE].iter().map(|&p| query(p)).sum();
+            width * height * bits / 8
+        };
+    }
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    size
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use super::mesh::Vertex;
+
+/// Merges vertices whose position, normal and texture coordinates are all
+/// within `epsilon` of each other, and drops triangles that collapse as a result.
+/// An `epsilon` of zero only merges exact duplicates.
+pub fn weld_vertices(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, epsilon: f32) {
+    let mut welded: Vec<Vertex> = Vec::with_capacity(vertices.len());
+    let mut remap: Vec<u32> = Vec::with_capacity(vertices.len());
+
+    if epsilon > 0.0 {
+        // bucket welded vertices on a grid of epsilon sized cells,
+        // anything within epsilon is then in the same or a neighbouring cell
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        for vertex in vertices.iter() {
+            let cell = grid_cell(vertex, epsilon);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let neighbour = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                        if let Some(candidates) = grid.get(&neighbour) {
+                            for &candidate in candidates {
+                                if nearly_equal(&welded[candidate as usize], vertex, epsilon) {
+                                    found = Some(candidate);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            let index = match found {
+                Some(index) => index,
+                None => {
+                    let index = welded.len() as u32;
+                    welded.push(*vertex);
+                    grid.entry(cell).or_insert_with(Vec::new).push(index);
+                    index
+                }
+            };
+            remap.push(index);
+        }
+    } else {
+        let mut seen: HashMap<[u32; 16], u32> = HashMap::new();
+        for vertex in vertices.iter() {
+            let next = welded.len() as u32;
+            let index = *seen.entry(vertex_key(vertex)).or_insert(next);
+            if index == next {
+                welded.push(*vertex);
+            }
+            remap.push(index);
+        }
+    }
+
+    let mut welded_indices: Vec<u32> = Vec::with_capacity(indices.len());
+    for tri in indices.chunks(3) {
+        if tri.len() < 3 {
+            break;
+        }
+        let (a, b, c) = (remap[tri[0] as usize], remap[tri[1] as usize], remap[tri[2] as usize]);
+        if a == b || b == c || a == c {
+            continue;
+        }
+        welded_indices.extend_from_slice(&[a, b, c]);
+    }
+
+    *vertices = welded;
+    *indices = welded_indices;
+}
+
+/// Hashable bit pattern of every attribute of a vertex.
+pub fn vertex_key(v: &Vertex) -> [u32; 16] {
+    // adding 0.0 turns -0.0 into 0.0 so they hash the same
+    [
+        (v.position.x + 0.0).to_bits(), (v.position.y + 0.0).to_bits(), (v.position.z + 0.0).to_bits(),
+        (v.normal.x + 0.0).to_bits(), (v.normal.y + 0.0).to_bits(), (v.normal.z + 0.0).to_bits(),
+        (v.tex_coords.x + 0.0).to_bits(), (v.tex_coords.y + 0.0).to_bits(),
+        (v.color.x + 0.0).to_bits(), (v.color.y + 0.0).to_bits(), (v.color.z + 0.0).to_bits(), (v.color.w + 0.0).to_bits(),
+        (v.tangent.x + 0.0).to_bits(), (v.tangent.y + 0.0).to_bits(), (v.tangent.z + 0.0).to_bits(), (v.tangent.w + 0.0).to_bits()
+    ]
+}
+
+fn grid_cell(v: &Vertex, epsilon: f32) -> [i64; 3] {
+    [
+        (v.position.x / epsilon).floor() as i64,
+        (v.position.y / epsilon).floor() as i64,
+        (v.position.z / epsilon).floor() as i64
+    ]
+}
+
+fn nearly_equal(a: &Vertex, b: &Vertex, epsilon: f32) -> bool {
+    (a.position.x - b.position.x).abs() <= epsilon
+        && (a.position.y - b.position.y).abs() <= epsilon
+        && (a.position.z - b.position.z).abs() <= epsilon
+        && (a.normal.x - b.normal.x).abs() <= epsilon
+        && (a.normal.y - b.normal.y).abs() <= epsilon
+        && (a.normal.z - b.normal.z).abs() <= epsilon
+        && (a.tex_coords.x - b.tex_coords.x).abs() <= epsilon
+        && (a.tex_coords.y - b.tex_coords.y).abs() <= epsilon
+        && (a.color.x - b.color.x).abs() <= epsilon
+        && (a.color.y - b.color.y).abs() <= epsilon
+        && (a.color.z - b.color.z).abs() <= epsilon
+        && (a.color.w - b.color.w).abs() <= epsilon
+        && (a.tangent.x - b.tangent.x).abs() <= epsilon
+        && (a.tangent.y - b.tangent.y).abs() <= epsilon
+        && (a.tangent.z - b.tangent.z).abs() <= epsilon
+        && a.tangent.w == b.tangent.w
+}
@@ -0,0 +1,146 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use gl;
+
+use crate::model::Shader;
+use crate::render::shader_path;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// Levels of the blur pyramid, fewer if the screen is small.
+const MAX_LEVELS: usize = 6;
+
+struct BloomLevel {
+    framebuffer: u32,
+    texture: u32,
+    width: i32,
+    height: i32
+}
+
+/// Parameters of one bloom render, the "bloom" effect's params.
+#[derive(Clone, Copy, Debug)]
+pub struct BloomSettings {
+    /// Luminance above which pixels bloom.
+    pub threshold: f32,
+    /// Width of the soft transition around the threshold, relative to it.
+    pub knee: f32,
+    /// Spread of each upsample step, in texels of the smaller level.
+    pub radius: f32
+}
+
+/// Blur pyramid of the bright parts of an HDR image: the image is thresholded and
+/// downsampled level by level with a 13 tap filter, then upsampled back with a tent
+/// filter, each level added onto the next larger one. The result is half the screen size.
+pub struct Bloom {
+    downsample: Shader,
+    upsample: Shader,
+    levels: Vec<BloomLevel>,
+    width: u32,
+    height: u32
+}
+
+impl Bloom {
+    pub fn new() -> Bloom {
+        Bloom {
+            downsample: Shader::new(&shader_path("fullscreen.vert"), &shader_path("bloom_downsample.frag")),
+            upsample: Shader::new(&shader_path("fullscreen.vert"), &shader_path("bloom_upsample.frag")),
+            levels: Vec::new(),
+            width: 0,
+            height: 0
+        }
+    }
+
+    /// Rebuilds the shaders whose files changed, see `Shader::reload_if_changed`.
+    /// Returns the programs that were replaced.
+    pub fn reload_shaders(&mut self) -> Vec<u32> {
+        vec![self.downsample.reload_if_changed(), self.upsample.reload_if_changed()].into_iter().flatten().collect()
+    }
+
+    unsafe fn resize(&mut self, width: u32, height: u32) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        for level in self.levels.drain(..) {
+            gl::DeleteTextures(1, &level.texture);
+            gl::DeleteFramebuffers(1, &level.framebuffer);
+        }
+
+        let (mut w, mut h) = (width as i32 / 2, height as i32 / 2);
+        while self.levels.len() < MAX_LEVELS && w >= 2 && h >= 2 {
+            let (mut framebuffer, mut texture) = (0, 0);
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R11F_G11F_B10F as i32, w, h, 0, gl::RGB, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+            self.levels.push(BloomLevel { framebuffer, texture, width: w, height: h });
+            w /= 2;
+            h /= 2;
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+
+    /// Blurs the bright parts of `source`, a `width` by `height` texture, and returns the
+    /// texture holding the result. Expects a VAO bound for full screen triangles and depth
+    /// testing and blending off. Leaves the viewport at the source size, no framebuffer bound.
+    pub unsafe fn render(&mut self, source: u32, width: u32, height: u32, settings: &BloomSettings) -> u32 {
+        self.resize(width, height);
+        if self.levels.is_empty() {
+            return 0;
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        self.downsample.useProgram();
+        self.downsample.setInt(c_str!("image"), 0);
+        self.downsample.setFloat(c_str!("threshold"), settings.threshold);
+        self.downsample.setFloat(c_str!("knee"), settings.knee);
+        let (mut input, mut input_width, mut input_height) = (source, width as i32, height as i32);
+        for (i, level) in self.levels.iter().enumerate() {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, level.framebuffer);
+            gl::Viewport(0, 0, level.width, level.height);
+            gl::BindTexture(gl::TEXTURE_2D, input);
+            self.downsample.setVec2(c_str!("texelSize"), 1.0 / input_width as f32, 1.0 / input_height as f32);
+            // only the first step thresholds, the levels below are already bright parts only
+            self.downsample.setBool(c_str!("prefilter"), i == 0);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            input = level.texture;
+            input_width = level.width;
+            input_height = level.height;
+        }
+
+        self.upsample.useProgram();
+        self.upsample.setInt(c_str!("image"), 0);
+        self.upsample.setFloat(c_str!("radius"), settings.radius);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::ONE, gl::ONE);
+        for i in (1..self.levels.len()).rev() {
+            let (smaller, larger) = (&self.levels[i], &self.levels[i - 1]);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, larger.framebuffer);
+            gl::Viewport(0, 0, larger.width, larger.height);
+            gl::BindTexture(gl::TEXTURE_2D, smaller.texture);
+            self.upsample.setVec2(c_str!("texelSize"), 1.0 / smaller.width as f32, 1.0 / smaller.height as f32);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::Disable(gl::BLEND);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, width as i32, height as i32);
+        self.levels[0].texture
+    }
+}
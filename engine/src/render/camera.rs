@@ -0,0 +1,17 @@
+use cgmath::{ perspective, Deg, Matrix4 };
+
+/// What the frame is seen through, for passes that need more than the matrices.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraView {
+    pub view: Matrix4<f32>,
+    pub fovy: Deg<f32>,
+    pub aspect: f32,
+    pub near: f32,
+    pub far: f32
+}
+
+impl CameraView {
+    pub fn projection(&self) -> Matrix4<f32> {
+        perspective(self.fovy, self.aspect, self.near, self.far)
+    }
+}
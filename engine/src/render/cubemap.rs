@@ -0,0 +1,202 @@
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::path::Path;
+use std::ptr;
+
+use cgmath::prelude::*;
+use cgmath::{ perspective, Deg, Matrix4, Point3, Vector3 };
+use gl;
+use image;
+use image::GenericImage;
+
+use crate::model::{ primitives, HdrImage, Mesh, Shader, TextureOptions, TextureSampler };
+use crate::render::shader_path;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// View of cube map face `face` (0 is +X, then -X, +Y, -Y, +Z, -Z) from `position`,
+/// oriented the way GL lays out the faces.
+pub fn face_view(position: Vector3<f32>, face: usize) -> Matrix4<f32> {
+    let (forward, up) = match face {
+        0 => (Vector3::unit_x(), -Vector3::unit_y()),
+        1 => (-Vector3::unit_x(), -Vector3::unit_y()),
+        2 => (Vector3::unit_y(), Vector3::unit_z()),
+        3 => (-Vector3::unit_y(), -Vector3::unit_z()),
+        4 => (Vector3::unit_z(), -Vector3::unit_y()),
+        _ => (-Vector3::unit_z(), -Vector3::unit_y())
+    };
+    Matrix4::look_at(Point3::from_vec(position), Point3::from_vec(position + forward), up)
+}
+
+pub struct Cubemap {
+    pub id: u32,
+    /// Width and height of each face.
+    pub size: u32
+}
+
+impl Cubemap {
+    /// Empty cube map with linear filtering, with room for a full mip chain if `mipmaps`.
+    pub fn new(size: u32, internal_format: u32, mipmaps: bool) -> Cubemap {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, id);
+            for face in 0..6 {
+                gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, 0, internal_format as i32, size as i32, size as i32,
+                               0, gl::RGB, gl::FLOAT, ptr::null());
+            }
+            set_parameters(mipmaps);
+            if mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            }
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+        Cubemap { id, size }
+    }
+
+    /// Loads the faces from six square sRGB images in the order +X, -X, +Y, -Y, +Z, -Z,
+    /// that is right, left, top, bottom, front and back.
+    pub fn from_faces(paths: &[&str; 6]) -> Cubemap {
+        let mut id = 0;
+        let mut size = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            for (face, path) in paths.iter().enumerate() {
+                // cube map faces start at the top left, unlike 2D textures, so no flip
+                let img = image::open(&Path::new(path)).expect("Cubemap face failed to load").to_rgba();
+                size = img.width();
+                if img.width() != img.height() {
+                    println!("Cubemap face {} is not square", path);
+                }
+                let data = img.into_raw();
+                gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32, 0, gl::SRGB8_ALPHA8 as i32, size as i32, size as i32,
+                               0, gl::RGBA, gl::UNSIGNED_BYTE, &data[0] as *const u8 as *const c_void);
+            }
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            set_parameters(true);
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+        Cubemap { id, size }
+    }
+
+    /// Loads an equirectangular (latitude-longitude) panorama and projects it onto the
+    /// faces of a `size` cube map on the GPU, stored linear as half floats. Radiance .hdr
+    /// and OpenEXR panoramas keep their range, anything else is read as sRGB.
+    pub fn from_equirect(path: &str, size: u32) -> Cubemap {
+        if HdrImage::is_hdr_path(Path::new(path)) {
+            let image = HdrImage::open(Path::new(path))
+                .unwrap_or_else(|err| panic!("Equirectangular image {} failed to load: {}", path, err));
+            return Cubemap::from_hdr_equirect(&image, size);
+        }
+        let img = image::open(&Path::new(path)).expect("Equirectangular image failed to load").flipv().to_rgba();
+        let (width, height) = img.dimensions();
+        let data = img.into_raw();
+        unsafe {
+            let mut panorama = 0;
+            gl::GenTextures(1, &mut panorama);
+            gl::BindTexture(gl::TEXTURE_2D, panorama);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::SRGB8_ALPHA8 as i32, width as i32, height as i32, 0, gl::RGBA,
+                           gl::UNSIGNED_BYTE, &data[0] as *const u8 as *const c_void);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+
+            let cubemap = Cubemap::new(size, gl::RGB16F, true);
+            cubemap.project_equirect(panorama);
+            gl::DeleteTextures(1, &panorama);
+            cubemap
+        }
+    }
+
+    /// Projects a float panorama onto a `size` RGB16F cube map. The panorama is uploaded
+    /// as full floats, so a sun brighter than a half float can hold only clips at the end.
+    pub fn from_hdr_equirect(image: &HdrImage, size: u32) -> Cubemap {
+        let options = TextureOptions {
+            mipmaps: false,
+            half_float: false,
+            sampler: TextureSampler {
+                wrap_s: gl::REPEAT,
+                wrap_t: gl::CLAMP_TO_EDGE,
+                min_filter: gl::LINEAR,
+                mag_filter: gl::LINEAR,
+                anisotropy: 1.0
+            },
+            ..TextureOptions::default()
+        };
+        unsafe {
+            let panorama = image.create_texture(&options);
+            let cubemap = Cubemap::new(size, gl::RGB16F, true);
+            cubemap.project_equirect(panorama);
+            gl::DeleteTextures(1, &panorama);
+            cubemap
+        }
+    }
+
+    /// Renders the equirectangular `panorama` texture into every face, then rebuilds the mipmaps.
+    pub(crate) unsafe fn project_equirect(&self, panorama: u32) {
+        let shader = Shader::new(&shader_path("cubemap_capture.vert"), &shader_path("equirect_to_cube.frag"));
+        shader.useProgram();
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, panorama);
+        shader.setInt(c_str!("panorama"), 0);
+        self.render_faces(0, &shader, |_| {});
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.id);
+        gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        gl::DeleteProgram(shader.ID);
+    }
+
+    /// Draws a unit cube around the origin into each face of mip level `level` with
+    /// `shader`, which has to be in use and takes a `viewProjection` uniform.
+    /// `per_face` runs before each face, with the face index, to set more uniforms.
+    /// Leaves the default framebuffer bound and the viewport as it was.
+    pub(crate) unsafe fn render_faces<F: FnMut(usize)>(&self, level: i32, shader: &Shader, mut per_face: F) {
+        let cube: Mesh = primitives::cube(2.0, 1).into_mesh(Vec::new());
+        let size = (self.size >> level).max(1) as i32;
+        let projection = perspective(Deg(90.0), 1.0, 0.1, 10.0);
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::Viewport(0, 0, size, size);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::CULL_FACE);
+
+        for face in 0..6 {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32,
+                                     self.id, level);
+            shader.setMat4(c_str!("viewProjection"), &(projection * face_view(Vector3::zero(), face)));
+            per_face(face);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            cube.draw(shader);
+        }
+
+        gl::Enable(gl::DEPTH_TEST);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::DeleteFramebuffers(1, &framebuffer);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+}
+
+unsafe fn set_parameters(mipmaps: bool) {
+    let min_filter = if mipmaps { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+    // filter across face edges, without this the seams show in blurry mip levels
+    gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+}
@@ -0,0 +1,266 @@
+use std::ffi::{ CStr, CString };
+use std::ptr;
+
+use cgmath::prelude::*;
+use cgmath::{ Matrix4, Vector3 };
+use gl;
+
+use crate::model::{ primitives, Mesh, Shader };
+use crate::render::camera::CameraView;
+use crate::render::cubemap::Cubemap;
+use crate::render::light::{ apply_lights, Light, LightKind, MAX_LIGHTS };
+use crate::render::shader_path;
+use crate::render::shadow::ShadowMaps;
+use crate::render::skybox::bind_environment;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// How the engine shades opaque objects. Transparent objects are always drawn forward.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderPath {
+    /// Every object is lit by every light as it is drawn.
+    Forward,
+    /// Opaque objects are drawn into a G-buffer first, then each light shades only the
+    /// pixels it reaches. Phong only, `Engine::start` falls back to forward for a PBR scene.
+    Deferred
+}
+
+/// What the deferred path puts on screen, the lit image or one of the G-buffer targets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GBufferView {
+    Lit,
+    Albedo,
+    Normal,
+    /// Specular strength in red, shininess in green.
+    Material,
+    Depth
+}
+
+/// Render targets of the geometry pass. Positions are rebuilt from depth.
+pub struct GBuffer {
+    pub framebuffer: u32,
+    /// RGB albedo.
+    pub albedo: u32,
+    /// World space normal, 16 bit float.
+    pub normal: u32,
+    /// Specular strength and shininess / 256.
+    pub material: u32,
+    /// Same format as the default framebuffer's depth, so it can be blitted there.
+    pub depth: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl GBuffer {
+    pub fn new(width: u32, height: u32) -> GBuffer {
+        let mut gbuffer = GBuffer { framebuffer: 0, albedo: 0, normal: 0, material: 0, depth: 0, width, height };
+        unsafe { gbuffer.allocate(); }
+        gbuffer
+    }
+
+    /// Recreates the targets if the size changed.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        unsafe {
+            self.delete();
+            self.allocate();
+        }
+    }
+
+    unsafe fn allocate(&mut self) {
+        gl::GenFramebuffers(1, &mut self.framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        self.albedo = self.attach(gl::COLOR_ATTACHMENT0, gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE);
+        self.normal = self.attach(gl::COLOR_ATTACHMENT1, gl::RGBA16F, gl::RGBA, gl::FLOAT);
+        self.material = self.attach(gl::COLOR_ATTACHMENT2, gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE);
+        self.depth = self.attach(gl::DEPTH_STENCIL_ATTACHMENT, gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8);
+        let attachments = [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1, gl::COLOR_ATTACHMENT2];
+        gl::DrawBuffers(attachments.len() as i32, attachments.as_ptr());
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            println!("ERROR::GBUFFER:: Framebuffer is not complete!");
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+
+    unsafe fn attach(&self, attachment: u32, internal_format: u32, format: u32, type_: u32) -> u32 {
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, self.width as i32, self.height as i32, 0,
+                       format, type_, ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, gl::TEXTURE_2D, texture, 0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        texture
+    }
+
+    unsafe fn delete(&mut self) {
+        let textures = [self.albedo, self.normal, self.material, self.depth];
+        gl::DeleteTextures(textures.len() as i32, textures.as_ptr());
+        gl::DeleteFramebuffers(1, &self.framebuffer);
+    }
+
+    /// Binds the targets to texture units 0 to 3 and points the `g*` samplers of `shader` at them.
+    unsafe fn bind_textures(&self, shader: &Shader) {
+        let targets = [("gAlbedo", self.albedo), ("gNormal", self.normal), ("gMaterial", self.material), ("gDepth", self.depth)];
+        for (unit, &(name, texture)) in targets.iter().enumerate() {
+            gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            shader.setInt(&CString::new(name).expect("CString::new failed"), unit as i32);
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+}
+
+/// Deferred shading of opaque objects: draw them with `geometry_shader` between
+/// `begin_geometry` and `light`, which then shades the screen one light at a time.
+/// Directional lights are drawn as full screen passes, point and spot lights as spheres
+/// around their range, so only the pixels a light reaches pay for it.
+pub struct DeferredRenderer {
+    pub gbuffer: GBuffer,
+    /// Takes the same uniforms as the forward model shader.
+    pub geometry_shader: Shader,
+    light_shader: Shader,
+    debug_shader: Shader,
+    /// Unit sphere, its triangles lie inside the sphere so it is scaled up a little.
+    volume: Mesh,
+    /// Full screen passes need a VAO bound, even one without attributes.
+    empty_vao: u32,
+    pub view: GBufferView
+}
+
+impl DeferredRenderer {
+    pub fn new(width: u32, height: u32) -> DeferredRenderer {
+        let mut empty_vao = 0;
+        unsafe { gl::GenVertexArrays(1, &mut empty_vao); }
+        let geometry_shader = Shader::new(&shader_path("model.vert"), &shader_path("gbuffer.frag"));
+        if let Err(problems) = geometry_shader.check_vertex_layout() {
+            println!("model.vert with gbuffer.frag does not match the mesh layout:\n{}", problems);
+        }
+        DeferredRenderer {
+            gbuffer: GBuffer::new(width, height),
+            geometry_shader,
+            light_shader: Shader::new(&shader_path("deferred_light.vert"), &shader_path("deferred_light.frag")),
+            debug_shader: Shader::new(&shader_path("fullscreen.vert"), &shader_path("gbuffer_debug.frag")),
+            volume: primitives::icosphere(1.0, 2).into_mesh(Vec::new()),
+            empty_vao,
+            view: GBufferView::Lit
+        }
+    }
+
+    /// Rebuilds the shaders whose files changed, see `Shader::reload_if_changed`.
+    /// Returns the programs that were replaced.
+    pub fn reload_shaders(&mut self) -> Vec<u32> {
+        vec![self.geometry_shader.reload_if_changed(), self.light_shader.reload_if_changed(), self.debug_shader.reload_if_changed()].into_iter().flatten().collect()
+    }
+
+    /// Binds and clears the G-buffer, sized to the screen. Whatever is drawn with
+    /// `geometry_shader` until `light` ends up in it.
+    pub unsafe fn begin_geometry(&mut self, width: u32, height: u32) {
+        self.gbuffer.resize(width, height);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.gbuffer.framebuffer);
+        gl::Viewport(0, 0, width as i32, height as i32);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    }
+
+    /// Shades the G-buffer into `target`, or shows the target picked by `view`, then copies
+    /// the depth over so forward passes after it are hidden behind opaque objects.
+    /// Changes GL state behind the back of `GlState`.
+    pub unsafe fn light(&self, target: u32, camera: &CameraView, lights: &[Light], shadows: Option<&ShadowMaps>,
+                        environment: Option<&Cubemap>) {
+        let (width, height) = (self.gbuffer.width, self.gbuffer.height);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::DepthMask(gl::FALSE);
+        gl::BindVertexArray(self.empty_vao);
+
+        if self.view != GBufferView::Lit {
+            let target = match self.view {
+                GBufferView::Albedo => 1,
+                GBufferView::Normal => 2,
+                GBufferView::Material => 3,
+                _ => 4
+            };
+            self.debug_shader.useProgram();
+            self.gbuffer.bind_textures(&self.debug_shader);
+            self.debug_shader.setInt(c_str!("target"), target);
+            self.debug_shader.setFloat(c_str!("near"), camera.near);
+            self.debug_shader.setFloat(c_str!("far"), camera.far);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        } else {
+            let view_projection = camera.projection() * camera.view;
+            let inverse_view = camera.view.invert().unwrap_or_else(Matrix4::identity);
+            let shader = &self.light_shader;
+            shader.useProgram();
+            self.gbuffer.bind_textures(shader);
+            shader.setVec2(c_str!("screenSize"), width as f32, height as f32);
+            shader.setMat4(c_str!("view"), &camera.view);
+            shader.setMat4(c_str!("viewProjection"), &view_projection);
+            shader.setMat4(c_str!("inverseViewProjection"), &view_projection.invert().unwrap_or_else(Matrix4::identity));
+            shader.setVector3(c_str!("viewPos"), &inverse_view.w.truncate());
+            apply_lights(shader, lights, shadows);
+            if let Some(shadows) = shadows {
+                shadows.bind(shader);
+            }
+            bind_environment(shader, environment);
+
+            // the ambient term replaces what is there, every light adds to it
+            shader.setBool(c_str!("fullscreen"), true);
+            shader.setInt(c_str!("lightIndex"), -1);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE);
+            // back faces, so volumes the camera is inside of still cover the screen
+            gl::Enable(gl::CULL_FACE);
+            gl::CullFace(gl::FRONT);
+            for (i, light) in lights.iter().take(MAX_LIGHTS).enumerate() {
+                shader.setInt(c_str!("lightIndex"), i as i32);
+                match light.kind {
+                    LightKind::Directional { .. } => {
+                        shader.setBool(c_str!("fullscreen"), true);
+                        gl::BindVertexArray(self.empty_vao);
+                        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+                    }
+                    LightKind::Point { position, range } | LightKind::Spot { position, range, .. } => {
+                        shader.setBool(c_str!("fullscreen"), false);
+                        shader.setMat4(c_str!("volume"), &self.volume_transform(position, range));
+                        self.volume.draw(shader);
+                    }
+                }
+            }
+            gl::CullFace(gl::BACK);
+            gl::Disable(gl::CULL_FACE);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::Disable(gl::BLEND);
+        }
+
+        gl::BindVertexArray(0);
+        gl::DepthMask(gl::TRUE);
+        gl::Enable(gl::DEPTH_TEST);
+
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.gbuffer.framebuffer);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target);
+        gl::BlitFramebuffer(0, 0, width as i32, height as i32, 0, 0, width as i32, height as i32,
+                            gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+    }
+
+    fn volume_transform(&self, position: Vector3<f32>, range: f32) -> Matrix4<f32> {
+        // the flat faces of a twice subdivided icosahedron dip up to about 2% inside the sphere
+        Matrix4::from_translation(position) * Matrix4::from_scale(range * 1.03)
+    }
+}
@@ -0,0 +1,216 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use crate::model::Shader;
+use crate::render::shader_path;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// Size of the luminance texture, its mip chain goes down to one pixel.
+const LUMINANCE_SIZE: u32 = 256;
+
+/// Frames a measured luminance takes to come back, reading it any sooner makes the CPU
+/// wait for the GPU to finish the frame.
+const READBACK_FRAMES: usize = 3;
+
+/// How the tone mapping effect squeezes HDR colors into 0..1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    Reinhard,
+    /// Fit of the ACES filmic curve by Krzysztof Narkowicz.
+    Aces,
+    /// John Hable's curve from Uncharted 2.
+    Filmic
+}
+
+impl ToneMapper {
+    /// Value of the `operator` parameter of the tone mapping effect.
+    pub fn param(self) -> f32 {
+        match self {
+            ToneMapper::Reinhard => 0.0,
+            ToneMapper::Aces => 1.0,
+            ToneMapper::Filmic => 2.0
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExposureSettings {
+    /// Adapt the exposure to the average brightness of the frame, like an eye does.
+    pub auto: bool,
+    /// The exposure used when `auto` is off.
+    pub exposure: f32,
+    /// Stops added to the adapted exposure.
+    pub compensation: f32,
+    /// Middle gray the average luminance is mapped to.
+    pub key: f32,
+    /// The adapted exposure stays within these.
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    /// How fast the eye adapts, higher is faster.
+    pub adaptation_speed: f32
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        ExposureSettings {
+            auto: true,
+            exposure: 1.0,
+            compensation: 0.0,
+            key: 0.18,
+            min_exposure: 0.1,
+            max_exposure: 10.0,
+            adaptation_speed: 1.5
+        }
+    }
+}
+
+/// Eye adaptation from a downsample chain: the log luminance of the frame is drawn into a
+/// small texture whose mipmaps average it down to one pixel, the geometric mean. That pixel
+/// is copied into a pixel buffer and read `READBACK_FRAMES` frames later.
+pub struct AutoExposure {
+    pub settings: ExposureSettings,
+    shader: Shader,
+    framebuffer: u32,
+    luminance: u32,
+    empty_vao: u32,
+    /// Pixel buffers the measurements are copied into, used in turn.
+    readbacks: [u32; READBACK_FRAMES],
+    /// Signaled once the copy into the buffer of the same index is done, null when none is pending.
+    fences: [GLsync; READBACK_FRAMES],
+    next_readback: usize,
+    /// Time since the exposure last adapted.
+    pending_time: f32,
+    /// Current adapted exposure, before compensation.
+    adapted: f32
+}
+
+impl AutoExposure {
+    pub fn new(settings: ExposureSettings) -> AutoExposure {
+        let shader = Shader::new(&shader_path("fullscreen.vert"), &shader_path("hdr_luminance.frag"));
+        let (mut framebuffer, mut luminance, mut empty_vao) = (0, 0, 0);
+        unsafe {
+            gl::GenTextures(1, &mut luminance);
+            gl::BindTexture(gl::TEXTURE_2D, luminance);
+            let levels = (LUMINANCE_SIZE as f32).log2() as i32 + 1;
+            for level in 0..levels {
+                let size = (LUMINANCE_SIZE >> level) as i32;
+                gl::TexImage2D(gl::TEXTURE_2D, level, gl::R16F as i32, size, size, 0, gl::RED, gl::FLOAT, ptr::null());
+            }
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, luminance, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::GenVertexArrays(1, &mut empty_vao);
+        }
+        let mut readbacks = [0; READBACK_FRAMES];
+        unsafe {
+            gl::GenBuffers(READBACK_FRAMES as i32, readbacks.as_mut_ptr());
+            for &buffer in &readbacks {
+                gl::BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
+                gl::BufferData(gl::PIXEL_PACK_BUFFER, 4, ptr::null(), gl::STREAM_READ);
+            }
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+        AutoExposure {
+            settings, shader, framebuffer, luminance, empty_vao, readbacks,
+            fences: [ptr::null(); READBACK_FRAMES],
+            next_readback: 0,
+            pending_time: 0.0,
+            adapted: settings.exposure
+        }
+    }
+
+    /// Rebuilds the shader if its files changed, see `Shader::reload_if_changed`.
+    /// Returns the program that was replaced.
+    pub fn reload_shaders(&mut self) -> Option<u32> {
+        self.shader.reload_if_changed()
+    }
+
+    /// Exposure the tone mapper should use this frame.
+    pub fn exposure(&self) -> f32 {
+        if self.settings.auto {
+            self.adapted * 2f32.powf(self.settings.compensation)
+        } else {
+            self.settings.exposure
+        }
+    }
+
+    /// Measures the average luminance of `scene`, an HDR color texture, and moves the
+    /// adapted exposure towards the one measured `READBACK_FRAMES` frames ago by the time
+    /// passed since it last moved. Does nothing when `auto` is off.
+    /// Leaves framebuffer 0 bound and changes GL state behind the back of `GlState`.
+    pub unsafe fn update(&mut self, scene: u32, delta_time: f32) -> f32 {
+        if !self.settings.auto {
+            return self.exposure();
+        }
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::Viewport(0, 0, LUMINANCE_SIZE as i32, LUMINANCE_SIZE as i32);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::BLEND);
+        self.shader.useProgram();
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, scene);
+        self.shader.setInt(c_str!("image"), 0);
+        gl::BindVertexArray(self.empty_vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        gl::BindVertexArray(0);
+
+        // the oldest measurement, in the buffer about to be reused
+        let slot = self.next_readback;
+        let mut average_log = None;
+        if !self.fences[slot].is_null() {
+            let status = gl::ClientWaitSync(self.fences[slot], 0, 0);
+            if status == gl::ALREADY_SIGNALED || status == gl::CONDITION_SATISFIED {
+                let mut value = 0.0f32;
+                gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.readbacks[slot]);
+                gl::GetBufferSubData(gl::PIXEL_PACK_BUFFER, 0, 4, &mut value as *mut f32 as *mut _);
+                average_log = Some(value);
+            }
+            gl::DeleteSync(self.fences[slot]);
+        }
+
+        // averaging the mip levels gives the mean of the logs, copy out the last one
+        gl::BindTexture(gl::TEXTURE_2D, self.luminance);
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+        let last_level = (LUMINANCE_SIZE as f32).log2() as i32;
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.readbacks[slot]);
+        gl::GetTexImage(gl::TEXTURE_2D, last_level, gl::RED, gl::FLOAT, ptr::null_mut());
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        self.fences[slot] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        self.next_readback = (slot + 1) % READBACK_FRAMES;
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        gl::Enable(gl::DEPTH_TEST);
+
+        self.pending_time += delta_time.max(0.0);
+        if let Some(average_log) = average_log {
+            let settings = &self.settings;
+            let target = (settings.key / average_log.exp()).max(settings.min_exposure).min(settings.max_exposure);
+            if target.is_finite() {
+                let blend = 1.0 - (-self.pending_time * settings.adaptation_speed).exp();
+                self.adapted += (target - self.adapted) * blend;
+            }
+            self.pending_time = 0.0;
+        }
+        self.exposure()
+    }
+}
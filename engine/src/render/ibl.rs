@@ -0,0 +1,129 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use gl;
+
+use crate::model::Shader;
+use crate::render::cubemap::Cubemap;
+use crate::render::shader_path;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// The irradiance map is bound to this unit, the prefiltered map and the BRDF lookup
+/// table to the two after it. Units below are left for the up to five material textures.
+pub const IBL_TEXTURE_UNIT: u32 = 5;
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Mip levels of the prefiltered map, roughness 0 at the top to 1 at the last.
+const PREFILTERED_LEVELS: i32 = 5;
+const BRDF_LUT_SIZE: i32 = 512;
+
+/// Image based lighting precomputed from an environment cube map, for the split sum
+/// approximation: diffuse irradiance, specular radiance prefiltered for increasing
+/// roughness down the mip chain, and the scale and bias the BRDF applies to it.
+pub struct IblMaps {
+    pub irradiance: Cubemap,
+    pub prefiltered: Cubemap,
+    /// RG: scale and bias to F0, by cos(view angle) and roughness.
+    pub brdf_lut: u32
+}
+
+impl IblMaps {
+    /// Convolves `environment`, which should have mipmaps, an HDR one for best results.
+    pub fn new(environment: &Cubemap) -> IblMaps {
+        unsafe {
+            // created before binding the environment, creating a cube map unbinds unit 0's
+            let irradiance = Cubemap::new(IRRADIANCE_SIZE, gl::RGB16F, false);
+            let prefiltered = Cubemap::new(PREFILTERED_SIZE, gl::RGB16F, true);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, environment.id);
+
+            let shader = Shader::new(&shader_path("cubemap_capture.vert"), &shader_path("ibl_irradiance.frag"));
+            shader.useProgram();
+            shader.setInt(c_str!("environment"), 0);
+            irradiance.render_faces(0, &shader, |_| {});
+            gl::DeleteProgram(shader.ID);
+
+            let shader = Shader::new(&shader_path("cubemap_capture.vert"), &shader_path("ibl_prefilter.frag"));
+            shader.useProgram();
+            shader.setInt(c_str!("environment"), 0);
+            shader.setFloat(c_str!("environmentSize"), environment.size as f32);
+            for level in 0..PREFILTERED_LEVELS {
+                shader.setFloat(c_str!("roughness"), level as f32 / (PREFILTERED_LEVELS - 1) as f32);
+                prefiltered.render_faces(level, &shader, |_| {});
+            }
+            gl::DeleteProgram(shader.ID);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+
+            IblMaps { irradiance, prefiltered, brdf_lut: integrate_brdf() }
+        }
+    }
+}
+
+/// Binds `ibl` to its units and sets the `irradianceMap`, `prefilterMap`, `brdfLUT`,
+/// `prefilterLevels` and `hasIbl` uniforms of `shader`, which has to be in use.
+/// The samplers are set even without maps, so no cube sampler is left on a 2D texture's unit.
+pub unsafe fn bind_ibl(shader: &Shader, ibl: Option<&IblMaps>) {
+    // the Phong and G-buffer shaders have no image based lighting
+    if !shader.has_uniform(c_str!("hasIbl")) {
+        return;
+    }
+    let (irradiance, prefiltered, brdf_lut) = ibl.map_or((0, 0, 0), |ibl| (ibl.irradiance.id, ibl.prefiltered.id, ibl.brdf_lut));
+    gl::ActiveTexture(gl::TEXTURE0 + IBL_TEXTURE_UNIT);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, irradiance);
+    gl::ActiveTexture(gl::TEXTURE0 + IBL_TEXTURE_UNIT + 1);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, prefiltered);
+    gl::ActiveTexture(gl::TEXTURE0 + IBL_TEXTURE_UNIT + 2);
+    gl::BindTexture(gl::TEXTURE_2D, brdf_lut);
+    gl::ActiveTexture(gl::TEXTURE0);
+
+    shader.setInt(c_str!("irradianceMap"), IBL_TEXTURE_UNIT as i32);
+    shader.setInt(c_str!("prefilterMap"), IBL_TEXTURE_UNIT as i32 + 1);
+    shader.setInt(c_str!("brdfLUT"), IBL_TEXTURE_UNIT as i32 + 2);
+    shader.setFloat(c_str!("prefilterLevels"), PREFILTERED_LEVELS as f32);
+    shader.setBool(c_str!("hasIbl"), ibl.is_some());
+}
+
+/// Renders the BRDF lookup table, it only depends on the BRDF, not the environment.
+unsafe fn integrate_brdf() -> u32 {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG16F as i32, BRDF_LUT_SIZE, BRDF_LUT_SIZE, 0, gl::RG, gl::FLOAT, ptr::null());
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+
+    let mut viewport = [0i32; 4];
+    gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+    let (mut framebuffer, mut vao) = (0, 0);
+    gl::GenFramebuffers(1, &mut framebuffer);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+    gl::Viewport(0, 0, BRDF_LUT_SIZE, BRDF_LUT_SIZE);
+    gl::Disable(gl::DEPTH_TEST);
+
+    let shader = Shader::new(&shader_path("fullscreen.vert"), &shader_path("ibl_brdf.frag"));
+    shader.useProgram();
+    gl::GenVertexArrays(1, &mut vao);
+    gl::BindVertexArray(vao);
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+    gl::BindVertexArray(0);
+
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DeleteVertexArrays(1, &vao);
+    gl::DeleteProgram(shader.ID);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    gl::DeleteFramebuffers(1, &framebuffer);
+    gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    texture
+}
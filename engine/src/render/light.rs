@@ -0,0 +1,94 @@
+use std::ffi::CString;
+
+use cgmath::prelude::*;
+use cgmath::{ Deg, Vector3 };
+
+use crate::model::Shader;
+use crate::render::shadow::ShadowMaps;
+
+/// Lights beyond this many are ignored by the shaders.
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    /// Infinitely far away, like the sun. `direction` is the way the light travels.
+    Directional { direction: Vector3<f32> },
+    /// Shines in every direction, fading out to nothing at `range`.
+    Point { position: Vector3<f32>, range: f32 },
+    /// A cone around `direction`, full strength inside `inner_angle` and fading out to
+    /// `outer_angle`, both measured from the axis.
+    Spot { position: Vector3<f32>, direction: Vector3<f32>, range: f32, inner_angle: Deg<f32>, outer_angle: Deg<f32> }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub cast_shadows: bool
+}
+
+impl Light {
+    pub fn directional(direction: Vector3<f32>, color: Vector3<f32>) -> Light {
+        Light {
+            kind: LightKind::Directional { direction: direction.normalize() },
+            color,
+            intensity: 1.0,
+            cast_shadows: true
+        }
+    }
+
+    pub fn point(position: Vector3<f32>, range: f32, color: Vector3<f32>) -> Light {
+        Light {
+            kind: LightKind::Point { position, range },
+            color,
+            intensity: 1.0,
+            cast_shadows: true
+        }
+    }
+
+    pub fn spot(position: Vector3<f32>, direction: Vector3<f32>, range: f32, inner_angle: Deg<f32>, outer_angle: Deg<f32>,
+                color: Vector3<f32>) -> Light {
+        Light {
+            kind: LightKind::Spot { position, direction: direction.normalize(), range, inner_angle, outer_angle },
+            color,
+            intensity: 1.0,
+            cast_shadows: true
+        }
+    }
+}
+
+/// Sets the `lights` array and `lightCount` of a shader, with the shadow slot of every light
+/// if `shadows` was rendered for the same lights. The shader has to be in use.
+pub unsafe fn apply_lights(shader: &Shader, lights: &[Light], shadows: Option<&ShadowMaps>) {
+    let count = lights.len().min(MAX_LIGHTS);
+    if lights.len() > MAX_LIGHTS {
+        println!("{} lights, only the first {} are used", lights.len(), MAX_LIGHTS);
+    }
+    shader.setInt(&name("lightCount"), count as i32);
+
+    for (i, light) in lights.iter().take(count).enumerate() {
+        let field = |field: &str| name(&format!("lights[{}].{}", i, field));
+        let (kind, position, direction, range, inner, outer) = match light.kind {
+            LightKind::Directional { direction } =>
+                (0, Vector3::zero(), direction, 0.0, 1.0, 1.0),
+            LightKind::Point { position, range } =>
+                (1, position, Vector3::zero(), range, 1.0, 1.0),
+            LightKind::Spot { position, direction, range, inner_angle, outer_angle } =>
+                (2, position, direction, range, inner_angle.cos(), outer_angle.cos())
+        };
+        shader.setInt(&field("kind"), kind);
+        shader.setVector3(&field("position"), &position);
+        shader.setVector3(&field("direction"), &direction);
+        shader.setVector3(&field("color"), &(light.color * light.intensity));
+        shader.setFloat(&field("range"), range);
+        shader.setFloat(&field("innerCos"), inner);
+        shader.setFloat(&field("outerCos"), outer);
+        let slot = shadows.and_then(|shadows| shadows.slot(i)).map_or(-1, |slot| slot as i32);
+        shader.setInt(&field("shadow"), slot);
+    }
+}
+
+fn name(name: &str) -> CString {
+    CString::new(name).expect("CString::new failed")
+}
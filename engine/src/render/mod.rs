@@ -0,0 +1,40 @@
+pub mod state;
+pub use state::{ GlState, FrameStats };
+
+pub mod queue;
+pub use queue::{ RenderQueue, DrawItem };
+
+pub mod camera;
+pub use camera::CameraView;
+
+pub mod light;
+pub use light::{ Light, LightKind, apply_lights };
+
+pub mod shadow;
+pub use shadow::{ ShadowMaps, ShadowSettings };
+
+pub mod cubemap;
+pub use cubemap::Cubemap;
+
+pub mod skybox;
+pub use skybox::{ Skybox, bind_environment };
+
+pub mod ibl;
+pub use ibl::{ IblMaps, bind_ibl };
+
+pub mod deferred;
+pub use deferred::{ DeferredRenderer, GBuffer, GBufferView, RenderPath };
+
+pub mod hdr;
+pub use hdr::{ AutoExposure, ExposureSettings, ToneMapper };
+
+pub mod bloom;
+pub use bloom::{ Bloom, BloomSettings };
+
+pub mod post;
+pub use post::{ PostEffect, PostProcessStack, RenderTarget };
+
+/// Path of one of the engine's own shaders, independent of the working directory.
+pub fn shader_path(file: &str) -> String {
+    format!("{}/src/shaders/{}", env!("CARGO_MANIFEST_DIR"), file)
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::ffi::{ CStr, CString };
+use std::ptr;
+
+use gl;
+
+use crate::model::Shader;
+use crate::render::bloom::{ Bloom, BloomSettings };
+use crate::render::hdr::ToneMapper;
+use crate::render::shader_path;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// Offscreen framebuffer with one color texture and a depth-stencil renderbuffer.
+pub struct RenderTarget {
+    pub framebuffer: u32,
+    pub color: u32,
+    depth: u32,
+    internal_format: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl RenderTarget {
+    /// `internal_format` of the color texture, e.g. `gl::RGBA8` or `gl::RGBA16F`.
+    pub fn new(width: u32, height: u32, internal_format: u32) -> RenderTarget {
+        let mut target = RenderTarget { framebuffer: 0, color: 0, depth: 0, internal_format, width, height };
+        unsafe { target.allocate(); }
+        target
+    }
+
+    /// Recreates the attachments if the size changed.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        unsafe {
+            self.delete();
+            self.allocate();
+        }
+    }
+
+    unsafe fn allocate(&mut self) {
+        gl::GenFramebuffers(1, &mut self.framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+
+        gl::GenTextures(1, &mut self.color);
+        gl::BindTexture(gl::TEXTURE_2D, self.color);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, self.internal_format as i32, self.width as i32, self.height as i32, 0,
+                       gl::RGBA, gl::FLOAT, ptr::null());
+        // linear, FXAA and friends sample between texels
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.color, 0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        // same format as the default framebuffer and the G-buffer, so depth can be blitted between them
+        gl::GenRenderbuffers(1, &mut self.depth);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, self.width as i32, self.height as i32);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, self.depth);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            println!("ERROR::FRAMEBUFFER:: Framebuffer is not complete!");
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+
+    unsafe fn delete(&mut self) {
+        gl::DeleteTextures(1, &self.color);
+        gl::DeleteRenderbuffers(1, &self.depth);
+        gl::DeleteFramebuffers(1, &self.framebuffer);
+    }
+}
+
+/// A full screen pass. Its fragment shader reads the previous image from `image`, with
+/// `texelSize` set to the size of one of its pixels, and every parameter as a float uniform.
+pub struct PostEffect {
+    pub name: String,
+    pub shader: Shader,
+    pub enabled: bool,
+    pub params: HashMap<String, f32>,
+    /// Set for the bloom effect, blurs the image into `bloomTexture` before the pass runs.
+    bloom: Option<Bloom>
+}
+
+impl PostEffect {
+    /// `fragment_path` is run with the shared full screen vertex shader.
+    pub fn new(name: &str, fragment_path: &str, params: &[(&str, f32)]) -> PostEffect {
+        PostEffect {
+            name: name.to_string(),
+            shader: Shader::new(&shader_path("fullscreen.vert"), fragment_path),
+            enabled: true,
+            params: params.iter().map(|&(name, value)| (name.to_string(), value)).collect(),
+            bloom: None
+        }
+    }
+
+    pub fn set_param(&mut self, name: &str, value: f32) {
+        match self.params.get_mut(name) {
+            Some(param) => *param = value,
+            None => println!("Post effect {} has no parameter {}", self.name, name)
+        }
+    }
+
+    /// Its `exposure` is set every frame from `Engine::exposure`.
+    pub fn tonemap(operator: ToneMapper) -> PostEffect {
+        PostEffect::new("tonemap", &shader_path("post_tonemap.frag"), &[
+            ("exposure", 1.0),
+            ("operator", operator.param()),
+            ("whitePoint", 11.2)
+        ])
+    }
+
+    /// Belongs before tone mapping, it needs the HDR colors to tell what is bright.
+    pub fn bloom() -> PostEffect {
+        let mut effect = PostEffect::new("bloom", &shader_path("post_bloom.frag"), &[
+            ("threshold", 1.0),
+            ("knee", 0.5),
+            ("radius", 1.0),
+            ("intensity", 0.05)
+        ]);
+        effect.bloom = Some(Bloom::new());
+        effect
+    }
+
+    pub fn gamma() -> PostEffect {
+        PostEffect::new("gamma", &shader_path("post_gamma.frag"), &[("gamma", 2.2)])
+    }
+
+    pub fn fxaa() -> PostEffect {
+        PostEffect::new("fxaa", &shader_path("post_fxaa.frag"), &[
+            ("spanMax", 8.0),
+            ("reduceMul", 1.0 / 8.0),
+            ("reduceMin", 1.0 / 128.0)
+        ])
+    }
+
+    pub fn vignette() -> PostEffect {
+        PostEffect::new("vignette", &shader_path("post_vignette.frag"), &[
+            ("strength", 0.5),
+            ("radius", 0.75),
+            ("softness", 0.45)
+        ])
+    }
+
+    pub fn sharpen() -> PostEffect {
+        PostEffect::new("sharpen", &shader_path("post_sharpen.frag"), &[("amount", 0.3)])
+    }
+
+    pub fn grayscale() -> PostEffect {
+        PostEffect::new("grayscale", &shader_path("post_grayscale.frag"), &[("amount", 1.0)])
+    }
+}
+
+/// The scene is drawn into an offscreen target, then every enabled effect runs in order,
+/// each reading the output of the one before. The last one draws to the screen.
+pub struct PostProcessStack {
+    pub effects: Vec<PostEffect>,
+    /// What the scene is drawn into, 16 bit float so later effects get values above 1.
+    scene: RenderTarget,
+    /// Effects alternate between these.
+    ping_pong: [RenderTarget; 2],
+    empty_vao: u32
+}
+
+impl PostProcessStack {
+    pub fn new(width: u32, height: u32) -> PostProcessStack {
+        let mut empty_vao = 0;
+        unsafe { gl::GenVertexArrays(1, &mut empty_vao); }
+        PostProcessStack {
+            effects: Vec::new(),
+            scene: RenderTarget::new(width, height, gl::RGBA16F),
+            ping_pong: [RenderTarget::new(width, height, gl::RGBA16F), RenderTarget::new(width, height, gl::RGBA16F)],
+            empty_vao
+        }
+    }
+
+    /// Every built-in effect. Bloom, tone mapping, gamma correction and FXAA are enabled,
+    /// in that order, as FXAA works best on gamma corrected colors.
+    pub fn with_defaults(width: u32, height: u32) -> PostProcessStack {
+        let mut stack = PostProcessStack::new(width, height);
+        stack.push(PostEffect::bloom());
+        stack.push(PostEffect::tonemap(ToneMapper::Aces));
+        stack.push(PostEffect::gamma());
+        stack.push(PostEffect::fxaa());
+        for mut effect in vec![PostEffect::sharpen(), PostEffect::vignette(), PostEffect::grayscale()] {
+            effect.enabled = false;
+            stack.push(effect);
+        }
+        stack
+    }
+
+    pub fn push(&mut self, effect: PostEffect) {
+        self.effects.push(effect);
+    }
+
+    pub fn effect_mut(&mut self, name: &str) -> Option<&mut PostEffect> {
+        self.effects.iter_mut().find(|effect| effect.name == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        match self.effect_mut(name) {
+            Some(effect) => effect.enabled = enabled,
+            None => println!("No post effect named {}", name)
+        }
+    }
+
+    /// Moves the effect called `name` to position `index` in the chain.
+    pub fn move_effect(&mut self, name: &str, index: usize) {
+        match self.effects.iter().position(|effect| effect.name == name) {
+            Some(from) => {
+                let effect = self.effects.remove(from);
+                let index = index.min(self.effects.len());
+                self.effects.insert(index, effect);
+            }
+            None => println!("No post effect named {}", name)
+        }
+    }
+
+    /// Rebuilds the shaders of the effects, bloom's blur included, whose files changed.
+    /// Returns the programs that were replaced.
+    pub fn reload_shaders(&mut self) -> Vec<u32> {
+        let mut replaced = Vec::new();
+        for effect in &mut self.effects {
+            replaced.extend(effect.shader.reload_if_changed());
+            if let Some(bloom) = effect.bloom.as_mut() {
+                replaced.extend(bloom.reload_shaders());
+            }
+        }
+        replaced
+    }
+
+    /// Color texture of the scene target, HDR until the effects have run.
+    pub fn scene_texture(&self) -> u32 {
+        self.scene.color
+    }
+
+    /// Binds and clears the scene target, sized to the screen, and returns its framebuffer.
+    pub unsafe fn begin(&mut self, width: u32, height: u32) -> u32 {
+        self.scene.resize(width, height);
+        for target in &mut self.ping_pong {
+            target.resize(width, height);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.scene.framebuffer);
+        gl::Viewport(0, 0, width as i32, height as i32);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        self.scene.framebuffer
+    }
+
+    /// Runs the enabled effects over what was drawn since `begin` and puts the result on
+    /// screen. Changes GL state behind the back of `GlState`.
+    pub unsafe fn finish(&mut self) {
+        let (width, height) = (self.scene.width, self.scene.height);
+        let enabled: Vec<usize> = (0..self.effects.len()).filter(|&i| self.effects[i].enabled).collect();
+        if enabled.is_empty() {
+            let (width, height) = (width as i32, height as i32);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.scene.framebuffer);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            return;
+        }
+
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::BLEND);
+        gl::BindVertexArray(self.empty_vao);
+        let mut source = self.scene.color;
+        for (n, &i) in enabled.iter().enumerate() {
+            let effect = &mut self.effects[i];
+            let mut bloom_texture = None;
+            if let Some(bloom) = effect.bloom.as_mut() {
+                let settings = BloomSettings {
+                    threshold: effect.params.get("threshold").cloned().unwrap_or(1.0),
+                    knee: effect.params.get("knee").cloned().unwrap_or(0.5),
+                    radius: effect.params.get("radius").cloned().unwrap_or(1.0)
+                };
+                bloom_texture = Some(bloom.render(source, width, height, &settings));
+            }
+
+            let last = n + 1 == enabled.len();
+            let target = &self.ping_pong[n % 2];
+            gl::BindFramebuffer(gl::FRAMEBUFFER, if last { 0 } else { target.framebuffer });
+
+            effect.shader.useProgram();
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, source);
+            effect.shader.setInt(c_str!("image"), 0);
+            if let Some(texture) = bloom_texture {
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                effect.shader.setInt(c_str!("bloomTexture"), 1);
+                gl::ActiveTexture(gl::TEXTURE0);
+            }
+            effect.shader.setVec2(c_str!("texelSize"), 1.0 / width as f32, 1.0 / height as f32);
+            for (name, &value) in &effect.params {
+                // some parameters are for the stage before the pass, like bloom's threshold
+                let name = CString::new(name.as_str()).expect("CString::new failed");
+                if effect.shader.has_uniform(&name) {
+                    effect.shader.setFloat(&name, value);
+                }
+            }
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            source = target.color;
+        }
+        gl::BindVertexArray(0);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
@@ -0,0 +1,249 @@
+use std::cmp::Ordering;
+use std::ffi::CStr;
+
+use cgmath::prelude::*;
+use cgmath::{ Matrix4, Vector4 };
+
+use crate::model::{ AlphaMode, Instance, Material, Mesh, Model, Shader };
+use crate::render::state::GlState;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// One draw call: a mesh with its material, drawn once or instanced.
+pub struct DrawItem<'a> {
+    pub shader: &'a Shader,
+    pub mesh: &'a Mesh,
+    pub material: Option<&'a Material>,
+    pub lod: usize,
+    /// One instance is drawn as a plain draw with its matrix as the `model` uniform.
+    pub instances: Vec<Instance>,
+    /// View space distance to the camera, of the nearest instance.
+    pub depth: f32,
+    pub transparent: bool
+}
+
+/// Draw calls collected over a frame, sorted before they are issued so GL state changes
+/// as little as possible and blending composites correctly.
+pub struct RenderQueue<'a> {
+    items: Vec<DrawItem<'a>>
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new() -> RenderQueue<'a> {
+        RenderQueue { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, item: DrawItem<'a>) {
+        self.items.push(item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Moves the transparent items into a queue of their own, e.g. to draw the sky between them.
+    pub fn take_transparent(&mut self) -> RenderQueue<'a> {
+        let (transparent, opaque) = self.items.drain(..).partition(|item| item.transparent);
+        self.items = opaque;
+        RenderQueue { items: transparent }
+    }
+
+    /// Keeps only the items `keep` returns true for, e.g. to draw opaque and transparent
+    /// items in different passes.
+    pub fn retain<F: FnMut(&DrawItem<'a>) -> bool>(&mut self, keep: F) {
+        self.items.retain(keep);
+    }
+
+    /// Queues every mesh of `model`. Nodes sharing an opaque mesh become one instanced item,
+    /// transparent ones stay separate so they can be sorted by depth.
+    pub fn push_model(&mut self, model: &'a Model, shader: &'a Shader, transform: &Matrix4<f32>, view: &Matrix4<f32>) {
+        let batches = model.collect_batches(transform);
+        for (mesh_index, instances) in batches.into_iter().enumerate() {
+            if instances.is_empty() {
+                continue;
+            }
+            let mesh = &model.meshes[mesh_index];
+            let material = mesh.material_id.and_then(|id| model.materials.get(id));
+            let transparent = material.map_or(false, |m| m.alpha_mode == AlphaMode::Blend);
+            let depth_of = |instance: &Instance| {
+                let c = mesh.center;
+                -(view * instance.model * Vector4::new(c.x, c.y, c.z, 1.0)).z
+            };
+
+            if transparent {
+                for instance in instances {
+                    self.push(DrawItem {
+                        shader, mesh, material,
+                        lod: model.current_lod,
+                        depth: depth_of(&instance),
+                        instances: vec![instance],
+                        transparent
+                    });
+                }
+            } else {
+                let depth = instances.iter().map(|i| depth_of(i)).fold(std::f32::INFINITY, f32::min);
+                self.push(DrawItem {
+                    shader, mesh, material,
+                    lod: model.current_lod,
+                    depth,
+                    instances,
+                    transparent
+                });
+            }
+        }
+    }
+
+    /// Opaque items first, grouped by shader, material and texture, then front to back so
+    /// early depth testing rejects hidden fragments. Transparent items after, back to front.
+    pub fn sort(&mut self) {
+        self.items.sort_by(|a, b| {
+            match (a.transparent, b.transparent) {
+                (false, true) => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (true, true) => b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal),
+                (false, false) => a.shader.ID.cmp(&b.shader.ID)
+                    .then(material_key(a).cmp(&material_key(b)))
+                    .then(texture_key(a).cmp(&texture_key(b)))
+                    .then(a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal))
+            }
+        });
+    }
+
+    /// Sorts and issues every item, then empties the queue.
+    pub unsafe fn execute(&mut self, state: &mut GlState) {
+        self.sort();
+        for item in &self.items {
+            state.use_program(item.shader.ID);
+            state.set_blend(item.transparent);
+            state.set_depth_write(!item.transparent);
+
+            if item.instances.len() == 1 {
+                item.shader.setMat4(c_str!("model"), &item.instances[0].model);
+            } else {
+                item.shader.setMat4(c_str!("model"), &Matrix4::identity());
+            }
+            state.stats.uniform_sets += 1;
+            apply_material(state, item.shader, item.mesh, item.material);
+            item.mesh.draw_queued(state, item.shader, item.lod, &item.instances);
+        }
+        // leave depth writes on for whatever draws next
+        state.set_depth_write(true);
+        state.release_samplers();
+        self.items.clear();
+    }
+}
+
+/// Material uniforms as last set on a program, to skip setting them again while the
+/// sorted queue draws items of the same material.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct MaterialUniforms {
+    specular: f32,
+    shininess: f32,
+    reflectivity: f32,
+    ior: f32,
+    base_color: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    emissive: [f32; 3],
+    /// Whether the mesh has each of `TEXTURE_FLAGS`.
+    textures: [bool; 5]
+}
+
+impl MaterialUniforms {
+    fn new(mesh: &Mesh, material: Option<&Material>) -> MaterialUniforms {
+        let default = Material::default();
+        let (specular, shininess) = match material {
+            Some(m) => ((m.specular[0] + m.specular[1] + m.specular[2]) / 3.0, m.shininess),
+            None => (0.5, 32.0)
+        };
+        let material = material.unwrap_or(&default);
+        let mut textures = [false; 5];
+        for (has, &(type_, _)) in textures.iter_mut().zip(TEXTURE_FLAGS.iter()) {
+            *has = mesh.textures.iter().any(|texture| texture.type_ == type_);
+        }
+        MaterialUniforms {
+            specular,
+            shininess: if shininess > 0.0 { shininess } else { 32.0 },
+            reflectivity: material.reflectivity,
+            ior: material.ior,
+            base_color: material.base_color,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            emissive: material.emissive,
+            textures
+        }
+    }
+}
+
+/// Texture types the PBR shader has a `material.has_<type>` flag for, with the flag.
+const TEXTURE_FLAGS: [(&str, &[u8]); 5] = [
+    ("texture_diffuse", b"material.has_texture_diffuse\0"),
+    ("texture_metallic_roughness", b"material.has_texture_metallic_roughness\0"),
+    ("texture_normal", b"material.has_texture_normal\0"),
+    ("texture_occlusion", b"material.has_texture_occlusion\0"),
+    ("texture_emissive", b"material.has_texture_emissive\0")
+];
+
+/// Sets the lighting parameters of the material, or defaults for meshes without one,
+/// for the Phong and the PBR shaders alike, each only if the shader has it. Skipped when
+/// the program already has them.
+unsafe fn apply_material(state: &mut GlState, shader: &Shader, mesh: &Mesh, material: Option<&Material>) {
+    let uniforms = MaterialUniforms::new(mesh, material);
+    if !state.material_changed(shader.ID, &uniforms) {
+        state.stats.skipped += 1;
+        return;
+    }
+    let mut sets = 0;
+    let floats = [
+        (c_str!("material.specularStrength"), uniforms.specular),
+        (c_str!("material.shininess"), uniforms.shininess),
+        (c_str!("material.reflectivity"), uniforms.reflectivity),
+        (c_str!("material.ior"), uniforms.ior),
+        (c_str!("material.metallic"), uniforms.metallic),
+        (c_str!("material.roughness"), uniforms.roughness)
+    ];
+    for &(name, value) in floats.iter().filter(|&&(name, _)| shader.has_uniform(name)) {
+        shader.setFloat(name, value);
+        sets += 1;
+    }
+    if shader.has_uniform(c_str!("material.baseColor")) {
+        let c = uniforms.base_color;
+        shader.setVec4(c_str!("material.baseColor"), c[0], c[1], c[2], c[3]);
+        sets += 1;
+    }
+    if shader.has_uniform(c_str!("material.emissive")) {
+        let e = uniforms.emissive;
+        shader.setVec3(c_str!("material.emissive"), e[0], e[1], e[2]);
+        sets += 1;
+    }
+    for (&has, &(_, name)) in uniforms.textures.iter().zip(TEXTURE_FLAGS.iter()) {
+        let name = CStr::from_bytes_with_nul_unchecked(name);
+        if shader.has_uniform(name) {
+            shader.setBool(name, has);
+            sets += 1;
+        }
+    }
+    state.stats.uniform_sets += sets;
+}
+
+fn material_key(item: &DrawItem) -> usize {
+    item.material.map_or(0, |m| m as *const Material as usize)
+}
+
+fn texture_key(item: &DrawItem) -> u32 {
+    item.mesh.textures.first().map_or(0, |t| t.id)
+}
@@ -0,0 +1,382 @@
+use std::ffi::CString;
+use std::ptr;
+
+use cgmath::prelude::*;
+use cgmath::{ ortho, perspective, Deg, Matrix4, Point3, Vector3, Vector4 };
+use gl;
+
+use crate::model::Shader;
+use crate::render::camera::CameraView;
+use crate::render::cubemap::face_view;
+use crate::render::light::{ Light, LightKind };
+use crate::render::shader_path;
+
+pub const MAX_CASCADES: usize = 4;
+pub const MAX_SPOT_SHADOWS: usize = 4;
+pub const MAX_POINT_SHADOWS: usize = 4;
+/// Shadow maps are bound from this texture unit on, past the ones materials use:
+/// the cascades, then the spot maps, then one unit per point light cube.
+pub const SHADOW_TEXTURE_UNIT: u32 = 8;
+
+/// Near plane of spot and point light projections.
+const LIGHT_NEAR: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// Size of each cascade and spot light map, in texels.
+    pub resolution: u32,
+    /// Size of each face of a point light cube map, in texels.
+    pub point_resolution: u32,
+    /// Cascades of the directional light, 1 to `MAX_CASCADES`.
+    pub cascades: usize,
+    /// 0 splits the view distance evenly between cascades, 1 logarithmically.
+    pub split_lambda: f32,
+    /// Directional shadows end this far from the camera.
+    pub max_distance: f32,
+    /// Subtracted from the depth a fragment is compared with.
+    pub bias: f32,
+    /// Moves fragments along their normal before the lookup, in texels of the cascade.
+    pub normal_bias: f32,
+    /// Depth offset by slope while rendering the maps, as for `glPolygonOffset`.
+    pub slope_bias: f32,
+    /// PCF kernel radius in texels: 0 is a single (hardware filtered) lookup, 1 is 3x3 and so on.
+    pub pcf_radius: i32
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 2048,
+            point_resolution: 1024,
+            cascades: 4,
+            split_lambda: 0.75,
+            max_distance: 50.0,
+            bias: 0.0005,
+            normal_bias: 1.0,
+            slope_bias: 1.5,
+            pcf_radius: 1
+        }
+    }
+}
+
+/// Depth maps of the shadow casting lights, rendered every frame before the scene.
+/// The first directional light gets cascaded maps, spot lights a perspective map each and
+/// point lights a cube map each, up to the `MAX_*` limits. Other lights are unshadowed.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    depth_shader: Shader,
+    point_shader: Shader,
+    framebuffer: u32,
+    /// Depth texture arrays, 0 until first needed.
+    cascade_maps: u32,
+    spot_maps: u32,
+    point_maps: Vec<u32>,
+    /// Settings the textures were made with.
+    allocated: ShadowSettings,
+
+    cascade_matrices: Vec<Matrix4<f32>>,
+    /// Far end of each cascade, as view space distance.
+    cascade_splits: Vec<f32>,
+    /// World size of one texel of each cascade.
+    cascade_texel_sizes: Vec<f32>,
+    spot_matrices: Vec<Matrix4<f32>>,
+    point_far: Vec<f32>,
+    /// For each light of the last render, its slot in the maps of its kind.
+    slots: Vec<Option<usize>>
+}
+
+impl ShadowMaps {
+    pub fn new(settings: ShadowSettings) -> ShadowMaps {
+        let depth_shader = Shader::new(&shader_path("shadow_depth.vert"), &shader_path("shadow_depth.frag"));
+        let point_shader = Shader::new(&shader_path("shadow_depth.vert"), &shader_path("shadow_point.frag"));
+        let mut framebuffer = 0;
+        unsafe {
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            // depth only
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        ShadowMaps {
+            settings,
+            depth_shader,
+            point_shader,
+            framebuffer,
+            cascade_maps: 0,
+            spot_maps: 0,
+            point_maps: Vec::new(),
+            allocated: settings,
+            cascade_matrices: Vec::new(),
+            cascade_splits: Vec::new(),
+            cascade_texel_sizes: Vec::new(),
+            spot_matrices: Vec::new(),
+            point_far: Vec::new(),
+            slots: Vec::new()
+        }
+    }
+
+    /// Rebuilds the shaders whose files changed, see `Shader::reload_if_changed`.
+    /// Returns the programs that were replaced.
+    pub fn reload_shaders(&mut self) -> Vec<u32> {
+        vec![self.depth_shader.reload_if_changed(), self.point_shader.reload_if_changed()].into_iter().flatten().collect()
+    }
+
+    /// Slot of light `index` in the maps of its kind, if it got shadows in the last render.
+    pub fn slot(&self, index: usize) -> Option<usize> {
+        self.slots.get(index).cloned().unwrap_or(None)
+    }
+
+    /// Renders the maps of `lights` as seen from `camera`. `draw_scene` draws every shadow
+    /// caster with the shader it is given, setting its `model` uniform, as `Model::draw` does.
+    /// Leaves the default framebuffer bound and the viewport as it was.
+    pub unsafe fn render<F: FnMut(&Shader)>(&mut self, lights: &[Light], camera: &CameraView, mut draw_scene: F) {
+        self.reallocate_if_changed();
+        self.slots = vec![None; lights.len()];
+        self.cascade_matrices.clear();
+        self.cascade_splits.clear();
+        self.cascade_texel_sizes.clear();
+        self.spot_matrices.clear();
+        self.point_far.clear();
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(self.settings.slope_bias, 1.0);
+
+        let mut has_directional = false;
+        for (i, light) in lights.iter().enumerate() {
+            if !light.cast_shadows {
+                continue;
+            }
+            match light.kind {
+                LightKind::Directional { direction } if !has_directional => {
+                    has_directional = true;
+                    self.render_cascades(direction, camera, &mut draw_scene);
+                    self.slots[i] = Some(0);
+                }
+                LightKind::Spot { position, direction, range, outer_angle, .. } if self.spot_matrices.len() < MAX_SPOT_SHADOWS => {
+                    let slot = self.spot_matrices.len();
+                    self.render_spot(slot, position, direction, range, outer_angle, &mut draw_scene);
+                    self.slots[i] = Some(slot);
+                }
+                LightKind::Point { position, range } if self.point_far.len() < MAX_POINT_SHADOWS => {
+                    let slot = self.point_far.len();
+                    self.render_point(slot, position, range, &mut draw_scene);
+                    self.slots[i] = Some(slot);
+                }
+                _ => {}
+            }
+        }
+
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+
+    fn reallocate_if_changed(&mut self) {
+        let (old, new) = (self.allocated, self.settings);
+        unsafe {
+            if old.resolution != new.resolution || old.cascades != new.cascades {
+                gl::DeleteTextures(1, &self.cascade_maps);
+                gl::DeleteTextures(1, &self.spot_maps);
+                self.cascade_maps = 0;
+                self.spot_maps = 0;
+            }
+            if old.point_resolution != new.point_resolution {
+                gl::DeleteTextures(self.point_maps.len() as i32, self.point_maps.as_ptr());
+                self.point_maps.clear();
+            }
+        }
+        self.allocated = new;
+    }
+
+    unsafe fn render_cascades<F: FnMut(&Shader)>(&mut self, direction: Vector3<f32>, camera: &CameraView, draw_scene: &mut F) {
+        let cascades = self.settings.cascades.max(1).min(MAX_CASCADES);
+        let resolution = self.settings.resolution as f32;
+        if self.cascade_maps == 0 {
+            self.cascade_maps = depth_texture_array(self.settings.resolution, cascades);
+        }
+
+        let near = camera.near;
+        let far = camera.far.min(self.settings.max_distance);
+        let inverse_view = camera.view.invert().unwrap_or_else(Matrix4::identity);
+        let tan_y = (camera.fovy / 2.0).tan();
+        let tan_x = tan_y * camera.aspect;
+        // any up vector that is not parallel to the light
+        let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+
+        let mut start = near;
+        for cascade in 0..cascades {
+            // practical split scheme, between logarithmic and even splits
+            let fraction = (cascade + 1) as f32 / cascades as f32;
+            let log = near * (far / near).powf(fraction);
+            let even = near + (far - near) * fraction;
+            let end = self.settings.split_lambda * log + (1.0 - self.settings.split_lambda) * even;
+
+            // bounding sphere of the slice of the view frustum, its size does not change as the
+            // camera turns, so shadow edges do not swim
+            let mut corners: Vec<Vector3<f32>> = Vec::with_capacity(8);
+            for &distance in &[start, end] {
+                for &(sx, sy) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    let corner = Vector4::new(sx * tan_x * distance, sy * tan_y * distance, -distance, 1.0);
+                    corners.push((inverse_view * corner).truncate());
+                }
+            }
+            let center = corners.iter().fold(Vector3::zero(), |sum, c| sum + c) / 8.0;
+            let radius = corners.iter().map(|c| (c - center).magnitude()).fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // casters behind the slice, up to the shadow distance, still have to land in the map
+            let back = radius + self.settings.max_distance;
+            let eye = center - direction * back;
+            let light_view = Matrix4::look_at(Point3::from_vec(eye), Point3::from_vec(center), up);
+            let mut light_projection = ortho(-radius, radius, -radius, radius, 0.0, back + radius);
+
+            // snap to whole texels, so moving the camera does not make edges crawl
+            let origin = light_projection * light_view * Vector4::new(0.0, 0.0, 0.0, 1.0) * (resolution / 2.0);
+            light_projection.w.x += (origin.x.round() - origin.x) * 2.0 / resolution;
+            light_projection.w.y += (origin.y.round() - origin.y) * 2.0 / resolution;
+
+            let matrix = light_projection * light_view;
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.cascade_maps, 0, cascade as i32);
+            self.draw_depth(&matrix, self.settings.resolution, None, draw_scene);
+
+            self.cascade_matrices.push(matrix);
+            self.cascade_splits.push(end);
+            self.cascade_texel_sizes.push(2.0 * radius / resolution);
+            start = end;
+        }
+    }
+
+    unsafe fn render_spot<F: FnMut(&Shader)>(&mut self, slot: usize, position: Vector3<f32>, direction: Vector3<f32>,
+                                             range: f32, outer_angle: Deg<f32>, draw_scene: &mut F) {
+        if self.spot_maps == 0 {
+            self.spot_maps = depth_texture_array(self.settings.resolution, MAX_SPOT_SHADOWS);
+        }
+        let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+        let view = Matrix4::look_at(Point3::from_vec(position), Point3::from_vec(position + direction), up);
+        let fovy = Deg((outer_angle.0 * 2.0).min(179.0));
+        let matrix = perspective(fovy, 1.0, LIGHT_NEAR, range) * view;
+
+        gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.spot_maps, 0, slot as i32);
+        self.draw_depth(&matrix, self.settings.resolution, None, draw_scene);
+        self.spot_matrices.push(matrix);
+    }
+
+    unsafe fn render_point<F: FnMut(&Shader)>(&mut self, slot: usize, position: Vector3<f32>, range: f32, draw_scene: &mut F) {
+        while self.point_maps.len() <= slot {
+            self.point_maps.push(depth_cube_map(self.settings.point_resolution));
+        }
+        let projection = perspective(Deg(90.0), 1.0, LIGHT_NEAR, range);
+        for face in 0..6 {
+            let view = face_view(position, face);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32,
+                                     self.point_maps[slot], 0);
+            self.draw_depth(&(projection * view), self.settings.point_resolution, Some((position, range)), draw_scene);
+        }
+        self.point_far.push(range);
+    }
+
+    /// Clears the attached depth map and draws the scene into it. Point lights store the
+    /// distance to the light, divided by its range, instead of the projected depth.
+    unsafe fn draw_depth<F: FnMut(&Shader)>(&self, light_space: &Matrix4<f32>, resolution: u32,
+                                            point: Option<(Vector3<f32>, f32)>, draw_scene: &mut F) {
+        gl::Viewport(0, 0, resolution as i32, resolution as i32);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+        let shader = if point.is_some() { &self.point_shader } else { &self.depth_shader };
+        shader.useProgram();
+        shader.setMat4(&name("lightSpace"), light_space);
+        if let Some((position, range)) = point {
+            shader.setVector3(&name("lightPos"), &position);
+            shader.setFloat(&name("far"), range);
+        }
+        draw_scene(shader);
+    }
+
+    /// Binds the maps to their texture units and sets the shadow uniforms of `shader`,
+    /// which has to be in use. Every shadow sampler gets its own unit even when unused,
+    /// GL refuses to draw when samplers of different types share one.
+    pub unsafe fn bind(&self, shader: &Shader) {
+        let cascade_unit = SHADOW_TEXTURE_UNIT;
+        let spot_unit = SHADOW_TEXTURE_UNIT + 1;
+        let point_unit = SHADOW_TEXTURE_UNIT + 2;
+
+        gl::ActiveTexture(gl::TEXTURE0 + cascade_unit);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.cascade_maps);
+        shader.setInt(&name("cascadeMaps"), cascade_unit as i32);
+        shader.setInt(&name("cascadeCount"), self.cascade_matrices.len() as i32);
+        for (i, matrix) in self.cascade_matrices.iter().enumerate() {
+            shader.setMat4(&name(&format!("cascadeMatrices[{}]", i)), matrix);
+            shader.setFloat(&name(&format!("cascadeSplits[{}]", i)), self.cascade_splits[i]);
+            shader.setFloat(&name(&format!("cascadeTexelSizes[{}]", i)), self.cascade_texel_sizes[i]);
+        }
+
+        gl::ActiveTexture(gl::TEXTURE0 + spot_unit);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.spot_maps);
+        shader.setInt(&name("spotMaps"), spot_unit as i32);
+        for (i, matrix) in self.spot_matrices.iter().enumerate() {
+            shader.setMat4(&name(&format!("spotMatrices[{}]", i)), matrix);
+        }
+
+        for i in 0..MAX_POINT_SHADOWS {
+            let unit = point_unit + i as u32;
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.point_maps.get(i).cloned().unwrap_or(0));
+            shader.setInt(&name(&format!("pointMaps[{}]", i)), unit as i32);
+        }
+        for (i, &far) in self.point_far.iter().enumerate() {
+            shader.setFloat(&name(&format!("pointFar[{}]", i)), far);
+        }
+
+        shader.setFloat(&name("shadowBias"), self.settings.bias);
+        shader.setFloat(&name("shadowNormalBias"), self.settings.normal_bias);
+        shader.setInt(&name("pcfRadius"), self.settings.pcf_radius.max(0));
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+}
+
+/// Depth texture array set up for hardware depth comparison, everything outside is lit.
+unsafe fn depth_texture_array(resolution: u32, layers: usize) -> u32 {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+    gl::TexImage3D(gl::TEXTURE_2D_ARRAY, 0, gl::DEPTH_COMPONENT32F as i32, resolution as i32, resolution as i32,
+                   layers as i32, 0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+    set_compare_parameters(gl::TEXTURE_2D_ARRAY);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+    let border = [1.0f32; 4];
+    gl::TexParameterfv(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+    gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+    texture
+}
+
+unsafe fn depth_cube_map(resolution: u32) -> u32 {
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture);
+    for face in 0..6 {
+        gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, 0, gl::DEPTH_COMPONENT32F as i32,
+                       resolution as i32, resolution as i32, 0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+    }
+    set_compare_parameters(gl::TEXTURE_CUBE_MAP);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+    texture
+}
+
+/// Linear filtering on a comparison sampler averages the 2x2 comparisons, a free bit of PCF.
+unsafe fn set_compare_parameters(target: u32) {
+    gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(target, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+    gl::TexParameteri(target, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+}
+
+fn name(name: &str) -> CString {
+    CString::new(name).expect("CString::new failed")
+}
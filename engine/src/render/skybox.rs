@@ -0,0 +1,88 @@
+use std::ffi::CStr;
+
+use cgmath::Vector4;
+use gl;
+
+use crate::model::{ primitives, Mesh, Shader };
+use crate::render::camera::CameraView;
+use crate::render::cubemap::Cubemap;
+use crate::render::shader_path;
+
+/// Macro to get c strings from literals without runtime overhead
+/// Literal must not contain any interior nul bytes!
+macro_rules! c_str {
+    ($literal:expr) => {
+        CStr::from_bytes_with_nul_unchecked(concat!($literal, "\0").as_bytes())
+    }
+}
+
+/// Material shaders find the environment cube map on this unit, past the shadow maps.
+pub const ENVIRONMENT_TEXTURE_UNIT: u32 = 14;
+
+/// Background drawn from a cube map, after opaque objects and at the far plane so it only
+/// fills the pixels nothing else covered.
+pub struct Skybox {
+    pub cubemap: Cubemap,
+    shader: Shader,
+    cube: Mesh
+}
+
+impl Skybox {
+    pub fn new(cubemap: Cubemap) -> Skybox {
+        Skybox {
+            cubemap,
+            shader: Shader::new(&shader_path("skybox.vert"), &shader_path("skybox.frag")),
+            cube: primitives::cube(2.0, 1).into_mesh(Vec::new())
+        }
+    }
+
+    /// Six images in the order right, left, top, bottom, front, back.
+    pub fn from_faces(paths: &[&str; 6]) -> Skybox {
+        Skybox::new(Cubemap::from_faces(paths))
+    }
+
+    pub fn from_equirect(path: &str, size: u32) -> Skybox {
+        Skybox::new(Cubemap::from_equirect(path, size))
+    }
+
+    /// Rebuilds the shader if its files changed, see `Shader::reload_if_changed`.
+    /// Returns the program that was replaced.
+    pub fn reload_shaders(&mut self) -> Option<u32> {
+        self.shader.reload_if_changed()
+    }
+
+    /// Changes GL state behind the back of `GlState`.
+    pub unsafe fn draw(&self, camera: &CameraView) {
+        // only the rotation, the sky is infinitely far away
+        let mut view = camera.view;
+        view.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+
+        gl::DepthFunc(gl::LEQUAL);
+        gl::DepthMask(gl::FALSE);
+        gl::Disable(gl::CULL_FACE);
+        self.shader.useProgram();
+        self.shader.setMat4(c_str!("view"), &view);
+        self.shader.setMat4(c_str!("projection"), &camera.projection());
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.cubemap.id);
+        self.shader.setInt(c_str!("skybox"), 0);
+        self.cube.draw(&self.shader);
+        gl::DepthMask(gl::TRUE);
+        gl::DepthFunc(gl::LESS);
+    }
+}
+
+/// Binds `environment` for reflections and refractions and sets `environmentMap` and
+/// `hasEnvironment` of `shader`, which has to be in use.
+pub unsafe fn bind_environment(shader: &Shader, environment: Option<&Cubemap>) {
+    // the PBR shader lights with the IBL maps instead
+    if !shader.has_uniform(c_str!("hasEnvironment")) {
+        return;
+    }
+    gl::ActiveTexture(gl::TEXTURE0 + ENVIRONMENT_TEXTURE_UNIT);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, environment.map_or(0, |cubemap| cubemap.id));
+    gl::ActiveTexture(gl::TEXTURE0);
+    // set even without a cube map, a samplerCube left on unit 0 would clash with the 2D textures there
+    shader.setInt(c_str!("environmentMap"), ENVIRONMENT_TEXTURE_UNIT as i32);
+    shader.setBool(c_str!("hasEnvironment"), environment.is_some());
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+
+use gl;
+
+use crate::model::shader::Shader;
+use crate::render::queue::MaterialUniforms;
+
+/// Counts of what a frame asked GL to do, and of what the state cache saved it from doing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    /// Instances drawn by instanced draw calls, plain draws not included.
+    pub instances: u32,
+    pub triangles: u64,
+    pub program_binds: u32,
+    pub vertex_array_binds: u32,
+    pub texture_binds: u32,
+    pub uniform_sets: u32,
+    pub state_changes: u32,
+    /// Binds, uniform sets and state changes skipped because the value was already current.
+    pub skipped: u32
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} draws ({} instances, {} triangles), binds: {} programs, {} VAOs, {} textures, {} uniforms, {} state changes, {} skipped",
+               self.draw_calls, self.instances, self.triangles, self.program_binds, self.vertex_array_binds,
+               self.texture_binds, self.uniform_sets, self.state_changes, self.skipped)
+    }
+}
+
+/// Mirror of the GL state the render queue touches, so setting something to the value it
+/// already has costs nothing.
+/// Anything that changes GL state without going through here has to call `invalidate` afterwards.
+pub struct GlState {
+    program: Option<u32>,
+    vertex_array: Option<u32>,
+    active_unit: Option<u32>,
+    /// Texture bound to TEXTURE_2D on each unit.
+    textures: Vec<Option<u32>>,
+    /// Sampler object bound to each unit, 0 for none.
+    samplers: Vec<Option<u32>>,
+    blend: Option<bool>,
+    depth_write: Option<bool>,
+    cull_face: Option<bool>,
+    /// Last value of int uniforms, i.e. sampler units, per program and location.
+    int_uniforms: HashMap<(u32, i32), i32>,
+    /// Material uniforms last set on each program.
+    materials: HashMap<u32, MaterialUniforms>,
+    pub stats: FrameStats
+}
+
+impl GlState {
+    pub fn new() -> GlState {
+        GlState {
+            program: None,
+            vertex_array: None,
+            active_unit: None,
+            textures: vec![None; 32],
+            samplers: vec![None; 32],
+            blend: None,
+            depth_write: None,
+            cull_face: None,
+            int_uniforms: HashMap::new(),
+            materials: HashMap::new(),
+            stats: FrameStats::default()
+        }
+    }
+
+    /// Forgets everything, the next call of each kind goes to GL.
+    /// Uniform values are kept, programs keep them no matter what else is bound.
+    pub fn invalidate(&mut self) {
+        self.program = None;
+        self.vertex_array = None;
+        self.active_unit = None;
+        for texture in &mut self.textures {
+            *texture = None;
+        }
+        for sampler in &mut self.samplers {
+            *sampler = None;
+        }
+        self.blend = None;
+        self.depth_write = None;
+        self.cull_face = None;
+    }
+
+    /// Forgets the cached uniform values of `program`, e.g. after it was relinked.
+    pub fn forget_program(&mut self, program: u32) {
+        self.int_uniforms.retain(|&(p, _), _| p != program);
+        self.materials.remove(&program);
+        if self.program == Some(program) {
+            self.program = None;
+        }
+    }
+
+    /// Returns the stats gathered since the last call and starts counting again.
+    pub fn take_stats(&mut self) -> FrameStats {
+        std::mem::replace(&mut self.stats, FrameStats::default())
+    }
+
+    pub unsafe fn use_program(&mut self, program: u32) {
+        if self.program == Some(program) {
+            self.stats.skipped += 1;
+            return;
+        }
+        gl::UseProgram(program);
+        self.program = Some(program);
+        self.stats.program_binds += 1;
+    }
+
+    pub unsafe fn bind_vertex_array(&mut self, vertex_array: u32) {
+        if self.vertex_array == Some(vertex_array) {
+            self.stats.skipped += 1;
+            return;
+        }
+        gl::BindVertexArray(vertex_array);
+        self.vertex_array = Some(vertex_array);
+        self.stats.vertex_array_binds += 1;
+    }
+
+    pub unsafe fn bind_texture(&mut self, unit: u32, texture: u32) {
+        if unit as usize >= self.textures.len() {
+            self.textures.resize(unit as usize + 1, None);
+        }
+        if self.textures[unit as usize] == Some(texture) {
+            self.stats.skipped += 1;
+            return;
+        }
+        if self.active_unit != Some(unit) {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            self.active_unit = Some(unit);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        self.textures[unit as usize] = Some(texture);
+        self.stats.texture_binds += 1;
+    }
+
+    pub unsafe fn bind_sampler(&mut self, unit: u32, sampler: u32) {
+        if unit as usize >= self.samplers.len() {
+            self.samplers.resize(unit as usize + 1, None);
+        }
+        if self.samplers[unit as usize] == Some(sampler) {
+            self.stats.skipped += 1;
+            return;
+        }
+        gl::BindSampler(unit, sampler);
+        self.samplers[unit as usize] = Some(sampler);
+    }
+
+    /// Unbinds every sampler object bound through `bind_sampler`, passes outside the
+    /// queue expect the modes of their textures.
+    pub unsafe fn release_samplers(&mut self) {
+        for (unit, sampler) in self.samplers.iter_mut().enumerate() {
+            if let Some(id) = *sampler {
+                if id != 0 {
+                    gl::BindSampler(unit as u32, 0);
+                    *sampler = Some(0);
+                }
+            }
+        }
+    }
+
+    /// Whether `material` differs from what was last set on `program`, which it is
+    /// remembered as from now on.
+    pub(crate) fn material_changed(&mut self, program: u32, material: &MaterialUniforms) -> bool {
+        if self.materials.get(&program) == Some(material) {
+            return false;
+        }
+        self.materials.insert(program, *material);
+        true
+    }
+
+    /// Sets an int uniform of `shader`, which has to be the current program, e.g. which
+    /// unit a sampler reads. Names the shader does not have are reported like setInt does.
+    pub unsafe fn set_int(&mut self, shader: &Shader, name: &CStr, value: i32) {
+        debug_assert_eq!(self.program, Some(shader.ID), "set_int on a program that is not current");
+        let location = shader.int_location(name);
+        if location == -1 {
+            return;
+        }
+        let key = (shader.ID, location);
+        if self.int_uniforms.get(&key) == Some(&value) {
+            self.stats.skipped += 1;
+            return;
+        }
+        gl::Uniform1i(location, value);
+        self.int_uniforms.insert(key, value);
+        self.stats.uniform_sets += 1;
+    }
+
+    pub unsafe fn set_blend(&mut self, enabled: bool) {
+        if self.blend == Some(enabled) {
+            self.stats.skipped += 1;
+            return;
+        }
+        if enabled {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        } else {
+            gl::Disable(gl::BLEND);
+        }
+        self.blend = Some(enabled);
+        self.stats.state_changes += 1;
+    }
+
+    pub unsafe fn set_depth_write(&mut self, enabled: bool) {
+        if self.depth_write == Some(enabled) {
+            self.stats.skipped += 1;
+            return;
+        }
+        gl::DepthMask(if enabled { gl::TRUE } else { gl::FALSE });
+        self.depth_write = Some(enabled);
+        self.stats.state_changes += 1;
+    }
+
+    pub unsafe fn set_cull_face(&mut self, enabled: bool) {
+        if self.cull_face == Some(enabled) {
+            self.stats.skipped += 1;
+            return;
+        }
+        if enabled {
+            gl::Enable(gl::CULL_FACE);
+        } else {
+            gl::Disable(gl::CULL_FACE);
+        }
+        self.cull_face = Some(enabled);
+        self.stats.state_changes += 1;
+    }
+
+    /// Counts a draw call of `triangles` triangles per instance.
+    pub fn count_draw(&mut self, triangles: usize, instances: usize) {
+        self.stats.draw_calls += 1;
+        if instances > 1 {
+            self.stats.instances += instances as u32;
+        }
+        self.stats.triangles += (triangles * instances.max(1)) as u64;
+    }
+}
@@ -0,0 +1,46 @@
+#version 330 core
+out vec3 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+// texel size of the image read, the one twice as large
+uniform vec2 texelSize;
+// first step only: keep just what is brighter than the threshold
+uniform bool prefilter;
+uniform float threshold;
+uniform float knee;
+
+// soft threshold, quadratic around the knee so there is no hard edge
+vec3 brightPart(vec3 color)
+{
+    float brightness = max(color.r, max(color.g, color.b));
+    float softness = knee * threshold + 0.00001;
+    float soft = clamp(brightness - threshold + softness, 0.0, 2.0 * softness);
+    soft = soft * soft / (4.0 * softness);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+vec3 tap(float x, float y)
+{
+    return texture(image, TexCoords + vec2(x, y) * texelSize).rgb;
+}
+
+// 13 taps as in Call of Duty: Advanced Warfare, four overlapping 2x2 boxes and a center one
+void main()
+{
+    vec3 a = tap(-2.0,  2.0), b = tap(0.0,  2.0), c = tap(2.0,  2.0);
+    vec3 d = tap(-2.0,  0.0), e = tap(0.0,  0.0), f = tap(2.0,  0.0);
+    vec3 g = tap(-2.0, -2.0), h = tap(0.0, -2.0), i = tap(2.0, -2.0);
+    vec3 j = tap(-1.0,  1.0), k = tap(1.0,  1.0);
+    vec3 l = tap(-1.0, -1.0), m = tap(1.0, -1.0);
+
+    vec3 color = e * 0.125
+               + (a + c + g + i) * 0.03125
+               + (b + d + f + h) * 0.0625
+               + (j + k + l + m) * 0.125;
+    if (prefilter)
+        color = brightPart(color);
+    FragColor = max(color, vec3(0.0));
+}
@@ -0,0 +1,25 @@
+#version 330 core
+out vec3 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+// texel size of the image read, the smaller one
+uniform vec2 texelSize;
+uniform float radius;
+
+// 3x3 tent filter, added onto the larger level by blending
+void main()
+{
+    vec2 r = texelSize * radius;
+    vec3 color = texture(image, TexCoords).rgb * 4.0;
+    color += (texture(image, TexCoords + vec2(-r.x, 0.0)).rgb
+            + texture(image, TexCoords + vec2( r.x, 0.0)).rgb
+            + texture(image, TexCoords + vec2(0.0, -r.y)).rgb
+            + texture(image, TexCoords + vec2(0.0,  r.y)).rgb) * 2.0;
+    color += texture(image, TexCoords + vec2(-r.x, -r.y)).rgb
+           + texture(image, TexCoords + vec2( r.x, -r.y)).rgb
+           + texture(image, TexCoords + vec2(-r.x,  r.y)).rgb
+           + texture(image, TexCoords + vec2( r.x,  r.y)).rgb;
+    FragColor = color / 16.0;
+}
@@ -0,0 +1,13 @@
+#version 330 core
+layout (location = 0) in vec3 aPos;
+
+// direction from the center of the cube map
+out vec3 LocalPos;
+
+uniform mat4 viewProjection;
+
+void main()
+{
+    LocalPos = aPos;
+    gl_Position = viewProjection * vec4(aPos, 1.0);
+}
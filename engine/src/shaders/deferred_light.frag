@@ -0,0 +1,56 @@
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D gAlbedo;
+uniform sampler2D gNormal;
+uniform sampler2D gMaterial;
+uniform sampler2D gDepth;
+
+uniform vec2 screenSize;
+uniform mat4 view;
+uniform mat4 inverseViewProjection;
+// the light this pass draws, -1 for the ambient term and reflections
+uniform int lightIndex;
+
+// set by bind_environment
+uniform samplerCube environmentMap;
+uniform bool hasEnvironment;
+
+// rebuilt from the G-buffer in main, named like the forward shader's inputs
+vec3 FragPos;
+float ViewDepth;
+
+#include "include/lights.glsl"
+#include "include/shadows.glsl"
+#include "include/phong.glsl"
+
+void main()
+{
+    vec2 uv = gl_FragCoord.xy / screenSize;
+    float depth = texture(gDepth, uv).r;
+    if (depth == 1.0)
+        discard;
+
+    vec4 world = inverseViewProjection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+    FragPos = world.xyz / world.w;
+    ViewDepth = -(view * vec4(FragPos, 1.0)).z;
+
+    vec3 albedo = texture(gAlbedo, uv).rgb;
+    vec3 normal = normalize(texture(gNormal, uv).xyz);
+    vec3 params = texture(gMaterial, uv).rgb;
+    vec3 viewDir = normalize(viewPos - FragPos);
+    // the forward shader mixes the lit color towards the reflection, here every pass
+    // is weighted by it instead, they are summed by blending
+    float reflectivity = hasEnvironment ? params.b : 0.0;
+
+    if (lightIndex < 0) {
+        vec3 ambient = albedo * 0.1 * (1.0 - reflectivity);
+        if (reflectivity > 0.0)
+            ambient += texture(environmentMap, reflect(-viewDir, normal)).rgb * reflectivity;
+        FragColor = vec4(ambient, 1.0);
+        return;
+    }
+
+    vec3 lit = albedo * shade(lights[lightIndex], normal, viewDir, params.r, params.g * 256.0);
+    FragColor = vec4(lit * (1.0 - reflectivity), 1.0);
+}
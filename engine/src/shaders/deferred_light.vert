@@ -0,0 +1,18 @@
+#version 330 core
+layout (location = 0) in vec3 aPos;
+
+// the sphere around a point or spot light, scaled to its range
+uniform mat4 volume;
+uniform mat4 viewProjection;
+// directional lights and the ambient term cover the whole screen
+uniform bool fullscreen;
+
+void main()
+{
+    if (fullscreen) {
+        vec2 corner = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+        gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+    } else {
+        gl_Position = viewProjection * volume * vec4(aPos, 1.0);
+    }
+}
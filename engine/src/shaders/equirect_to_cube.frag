@@ -0,0 +1,16 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec3 LocalPos;
+
+uniform sampler2D panorama;
+
+const vec2 invAtan = vec2(0.1591, 0.3183);
+
+void main()
+{
+    vec3 dir = normalize(LocalPos);
+    // longitude and latitude, mapped to 0..1
+    vec2 uv = vec2(atan(dir.z, dir.x), asin(dir.y)) * invAtan + 0.5;
+    FragColor = vec4(texture(panorama, uv).rgb, 1.0);
+}
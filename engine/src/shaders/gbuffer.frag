@@ -0,0 +1,28 @@
+#version 330 core
+layout (location = 0) out vec4 gAlbedo;
+layout (location = 1) out vec4 gNormal;
+layout (location = 2) out vec4 gMaterial;
+
+in vec2 TexCoords;
+in vec4 Color;
+in vec3 FragPos;
+in vec3 Normal;
+in float ViewDepth;
+
+struct Material {
+    sampler2D texture_diffuse1;
+    float specularStrength;
+    float shininess;
+    float reflectivity;
+    float ior;
+};
+
+uniform Material material;
+
+void main()
+{
+    gAlbedo = vec4((texture(material.texture_diffuse1, TexCoords) * Color).rgb, 1.0);
+    gNormal = vec4(normalize(Normal), 0.0);
+    // shininess is stored divided by 256, the target is 8 bit
+    gMaterial = vec4(material.specularStrength, material.shininess / 256.0, material.reflectivity, 0.0);
+}
@@ -0,0 +1,30 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D gAlbedo;
+uniform sampler2D gNormal;
+uniform sampler2D gMaterial;
+uniform sampler2D gDepth;
+
+// 1 albedo, 2 normal, 3 material, 4 depth
+uniform int target;
+uniform float near;
+uniform float far;
+
+void main()
+{
+    if (target == 1) {
+        FragColor = vec4(texture(gAlbedo, TexCoords).rgb, 1.0);
+    } else if (target == 2) {
+        FragColor = vec4(texture(gNormal, TexCoords).xyz * 0.5 + 0.5, 1.0);
+    } else if (target == 3) {
+        FragColor = vec4(texture(gMaterial, TexCoords).rg, 0.0, 1.0);
+    } else {
+        // linear, so more than the near plane shows up
+        float z = texture(gDepth, TexCoords).r * 2.0 - 1.0;
+        float linear = 2.0 * near * far / (far + near - z * (far - near));
+        FragColor = vec4(vec3(linear / far), 1.0);
+    }
+}
@@ -0,0 +1,13 @@
+#version 330 core
+out float LogLuminance;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+
+void main()
+{
+    float luminance = dot(texture(image, TexCoords).rgb, vec3(0.2126, 0.7152, 0.0722));
+    // small floor so black pixels do not pull the mean to minus infinity
+    LogLuminance = log(max(luminance, 0.0001));
+}
@@ -0,0 +1,61 @@
+#version 330 core
+out vec2 FragColor;
+
+in vec2 TexCoords;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 1024u;
+
+float radicalInverse(uint bits)
+{
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec3 importanceSampleGGX(vec2 xi, float roughness)
+{
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    return vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+}
+
+// Smith-Schlick with k = a / 2, the remapping used for image based lighting
+float geometrySchlick(float NdotX, float roughness)
+{
+    float k = roughness * roughness / 2.0;
+    return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+// scale and bias to F0 of the specular BRDF integrated over the hemisphere, with the
+// normal along z, for cos(view angle) along x and roughness along y
+void main()
+{
+    float NdotV = max(TexCoords.x, 0.0001);
+    float roughness = TexCoords.y;
+    vec3 V = vec3(sqrt(1.0 - NdotV * NdotV), 0.0, NdotV);
+
+    float scale = 0.0;
+    float bias = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; ++i) {
+        vec2 xi = vec2(float(i) / float(SAMPLE_COUNT), radicalInverse(i));
+        vec3 H = importanceSampleGGX(xi, roughness);
+        vec3 L = normalize(2.0 * dot(V, H) * H - V);
+        float NdotL = max(L.z, 0.0);
+        float NdotH = max(H.z, 0.0);
+        float VdotH = max(dot(V, H), 0.0);
+        if (NdotL > 0.0) {
+            float G = geometrySchlick(NdotV, roughness) * geometrySchlick(NdotL, roughness);
+            float visibility = G * VdotH / (NdotH * NdotV);
+            float fresnel = pow(1.0 - VdotH, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    FragColor = vec2(scale, bias) / float(SAMPLE_COUNT);
+}
@@ -0,0 +1,30 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec3 LocalPos;
+
+uniform samplerCube environment;
+
+const float PI = 3.14159265359;
+
+// cosine weighted average of the environment over the hemisphere around the direction
+void main()
+{
+    vec3 normal = normalize(LocalPos);
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    vec3 irradiance = vec3(0.0);
+    float samples = 0.0;
+    const float delta = 0.025;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            vec3 tangentSample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 dir = tangentSample.x * right + tangentSample.y * up + tangentSample.z * normal;
+            irradiance += texture(environment, dir).rgb * cos(theta) * sin(theta);
+            samples += 1.0;
+        }
+    }
+    FragColor = vec4(PI * irradiance / samples, 1.0);
+}
@@ -0,0 +1,24 @@
+// Light uniforms, set by apply_lights. MAX_LIGHTS can be defined lower for a variant.
+
+#ifndef MAX_LIGHTS
+#define MAX_LIGHTS 8
+#endif
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+struct Light {
+    int kind;
+    vec3 position;
+    vec3 direction;
+    vec3 color;
+    float range;
+    float innerCos;
+    float outerCos;
+    // slot in the shadow maps of its kind, -1 for none
+    int shadow;
+};
+
+uniform Light lights[MAX_LIGHTS];
+uniform int lightCount;
+uniform vec3 viewPos;
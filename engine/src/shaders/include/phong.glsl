@@ -0,0 +1,35 @@
+// Blinn-Phong for one light, with its shadow. Needs lights.glsl and shadows.glsl.
+
+vec3 shade(Light light, vec3 normal, vec3 viewDir, float specularStrength, float shininess)
+{
+    vec3 toLight;
+    float attenuation = 1.0;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        toLight = -light.direction;
+    } else {
+        toLight = light.position - FragPos;
+        float dist = length(toLight);
+        toLight /= dist;
+        float falloff = clamp(1.0 - pow(dist / light.range, 2.0), 0.0, 1.0);
+        attenuation = falloff * falloff;
+        if (light.kind == LIGHT_SPOT)
+            attenuation *= smoothstep(light.outerCos, light.innerCos, dot(-toLight, light.direction));
+    }
+    if (attenuation <= 0.0)
+        return vec3(0.0);
+
+    float shadow = 1.0;
+    if (light.shadow >= 0) {
+        if (light.kind == LIGHT_DIRECTIONAL)
+            shadow = directionalShadow(normal);
+        else if (light.kind == LIGHT_SPOT)
+            shadow = spotShadow(light.shadow, normal);
+        else
+            shadow = pointShadow(light.shadow, light.position, normal);
+    }
+
+    float diffuse = max(dot(normal, toLight), 0.0);
+    vec3 halfway = normalize(toLight + viewDir);
+    float specular = diffuse > 0.0 ? pow(max(dot(normal, halfway), 0.0), shininess) * specularStrength : 0.0;
+    return light.color * (diffuse + specular) * attenuation * shadow;
+}
@@ -0,0 +1,95 @@
+// Shadow maps, set by ShadowMaps::bind. Needs FragPos and ViewDepth declared before it.
+
+#define MAX_CASCADES 4
+#define MAX_SPOT_SHADOWS 4
+#define MAX_POINT_SHADOWS 4
+
+uniform sampler2DArrayShadow cascadeMaps;
+uniform mat4 cascadeMatrices[MAX_CASCADES];
+uniform float cascadeSplits[MAX_CASCADES];
+uniform float cascadeTexelSizes[MAX_CASCADES];
+uniform int cascadeCount;
+
+uniform sampler2DArrayShadow spotMaps;
+uniform mat4 spotMatrices[MAX_SPOT_SHADOWS];
+
+uniform samplerCubeShadow pointMaps[MAX_POINT_SHADOWS];
+uniform float pointFar[MAX_POINT_SHADOWS];
+
+uniform float shadowBias;
+uniform float shadowNormalBias;
+uniform int pcfRadius;
+
+// spread out directions for sampling around a cube map lookup
+const vec3 cubeOffsets[20] = vec3[](
+    vec3( 1,  1,  1), vec3( 1, -1,  1), vec3(-1, -1,  1), vec3(-1,  1,  1),
+    vec3( 1,  1, -1), vec3( 1, -1, -1), vec3(-1, -1, -1), vec3(-1,  1, -1),
+    vec3( 1,  1,  0), vec3( 1, -1,  0), vec3(-1, -1,  0), vec3(-1,  1,  0),
+    vec3( 1,  0,  1), vec3(-1,  0,  1), vec3( 1,  0, -1), vec3(-1,  0, -1),
+    vec3( 0,  1,  1), vec3( 0, -1,  1), vec3( 0, -1, -1), vec3( 0,  1, -1)
+);
+
+// averages the depth comparisons of a (2 * pcfRadius + 1)^2 texel square, 1 is fully lit
+float filterLayer(sampler2DArrayShadow maps, vec3 coords, float layer)
+{
+    if (coords.z > 1.0)
+        return 1.0;
+    vec2 texel = 1.0 / vec2(textureSize(maps, 0).xy);
+    float lit = 0.0;
+    for (int x = -pcfRadius; x <= pcfRadius; ++x)
+        for (int y = -pcfRadius; y <= pcfRadius; ++y)
+            lit += texture(maps, vec4(coords.xy + vec2(x, y) * texel, layer, coords.z - shadowBias));
+    float size = float(2 * pcfRadius + 1);
+    return lit / (size * size);
+}
+
+float directionalShadow(vec3 normal)
+{
+    int cascade = cascadeCount - 1;
+    for (int i = 0; i < cascadeCount; ++i) {
+        if (ViewDepth < cascadeSplits[i]) {
+            cascade = i;
+            break;
+        }
+    }
+    if (cascadeCount == 0 || ViewDepth > cascadeSplits[cascadeCount - 1])
+        return 1.0;
+    vec3 pos = FragPos + normal * shadowNormalBias * cascadeTexelSizes[cascade];
+    vec4 lightSpace = cascadeMatrices[cascade] * vec4(pos, 1.0);
+    return filterLayer(cascadeMaps, lightSpace.xyz * 0.5 + 0.5, float(cascade));
+}
+
+float spotShadow(int slot, vec3 normal)
+{
+    vec4 lightSpace = spotMatrices[slot] * vec4(FragPos, 1.0);
+    // a texel grows with the distance from the light, w is that distance along the axis
+    float texelSize = 2.0 * lightSpace.w / float(textureSize(spotMaps, 0).x);
+    lightSpace = spotMatrices[slot] * vec4(FragPos + normal * shadowNormalBias * texelSize, 1.0);
+    return filterLayer(spotMaps, lightSpace.xyz / lightSpace.w * 0.5 + 0.5, float(slot));
+}
+
+// samplers in an array may only be indexed with constants
+float pointLookup(int slot, vec4 coords)
+{
+    if (slot == 0) return texture(pointMaps[0], coords);
+    if (slot == 1) return texture(pointMaps[1], coords);
+    if (slot == 2) return texture(pointMaps[2], coords);
+    return texture(pointMaps[3], coords);
+}
+
+float pointShadow(int slot, vec3 lightPos, vec3 normal)
+{
+    float far = pointFar[slot];
+    vec3 toFrag = FragPos - lightPos;
+    float dist = length(toFrag);
+    float texelSize = 2.0 * dist / float(textureSize(pointMaps[0], 0).x);
+    toFrag += normal * shadowNormalBias * texelSize;
+    float depth = length(toFrag) / far - shadowBias;
+    if (pcfRadius == 0)
+        return pointLookup(slot, vec4(toFrag, depth));
+    float radius = float(pcfRadius) * texelSize;
+    float lit = 0.0;
+    for (int i = 0; i < 20; ++i)
+        lit += pointLookup(slot, vec4(toFrag + cubeOffsets[i] * radius, depth));
+    return lit / 20.0;
+}
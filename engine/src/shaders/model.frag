@@ -2,10 +2,55 @@
 out vec4 FragColor;
 
 in vec2 TexCoords;
+in vec4 Color;
+in vec3 FragPos;
+in vec3 Normal;
+in float ViewDepth;
 
-uniform sampler2D texture_diffuse1;
+struct Material {
+    sampler2D texture_diffuse1;
+    float specularStrength;
+    float shininess;
+    // mix towards the mirrored environment, 0 to 1
+    float reflectivity;
+    // index of refraction, above 1 transparent surfaces refract the environment
+    float ior;
+};
+
+uniform Material material;
+
+// set by bind_environment
+uniform samplerCube environmentMap;
+uniform bool hasEnvironment;
+
+#include "include/lights.glsl"
+#include "include/shadows.glsl"
+#include "include/phong.glsl"
 
 void main()
 {
-    FragColor = texture(texture_diffuse1, TexCoords);
+    vec4 base = texture(material.texture_diffuse1, TexCoords) * Color;
+    vec3 normal = normalize(Normal);
+    vec3 viewDir = normalize(viewPos - FragPos);
+
+    vec3 color = base.rgb;
+    if (lightCount > 0) {
+        vec3 lighting = vec3(0.1);
+        for (int i = 0; i < lightCount; ++i)
+            lighting += shade(lights[i], normal, viewDir, material.specularStrength, material.shininess);
+        color *= lighting;
+    }
+
+    float alpha = base.a;
+    if (hasEnvironment) {
+        if (material.reflectivity > 0.0)
+            color = mix(color, texture(environmentMap, reflect(-viewDir, normal)).rgb, material.reflectivity);
+        if (material.ior > 1.0 && alpha < 1.0) {
+            // what shows through is the environment, bent at the surface
+            vec3 refracted = texture(environmentMap, refract(-viewDir, normal, 1.0 / material.ior)).rgb;
+            color = mix(refracted, color, alpha);
+            alpha = 1.0;
+        }
+    }
+    FragColor = vec4(color, alpha);
 }
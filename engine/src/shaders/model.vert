@@ -2,8 +2,20 @@
 layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec3 aNormal;
 layout (location = 2) in vec2 aTexCoords;
+layout (location = 3) in vec4 aColor;
+layout (location = 4) in vec4 aTangent;
+// per instance, identity and white when not drawing instanced
+layout (location = 5) in mat4 aInstanceModel;
+layout (location = 9) in vec4 aInstanceColor;
 
 out vec2 TexCoords;
+out vec4 Color;
+out vec3 FragPos;
+out vec3 Normal;
+// w is the handedness of the bitangent
+out vec4 Tangent;
+// distance in front of the camera, picks the shadow cascade
+out float ViewDepth;
 
 uniform mat4 model;
 uniform mat4 view;
@@ -11,6 +23,15 @@ uniform mat4 projection;
 
 void main()
 {
+    mat4 world = model * aInstanceModel;
+    vec4 worldPos = world * vec4(aPos, 1.0);
+    vec4 viewPos = view * worldPos;
+
     TexCoords = aTexCoords;
-    gl_Position = projection * view * model * vec4(aPos, 1.0);
+    Color = aColor * aInstanceColor;
+    FragPos = worldPos.xyz;
+    Normal = mat3(transpose(inverse(world))) * aNormal;
+    Tangent = vec4(mat3(world) * aTangent.xyz, aTangent.w);
+    ViewDepth = -viewPos.z;
+    gl_Position = projection * viewPos;
 }
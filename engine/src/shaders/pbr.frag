@@ -0,0 +1,165 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+in vec4 Color;
+in vec3 FragPos;
+in vec3 Normal;
+in vec4 Tangent;
+in float ViewDepth;
+
+// metallic-roughness, as glTF defines it. Factors multiply the maps, missing maps count as white.
+struct Material {
+    sampler2D texture_diffuse1;
+    // metalness in blue, roughness in green
+    sampler2D texture_metallic_roughness1;
+    sampler2D texture_normal1;
+    sampler2D texture_occlusion1;
+    sampler2D texture_emissive1;
+    bool has_texture_diffuse;
+    bool has_texture_metallic_roughness;
+    bool has_texture_normal;
+    bool has_texture_occlusion;
+    bool has_texture_emissive;
+    vec4 baseColor;
+    float metallic;
+    float roughness;
+    vec3 emissive;
+};
+
+uniform Material material;
+
+// set by bind_ibl
+uniform samplerCube irradianceMap;
+uniform samplerCube prefilterMap;
+uniform sampler2D brdfLUT;
+uniform float prefilterLevels;
+uniform bool hasIbl;
+
+#include "include/lights.glsl"
+#include "include/shadows.glsl"
+
+const float PI = 3.14159265359;
+
+float distributionGGX(float NdotH, float roughness)
+{
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denom = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Smith-Schlick with k = (r + 1)^2 / 8, the remapping used for analytic lights
+float geometrySchlick(float NdotX, float roughness)
+{
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0)
+{
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 F0, float roughness)
+{
+    return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance for one light
+vec3 shadePbr(Light light, vec3 N, vec3 V, vec3 albedo, float metallic, float roughness, vec3 F0)
+{
+    vec3 L;
+    float attenuation = 1.0;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        L = -light.direction;
+    } else {
+        L = light.position - FragPos;
+        float dist = length(L);
+        L /= dist;
+        float falloff = clamp(1.0 - pow(dist / light.range, 2.0), 0.0, 1.0);
+        attenuation = falloff * falloff;
+        if (light.kind == LIGHT_SPOT)
+            attenuation *= smoothstep(light.outerCos, light.innerCos, dot(-L, light.direction));
+    }
+    float NdotL = max(dot(N, L), 0.0);
+    if (attenuation <= 0.0 || NdotL <= 0.0)
+        return vec3(0.0);
+
+    float shadow = 1.0;
+    if (light.shadow >= 0) {
+        if (light.kind == LIGHT_DIRECTIONAL)
+            shadow = directionalShadow(N);
+        else if (light.kind == LIGHT_SPOT)
+            shadow = spotShadow(light.shadow, N);
+        else
+            shadow = pointShadow(light.shadow, light.position, N);
+    }
+
+    vec3 H = normalize(V + L);
+    float NdotV = max(dot(N, V), 0.0001);
+    float D = distributionGGX(max(dot(N, H), 0.0), roughness);
+    float G = geometrySchlick(NdotV, roughness) * geometrySchlick(NdotL, roughness);
+    vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+    vec3 specular = D * G * F / (4.0 * NdotV * NdotL + 0.0001);
+    vec3 kD = (vec3(1.0) - F) * (1.0 - metallic);
+    return (kD * albedo / PI + specular) * light.color * NdotL * attenuation * shadow;
+}
+
+vec3 surfaceNormal()
+{
+    vec3 N = normalize(Normal);
+    if (!material.has_texture_normal)
+        return N;
+    vec3 T = normalize(Tangent.xyz - N * dot(N, Tangent.xyz));
+    vec3 B = cross(N, T) * Tangent.w;
+    vec3 mapped = texture(material.texture_normal1, TexCoords).xyz * 2.0 - 1.0;
+    return normalize(mat3(T, B, N) * mapped);
+}
+
+void main()
+{
+    vec4 base = material.baseColor * Color;
+    if (material.has_texture_diffuse)
+        base *= texture(material.texture_diffuse1, TexCoords);
+    float metallic = material.metallic;
+    float roughness = material.roughness;
+    if (material.has_texture_metallic_roughness) {
+        vec4 mr = texture(material.texture_metallic_roughness1, TexCoords);
+        metallic *= mr.b;
+        roughness *= mr.g;
+    }
+    // perfectly smooth surfaces make the highlights of point lights vanish
+    roughness = clamp(roughness, 0.04, 1.0);
+    float ao = material.has_texture_occlusion ? texture(material.texture_occlusion1, TexCoords).r : 1.0;
+    vec3 emissive = material.emissive;
+    if (material.has_texture_emissive)
+        emissive *= texture(material.texture_emissive1, TexCoords).rgb;
+
+    vec3 albedo = base.rgb;
+    vec3 N = surfaceNormal();
+    vec3 V = normalize(viewPos - FragPos);
+    // dielectrics reflect about 4% head on, metals their albedo
+    vec3 F0 = mix(vec3(0.04), albedo, metallic);
+
+    vec3 color = vec3(0.0);
+    for (int i = 0; i < lightCount; ++i)
+        color += shadePbr(lights[i], N, V, albedo, metallic, roughness, F0);
+
+    float NdotV = max(dot(N, V), 0.0);
+    if (hasIbl) {
+        vec3 F = fresnelSchlickRoughness(NdotV, F0, roughness);
+        vec3 kD = (vec3(1.0) - F) * (1.0 - metallic);
+        vec3 diffuse = texture(irradianceMap, N).rgb * albedo;
+        vec3 R = reflect(-V, N);
+        vec3 prefiltered = textureLod(prefilterMap, R, roughness * (prefilterLevels - 1.0)).rgb;
+        vec2 brdf = texture(brdfLUT, vec2(NdotV, roughness)).rg;
+        vec3 specular = prefiltered * (F * brdf.x + brdf.y);
+        color += (kD * diffuse + specular) * ao;
+    } else {
+        color += vec3(0.03) * albedo * ao;
+    }
+
+    FragColor = vec4(color + emissive, base.a);
+}
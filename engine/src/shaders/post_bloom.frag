@@ -0,0 +1,15 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+// set by the bloom stage, the blurred bright parts at half size
+uniform sampler2D bloomTexture;
+uniform float intensity;
+
+void main()
+{
+    vec4 color = texture(image, TexCoords);
+    FragColor = vec4(color.rgb + texture(bloomTexture, TexCoords).rgb * intensity, color.a);
+}
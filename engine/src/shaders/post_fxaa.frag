@@ -0,0 +1,41 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+uniform vec2 texelSize;
+// longest blur along an edge, in pixels
+uniform float spanMax;
+uniform float reduceMul;
+uniform float reduceMin;
+
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+
+// the classic single pass FXAA: find the edge direction from the luma of the corners,
+// blur along it and keep the result only if it stays within the local luma range
+void main()
+{
+    float lumaNW = dot(texture(image, TexCoords + vec2(-1.0, -1.0) * texelSize).rgb, LUMA);
+    float lumaNE = dot(texture(image, TexCoords + vec2( 1.0, -1.0) * texelSize).rgb, LUMA);
+    float lumaSW = dot(texture(image, TexCoords + vec2(-1.0,  1.0) * texelSize).rgb, LUMA);
+    float lumaSE = dot(texture(image, TexCoords + vec2( 1.0,  1.0) * texelSize).rgb, LUMA);
+    vec4 center = texture(image, TexCoords);
+    float lumaM = dot(center.rgb, LUMA);
+
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 dir = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)),
+                      (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * reduceMul, reduceMin);
+    float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+    dir = clamp(dir * rcpDirMin, vec2(-spanMax), vec2(spanMax)) * texelSize;
+
+    vec3 rgbA = 0.5 * (texture(image, TexCoords + dir * (1.0 / 3.0 - 0.5)).rgb
+                     + texture(image, TexCoords + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (texture(image, TexCoords + dir * -0.5).rgb
+                                   + texture(image, TexCoords + dir * 0.5).rgb);
+    float lumaB = dot(rgbB, LUMA);
+    FragColor = vec4((lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB, center.a);
+}
@@ -0,0 +1,13 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+uniform float gamma;
+
+void main()
+{
+    vec4 color = texture(image, TexCoords);
+    FragColor = vec4(pow(max(color.rgb, vec3(0.0)), vec3(1.0 / gamma)), color.a);
+}
@@ -0,0 +1,15 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+// 0 leaves the colors alone, 1 is fully gray
+uniform float amount;
+
+void main()
+{
+    vec4 color = texture(image, TexCoords);
+    float luma = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+    FragColor = vec4(mix(color.rgb, vec3(luma), amount), color.a);
+}
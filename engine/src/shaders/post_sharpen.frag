@@ -0,0 +1,20 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+uniform vec2 texelSize;
+uniform float amount;
+
+void main()
+{
+    vec4 center = texture(image, TexCoords);
+    vec3 neighbours = texture(image, TexCoords + vec2(texelSize.x, 0.0)).rgb
+                    + texture(image, TexCoords - vec2(texelSize.x, 0.0)).rgb
+                    + texture(image, TexCoords + vec2(0.0, texelSize.y)).rgb
+                    + texture(image, TexCoords - vec2(0.0, texelSize.y)).rgb;
+    // unsharp mask, the difference from the blurred neighbourhood is added back
+    vec3 sharpened = center.rgb + (center.rgb * 4.0 - neighbours) * amount;
+    FragColor = vec4(max(sharpened, vec3(0.0)), center.a);
+}
@@ -0,0 +1,47 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+uniform float exposure;
+// 0 Reinhard, 1 ACES, 2 filmic, see ToneMapper
+uniform float operator;
+// filmic only, the input that maps to white
+uniform float whitePoint;
+
+vec3 aces(vec3 x)
+{
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+vec3 hable(vec3 x)
+{
+    const float A = 0.15;
+    const float B = 0.50;
+    const float C = 0.10;
+    const float D = 0.20;
+    const float E = 0.02;
+    const float F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+void main()
+{
+    vec4 color = texture(image, TexCoords);
+    vec3 hdr = max(color.rgb, vec3(0.0)) * exposure;
+    vec3 mapped;
+    int op = int(operator + 0.5);
+    if (op == 0)
+        mapped = hdr / (hdr + vec3(1.0));
+    else if (op == 1)
+        mapped = aces(hdr);
+    else
+        mapped = hable(hdr * 2.0) / hable(vec3(whitePoint));
+    FragColor = vec4(mapped, color.a);
+}
@@ -0,0 +1,20 @@
+#version 330 core
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D image;
+// how dark the corners get
+uniform float strength;
+// distance from the center, 0.5 is the edge of the screen, where darkening starts
+uniform float radius;
+// width of the fade
+uniform float softness;
+
+void main()
+{
+    vec4 color = texture(image, TexCoords);
+    float dist = length(TexCoords - vec2(0.5)) * 1.414;
+    float vignette = smoothstep(radius, radius - softness, dist);
+    FragColor = vec4(color.rgb * mix(1.0, vignette, strength), color.a);
+}
@@ -0,0 +1,15 @@
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 5) in mat4 aInstanceModel;
+
+out vec3 FragPos;
+
+uniform mat4 model;
+uniform mat4 lightSpace;
+
+void main()
+{
+    vec4 worldPos = model * aInstanceModel * vec4(aPos, 1.0);
+    FragPos = worldPos.xyz;
+    gl_Position = lightSpace * worldPos;
+}
@@ -0,0 +1,11 @@
+#version 330 core
+in vec3 FragPos;
+
+uniform vec3 lightPos;
+uniform float far;
+
+void main()
+{
+    // linear distance to the light, what point light shadows compare against
+    gl_FragDepth = length(FragPos - lightPos) / far;
+}
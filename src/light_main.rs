@@ -26,6 +26,8 @@ mod macros;
 use cgmath::{Matrix4, Vector3, vec3, Point3, Deg, Rad, perspective};
 use cgmath::prelude::*;
 
+use engine::model::primitives;
+
 // settings
 const SCR_WIDTH: u32 = 800;
 const SCR_HEIGHT: u32 = 600;
@@ -68,7 +70,7 @@ pub fn main() {
 
     let lightPos = vec3(1.2, 1.0, 2.0);
     
-    let (lampShader, lightShader, VBO, cubeVAO, lightVAO) = unsafe {
+    let (lampShader, lightShader, VBO, EBO, indexCount, cubeVAO, lightVAO) = unsafe {
         
         gl::Enable(gl::DEPTH_TEST);
         
@@ -83,54 +85,17 @@ pub fn main() {
 
         // set up vertex data (and buffer(s)) and configure vertex attributes
         // ------------------------------------------------------------------
-        // HINT: type annotation is crucial since default for float literals is f64
-        let vertices: [f32; 216] = [
-            -0.5, -0.5, -0.5,  0.0,  0.0, -1.0,
-             0.5, -0.5, -0.5,  0.0,  0.0, -1.0,
-             0.5,  0.5, -0.5,  0.0,  0.0, -1.0,
-             0.5,  0.5, -0.5,  0.0,  0.0, -1.0,
-            -0.5,  0.5, -0.5,  0.0,  0.0, -1.0,
-            -0.5, -0.5, -0.5,  0.0,  0.0, -1.0,
-
-            -0.5, -0.5,  0.5,  0.0,  0.0,  1.0,
-             0.5, -0.5,  0.5,  0.0,  0.0,  1.0,
-             0.5,  0.5,  0.5,  0.0,  0.0,  1.0,
-             0.5,  0.5,  0.5,  0.0,  0.0,  1.0,
-            -0.5,  0.5,  0.5,  0.0,  0.0,  1.0,
-            -0.5, -0.5,  0.5,  0.0,  0.0,  1.0,
-
-            -0.5,  0.5,  0.5, -1.0,  0.0,  0.0,
-            -0.5,  0.5, -0.5, -1.0,  0.0,  0.0,
-            -0.5, -0.5, -0.5, -1.0,  0.0,  0.0,
-            -0.5, -0.5, -0.5, -1.0,  0.0,  0.0,
-            -0.5, -0.5,  0.5, -1.0,  0.0,  0.0,
-            -0.5,  0.5,  0.5, -1.0,  0.0,  0.0,
-
-             0.5,  0.5,  0.5,  1.0,  0.0,  0.0,
-             0.5,  0.5, -0.5,  1.0,  0.0,  0.0,
-             0.5, -0.5, -0.5,  1.0,  0.0,  0.0,
-             0.5, -0.5, -0.5,  1.0,  0.0,  0.0,
-             0.5, -0.5,  0.5,  1.0,  0.0,  0.0,
-             0.5,  0.5,  0.5,  1.0,  0.0,  0.0,
-
-            -0.5, -0.5, -0.5,  0.0, -1.0,  0.0,
-             0.5, -0.5, -0.5,  0.0, -1.0,  0.0,
-             0.5, -0.5,  0.5,  0.0, -1.0,  0.0,
-             0.5, -0.5,  0.5,  0.0, -1.0,  0.0,
-            -0.5, -0.5,  0.5,  0.0, -1.0,  0.0,
-            -0.5, -0.5, -0.5,  0.0, -1.0,  0.0,
-
-            -0.5,  0.5, -0.5,  0.0,  1.0,  0.0,
-             0.5,  0.5, -0.5,  0.0,  1.0,  0.0,
-             0.5,  0.5,  0.5,  0.0,  1.0,  0.0,
-             0.5,  0.5,  0.5,  0.0,  1.0,  0.0,
-            -0.5,  0.5,  0.5,  0.0,  1.0,  0.0,
-            -0.5,  0.5, -0.5,  0.0,  1.0,  0.0
-        ];
-
-        let (mut VBO, mut cubeVAO) = (0, 0);
+        // positions and normals interleaved, 6 floats per vertex
+        let cube = primitives::cube(1.0, 1);
+        let vertices: Vec<f32> = cube.vertices.iter()
+            .flat_map(|v| vec![v.position.x, v.position.y, v.position.z, v.normal.x, v.normal.y, v.normal.z])
+            .collect();
+        let indices = cube.indices;
+
+        let (mut VBO, mut cubeVAO, mut EBO) = (0, 0, 0);
         gl::GenVertexArrays(1, &mut cubeVAO);
         gl::GenBuffers(1, &mut VBO);
+        gl::GenBuffers(1, &mut EBO);
 
         gl::BindVertexArray(cubeVAO);
 
@@ -139,6 +104,12 @@ pub fn main() {
                     (vertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
                     &vertices[0] as *const f32 as *const c_void,
                     gl::STATIC_DRAW);
+
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, EBO);
+        gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+                    (indices.len() * mem::size_of::<u32>()) as GLsizeiptr,
+                    &indices[0] as *const u32 as *const c_void,
+                    gl::STATIC_DRAW);
         
         let stride = 6 * mem::size_of::<GLfloat>() as GLsizei;
         // position      attribute
@@ -154,11 +125,12 @@ pub fn main() {
         gl::BindVertexArray(lightVAO);
         
         gl::BindBuffer(gl::ARRAY_BUFFER, VBO);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, EBO);
 
         gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
         gl::EnableVertexAttribArray(0);
         
-        (lampShader, lightShader, VBO, cubeVAO, lightVAO)
+        (lampShader, lightShader, VBO, EBO, indices.len() as i32, cubeVAO, lightVAO)
     };
 
     while !window.should_close() {
@@ -194,7 +166,7 @@ pub fn main() {
 
             // render the cube
             gl::BindVertexArray(cubeVAO);
-            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            gl::DrawElements(gl::TRIANGLES, indexCount, gl::UNSIGNED_INT, ptr::null());
 
             // also draw the lamp object
             lampShader.useProgram();
@@ -206,7 +178,7 @@ pub fn main() {
             
 
             gl::BindVertexArray(lightVAO);
-            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            gl::DrawElements(gl::TRIANGLES, indexCount, gl::UNSIGNED_INT, ptr::null());
         }
 
         // glfw: swap buffers and poll IO events (keys pressed/released, mouse moved etc.)
@@ -220,6 +192,7 @@ pub fn main() {
         gl::DeleteVertexArrays(1, &cubeVAO);
         gl::DeleteVertexArrays(1, &lightVAO);
         gl::DeleteBuffers(1, &VBO);
+        gl::DeleteBuffers(1, &EBO);
     }
 }
 
@@ -31,6 +31,8 @@ use cgmath::prelude::*;
 
 use rand::{thread_rng, Rng};
 
+use engine::model::primitives;
+
 // settings
 const SCR_WIDTH: u32 = 800;
 const SCR_HEIGHT: u32 = 600;
@@ -60,7 +62,7 @@ pub fn main() {
     // ---------------------------------------
     gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
 
-    let (ourShader, VBO, VAO, texture) = unsafe {
+    let (ourShader, VBO, VAO, EBO, instanceVBO, indexCount, texture) = unsafe {
         
         gl::Enable(gl::DEPTH_TEST);
         
@@ -71,62 +73,31 @@ pub fn main() {
 
         // set up vertex data (and buffer(s)) and configure vertex attributes
         // ------------------------------------------------------------------
-        // HINT: type annotation is crucial since default for float literals is f64
-        let vertices: [f32; 180] = [
-           -0.5, -0.5, -0.5,  0.0, 0.0,
-            0.5, -0.5, -0.5,  1.0, 0.0,
-            0.5,  0.5, -0.5,  1.0, 1.0,
-            0.5,  0.5, -0.5,  1.0, 1.0,
-           -0.5,  0.5, -0.5,  0.0, 1.0,
-           -0.5, -0.5, -0.5,  0.0, 0.0,
-       
-           -0.5, -0.5,  0.5,  0.0, 0.0,
-            0.5, -0.5,  0.5,  1.0, 0.0,
-            0.5,  0.5,  0.5,  1.0, 1.0,
-            0.5,  0.5,  0.5,  1.0, 1.0,
-           -0.5,  0.5,  0.5,  0.0, 1.0,
-           -0.5, -0.5,  0.5,  0.0, 0.0,
-       
-           -0.5,  0.5,  0.5,  1.0, 0.0,
-           -0.5,  0.5, -0.5,  1.0, 1.0,
-           -0.5, -0.5, -0.5,  0.0, 1.0,
-           -0.5, -0.5, -0.5,  0.0, 1.0,
-           -0.5, -0.5,  0.5,  0.0, 0.0,
-           -0.5,  0.5,  0.5,  1.0, 0.0,
-       
-            0.5,  0.5,  0.5,  1.0, 0.0,
-            0.5,  0.5, -0.5,  1.0, 1.0,
-            0.5, -0.5, -0.5,  0.0, 1.0,
-            0.5, -0.5, -0.5,  0.0, 1.0,
-            0.5, -0.5,  0.5,  0.0, 0.0,
-            0.5,  0.5,  0.5,  1.0, 0.0,
-       
-           -0.5, -0.5, -0.5,  0.0, 1.0,
-            0.5, -0.5, -0.5,  1.0, 1.0,
-            0.5, -0.5,  0.5,  1.0, 0.0,
-            0.5, -0.5,  0.5,  1.0, 0.0,
-           -0.5, -0.5,  0.5,  0.0, 0.0,
-           -0.5, -0.5, -0.5,  0.0, 1.0,
-       
-           -0.5,  0.5, -0.5,  0.0, 1.0,
-            0.5,  0.5, -0.5,  1.0, 1.0,
-            0.5,  0.5,  0.5,  1.0, 0.0,
-            0.5,  0.5,  0.5,  1.0, 0.0,
-           -0.5,  0.5,  0.5,  0.0, 0.0,
-           -0.5,  0.5, -0.5,  0.0, 1.0
-        ];
-        let mut smallVertices: Vec<f32> = vertices.iter().map(|v| 0.90 * v).collect();
-
-        let (mut VBO, mut VAO) = (0, 0);
+        // a slightly smaller cube than unit size, so neighbouring cubes don't touch.
+        // positions and texture coordinates interleaved, 5 floats per vertex
+        let cube = primitives::cube(0.9, 1);
+        let vertices: Vec<f32> = cube.vertices.iter()
+            .flat_map(|v| vec![v.position.x, v.position.y, v.position.z, v.tex_coords.x, v.tex_coords.y])
+            .collect();
+        let indices = cube.indices;
+
+        let (mut VBO, mut VAO, mut EBO) = (0, 0, 0);
         gl::GenVertexArrays(1, &mut VAO);
         gl::GenBuffers(1, &mut VBO);
+        gl::GenBuffers(1, &mut EBO);
         
         gl::BindVertexArray(VAO);
 
         gl::BindBuffer(gl::ARRAY_BUFFER, VBO);
         gl::BufferData(gl::ARRAY_BUFFER,
-                       (smallVertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-                       &smallVertices[0] as *const f32 as *const c_void,
+                       (vertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                       &vertices[0] as *const f32 as *const c_void,
+                       gl::STATIC_DRAW);
+
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, EBO);
+        gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+                       (indices.len() * mem::size_of::<u32>()) as GLsizeiptr,
+                       &indices[0] as *const u32 as *const c_void,
                        gl::STATIC_DRAW);
         
         let stride = 5 * mem::size_of::<GLfloat>() as GLsizei;
@@ -136,6 +107,16 @@ pub fn main() {
         // color attribute
         gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (3 * mem::size_of::<GLfloat>()) as *const c_void);
         gl::EnableVertexAttribArray(1);
+        // instance model matrix attribute, one vec4 per column, advancing once per cube
+        let mut instanceVBO = 0;
+        gl::GenBuffers(1, &mut instanceVBO);
+        gl::BindBuffer(gl::ARRAY_BUFFER, instanceVBO);
+        let matrixStride = mem::size_of::<Matrix4<f32>>() as GLsizei;
+        for column in 0..4 {
+            gl::VertexAttribPointer(2 + column, 4, gl::FLOAT, gl::FALSE, matrixStride, (column as usize * 4 * mem::size_of::<GLfloat>()) as *const c_void);
+            gl::EnableVertexAttribArray(2 + column);
+            gl::VertexAttribDivisor(2 + column, 1);
+        }
         // load and create a texture
         // -------------------------
         let mut texture = 0;
@@ -165,7 +146,7 @@ pub fn main() {
         ourShader.useProgram();
         ourShader.setInt(c_str!("texture1"), 0);
 
-        (ourShader, VBO, VAO, texture)
+        (ourShader, VBO, VAO, EBO, instanceVBO, indices.len() as i32, texture)
     };
     
     let randomPositions = gen_random_positions(1000, -20.0, 30.0);
@@ -234,16 +215,20 @@ pub fn main() {
             let view = camera.GetViewMatrix();
             ourShader.setMat4(c_str!("view"), &view);
 
-            // render cubes
-            gl::BindVertexArray(VAO);
-            for (i, position) in randomPositions.iter().enumerate() {
-                let mut model: Matrix4<f32> = Matrix4::from_translation(*position);
+            // render cubes, all of them in one instanced draw
+            let models: Vec<Matrix4<f32>> = randomPositions.iter().enumerate().map(|(i, position)| {
                 let angle = 30.0 * i as f32;
-                model = model * Matrix4::from_axis_angle(vec3(1.0, 0.3, 0.5).normalize(), Deg(angle + 20.0 * glfw.get_time() as f32));
+                Matrix4::from_translation(*position)
+                    * Matrix4::from_axis_angle(vec3(1.0, 0.3, 0.5).normalize(), Deg(angle + 20.0 * glfw.get_time() as f32))
+            }).collect();
+            gl::BindBuffer(gl::ARRAY_BUFFER, instanceVBO);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (models.len() * mem::size_of::<Matrix4<f32>>()) as GLsizeiptr,
+                           models.as_ptr() as *const c_void,
+                           gl::STREAM_DRAW);
 
-                ourShader.setMat4(c_str!("model"), &model);
-                gl::DrawArrays(gl::TRIANGLES, 0, 36);
-            }
+            gl::BindVertexArray(VAO);
+            gl::DrawElementsInstanced(gl::TRIANGLES, indexCount, gl::UNSIGNED_INT, ptr::null(), models.len() as i32);
         }
 
         // glfw: swap buffers and poll IO events (keys pressed/released, mouse moved etc.)
@@ -256,6 +241,8 @@ pub fn main() {
     unsafe {
         gl::DeleteVertexArrays(1, &VAO);
         gl::DeleteBuffers(1, &VBO);
+        gl::DeleteBuffers(1, &EBO);
+        gl::DeleteBuffers(1, &instanceVBO);
     }
 }
 